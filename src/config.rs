@@ -1,54 +1,2234 @@
-//! Global configuration from environment variables
+//! Global configuration from environment variables and an optional
+//! configuration file
 
-use anyhow::Result;
-use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{docker, healthchecks::MIN_PING_BODY_LIMIT};
+
+/// Prefix of the environment variables
+const ENV_PREFIX: &str = "DHC";
+
+/// Standard docker environment variables which are also read without prefix
+const DOCKER_VARIABLES: [&str; 4] = [
+    "DOCKER_HOST",
+    "DOCKER_CONTEXT",
+    "DOCKER_CERT_PATH",
+    "DOCKER_TLS_VERIFY",
+];
+
+/// Path of the configuration file that is loaded if it exists and no other
+/// file is configured
+const DEFAULT_CONFIG_FILE: &str = "/etc/docker-healthchecks.toml";
+
+/// Environment variables the configuration is loaded from
+type Env = config::Map<String, String>;
+
+/// Minimum duration between healthcheck pings
+const MIN_PING_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Values from environment variables
-#[derive(Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
-    /// Path of the docker daemon socket
+    /// Whether the containers are monitored continuously or only checked once
+    pub mode: RunMode,
+
+    /// Log level (overridden by `RUST_LOG` if set)
+    pub log_level: LogLevel,
+
+    /// Log level of the docker api client (same as `log_level` if not set)
+    pub log_level_docker_api: Option<LogLevel>,
+
+    /// Connection string of the docker daemon (`unix:///path`,
+    /// `tcp://host:port`, `http://host:port`, `https://host:port` or
+    /// `ssh://user@host`). The sockets in `docker_paths` are probed if not
+    /// set.
+    pub docker_host: Option<String>,
+
+    /// Name of a docker cli context from which the docker host and its
+    /// certificates are taken if `docker_host` is not set
+    pub docker_context: Option<String>,
+
+    /// Comma-separated list of docker hosts that are monitored, each
+    /// optionally prefixed with a name (`name=url`). Takes precedence over
+    /// `docker_host`.
+    pub docker_hosts: Option<String>,
+
+    /// Path of the directory containing the client certificates (`ca.pem`,
+    /// `cert.pem` and `key.pem`) for `https` docker hosts
+    pub docker_cert_path: Option<String>,
+
+    /// Whether the certificate of `https` docker hosts is verified using the
+    /// ca certificate
+    pub docker_tls_verify: bool,
+
+    /// Path of the ca certificate for `https` docker hosts (`ca.pem` in
+    /// `docker_cert_path` if not set)
+    pub docker_ca: Option<String>,
+
+    /// Path of the client certificate for `https` docker hosts (`cert.pem` in
+    /// `docker_cert_path` if not set)
+    pub docker_cert: Option<String>,
+
+    /// Path of the client key for `https` docker hosts (`key.pem` in
+    /// `docker_cert_path` if not set)
+    pub docker_key: Option<String>,
+
+    /// Path of the private key used to connect to `ssh` docker hosts (the
+    /// default keys of ssh are used if not set)
+    pub docker_ssh_identity: Option<String>,
+
+    /// Path of the docker daemon socket (deprecated, use `docker_host`)
     pub docker_path: String,
 
-    /// Number of seconds between healthcheck pings
-    pub ping_interval: u64,
+    /// Comma-separated list of docker socket paths that are probed in order
+    /// if `docker_host` is not set. Defaults to `docker_path` followed by the
+    /// sockets of rootless docker and podman.
+    pub docker_paths: Option<String>,
+
+    /// Duration for which the docker daemon is retried at startup (`0` to
+    /// wait forever). Fail on the first error if not set.
+    #[serde(
+        default,
+        serialize_with = "duration::serialize_option",
+        deserialize_with = "duration::deserialize_option"
+    )]
+    pub startup_timeout: Option<Duration>,
+
+    /// Duration between healthcheck pings
+    #[serde(with = "duration")]
+    pub ping_interval: Duration,
+
+    /// Duration across which the periodic healthcheck pings are spread (`0`
+    /// to send all pings at once)
+    #[serde(with = "duration")]
+    pub ping_spread: Duration,
+
+    /// Which healthcheck pings should be sent
+    pub ping_mode: PingMode,
+
+    /// Suffix appended to the ping url for healthy pings. Suffixes starting
+    /// with `?` or `&` are added to the query of the url, other suffixes are
+    /// appended to its path.
+    pub ping_suffix_healthy: String,
+
+    /// Suffix appended to the ping url for unhealthy pings
+    pub ping_suffix_unhealthy: String,
+
+    /// Suffix appended to the ping url for starting pings
+    pub ping_suffix_starting: String,
+
+    /// Whether pings are only logged instead of being sent
+    pub dry_run: bool,
+
+    /// Duration between keep-alive pings of all healthcheck urls in `both`
+    /// ping mode
+    #[serde(with = "duration")]
+    pub keepalive_interval: Duration,
+
+    /// Whether the healthcheck urls should be pinged right after the initial
+    /// container fetch instead of only after the first ping interval
+    pub ping_on_startup: bool,
+
+    /// Whether the periodic pings are sent at multiples of the ping interval
+    /// since the unix epoch instead of relative to the startup
+    pub align_pings: bool,
 
     /// Number of retries for failed healthcheck pings
     pub ping_retries: u8,
 
-    /// Number of seconds after which the ping timeout expires
-    pub ping_timeout: u64,
+    /// Maximum size of ping bodies in bytes, longer bodies are truncated
+    pub ping_body_limit: usize,
+
+    /// Comma separated list of static resolutions of the hosts of the ping
+    /// urls in the form `host=ip[:port]`, which bypass dns
+    pub ping_resolve: Option<String>,
+
+    /// Whether the last successful dns resolution of the hosts of the ping
+    /// urls is used if a lookup fails
+    pub ping_dns_cache: bool,
+
+    /// Which ip version is used to connect to the hosts of the ping urls
+    pub ping_ip_version: IpVersion,
+
+    /// Duration after which idle connections to the hosts of the ping urls
+    /// are closed. Should exceed `ping_interval`, otherwise the connections
+    /// are closed right before the next periodic pings and have to be
+    /// reestablished.
+    #[serde(with = "duration")]
+    pub ping_pool_idle_timeout: Duration,
+
+    /// Maximum number of idle connections kept per host of the ping urls
+    /// (`0` to disable connection reuse)
+    pub ping_pool_max_idle_per_host: usize,
+
+    /// Interval of tcp keep-alive probes on connections to the hosts of the
+    /// ping urls, which keeps idle connections from being dropped by
+    /// firewalls and nat gateways between the periodic pings (`0` to
+    /// disable)
+    #[serde(with = "duration")]
+    pub ping_tcp_keepalive: Duration,
+
+    /// Duration after which the ping timeout expires
+    #[serde(with = "duration")]
+    pub ping_timeout: Duration,
+
+    /// Duration between reloading the full container list from the docker
+    /// daemon (`0` to disable the periodic fetch)
+    #[serde(with = "duration")]
+    pub fetch_interval: Duration,
+
+    /// Duration after which the container fetch timeout expires
+    #[serde(with = "duration")]
+    pub fetch_timeout: Duration,
+
+    /// Maximum random delay before the initial container fetch and pings
+    #[serde(with = "duration")]
+    pub startup_jitter: Duration,
+
+    /// Maximum percentage by which each ping and fetch interval is randomly
+    /// lengthened or shortened
+    pub interval_jitter: u8,
+
+    /// Maximum number of containers that are inspected concurrently while
+    /// reloading the full container list
+    pub fetch_concurrency: usize,
+
+    /// Maximum number of exec probes that run concurrently on a docker host
+    pub exec_probe_concurrency: usize,
+
+    /// Interval in which the docker stats of containers with resource
+    /// thresholds are sampled
+    #[serde(with = "duration")]
+    pub stats_interval: Duration,
+
+    /// Number of retries for failed docker container inspect requests
+    pub inspect_retries: u8,
+
+    /// Duration to wait before the first retry of a failed docker container
+    /// inspect request (doubled for each further retry)
+    #[serde(with = "duration")]
+    pub inspect_retry_delay: Duration,
+
+    /// Duration after which the timeout for handling a docker event expires
+    #[serde(with = "duration")]
+    pub event_timeout: Duration,
+
+    /// Maximum number of docker events that are handled concurrently
+    pub event_concurrency: usize,
+
+    /// Maximum number of docker events to buffer until the initial container
+    /// fetch has completed
+    pub event_buffer_size: usize,
+
+    /// Behavior if the event buffer is full
+    pub event_buffer_overflow: BufferOverflow,
+
+    /// Number of consecutive unhealthy observations required before a
+    /// container is reported as unhealthy (can be overridden per container
+    /// using the `healthchecks.flap-threshold` label)
+    pub flap_threshold: u32,
+
+    /// Number of consecutive failed healthchecks required before a container
+    /// is reported as unhealthy (can be overridden per container using the
+    /// `healthchecks.min-failing-streak` label)
+    pub min_failing_streak: usize,
+
+    /// Duration to wait before sending an unhealthy ping after the last
+    /// container with a ping url died. The ping is cancelled if a
+    /// container with the same ping url is started in the meantime.
+    #[serde(with = "duration")]
+    pub die_grace: Duration,
+
+    /// Which health status is considered the 'worst' when aggregating the
+    /// health statuses of multiple containers with the same ping url
+    pub severity_order: SeverityOrder,
+
+    /// Whether containers that are restarting (e.g. in a crash loop) are
+    /// reported as unhealthy
+    pub restarting_unhealthy: bool,
+
+    /// Maximum number of restarts of a container within one hour before it is
+    /// reported as unhealthy (unlimited if not set)
+    pub max_restarts_per_hour: Option<usize>,
+
+    /// Duration after which a container that is continuously starting is
+    /// reported as healthy (disabled if not set)
+    #[serde(
+        default,
+        serialize_with = "duration::serialize_option",
+        deserialize_with = "duration::deserialize_option"
+    )]
+    pub starting_as_healthy_after: Option<Duration>,
+
+    /// Whether tasks of swarm services should be aggregated per service. In
+    /// swarm mode, the ping url can also be set using a service label.
+    pub swarm_mode: bool,
+
+    /// Whether the health of swarm services is computed across the cluster
+    /// by the reachable manager with the lowest node id, instead of pinging
+    /// the tasks running on each node
+    pub swarm_aggregation: bool,
+
+    /// Whether stopped containers should be considered at startup. An
+    /// unhealthy ping is sent to the ping urls of exited containers without a
+    /// running counterpart.
+    pub include_stopped_on_startup: bool,
+
+    /// Path of a file in which the state of the healthchecks checks is
+    /// persisted across restarts (disabled if not set)
+    pub state_file: Option<String>,
+
+    /// Duration after which a persisted state is considered stale and
+    /// ignored
+    #[serde(with = "duration")]
+    pub state_max_age: Duration,
+
+    /// Maximum number of health transitions that are stored per container
+    pub health_history_size: usize,
+
+    /// Duration between summaries of the monitored containers in the log (`0`
+    /// to disable)
+    #[serde(with = "duration")]
+    pub summary_interval: Duration,
+
+    /// Path of a file to which the internal state is written on SIGUSR1
+    /// (logged if not set)
+    pub dump_file: Option<String>,
+
+    /// Whether ping urls are redacted in dumps of the internal state
+    pub dump_redact_urls: bool,
+
+    /// Address on which the http status endpoint is served (e.g.
+    /// `0.0.0.0:8080`, disabled if not set)
+    pub status_addr: Option<String>,
+
+    /// Bearer token required to access the http status endpoint
+    #[serde(serialize_with = "redact::secret")]
+    pub status_token: Option<String>,
+
+    /// Bearer token required to access the http admin endpoints (disabled if
+    /// not set)
+    #[serde(serialize_with = "redact::secret")]
+    pub admin_token: Option<String>,
+
+    /// Healthchecks url that is pinged after every completed round of pings
+    /// to monitor docker-healthchecks itself (disabled if not set)
+    #[serde(serialize_with = "redact::url")]
+    pub self_ping_url: Option<String>,
+
+    /// Healthchecks url that is pinged after every round of pings to monitor
+    /// the docker daemons (disabled if not set)
+    #[serde(serialize_with = "redact::url")]
+    pub docker_check_url: Option<String>,
+
+    /// Url to which a json summary of the health statuses of all monitored
+    /// containers is posted after every round of pings (disabled if not set)
+    #[serde(serialize_with = "redact::url")]
+    pub summary_url: Option<String>,
+
+    /// Whether only the summary is sent instead of pinging the ping urls of
+    /// the containers
+    pub summary_only: bool,
+
+    /// Duration after which an unreachable docker daemon is reported as a
+    /// failure to the self ping url and in the summary
+    #[serde(with = "duration")]
+    pub docker_down_threshold: Duration,
+
+    /// What happens to the pings of the containers while the docker daemon
+    /// is unreachable
+    pub on_docker_lost: DockerLostAction,
+}
+
+/// Configuration values from command line flags, which take precedence over
+/// all other sources
+#[derive(Default, clap::Args, Serialize)]
+pub struct Overrides {
+    /// Path of a TOML or YAML configuration file
+    #[arg(long)]
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+
+    /// Whether the containers are monitored continuously or only checked once
+    #[arg(long)]
+    pub mode: Option<RunMode>,
+
+    /// Log level (overridden by `RUST_LOG` if set)
+    #[arg(long)]
+    pub log_level: Option<LogLevel>,
+
+    /// Log level of the docker api client (same as `log_level` if not set)
+    #[arg(long)]
+    pub log_level_docker_api: Option<LogLevel>,
+
+    /// Connection string of the docker daemon (`unix:///path`,
+    /// `tcp://host:port`, `http://host:port`, `https://host:port` or
+    /// `ssh://user@host`). The sockets in `docker_paths` are probed if not
+    /// set.
+    #[arg(long)]
+    pub docker_host: Option<String>,
+
+    /// Name of a docker cli context from which the docker host and its
+    /// certificates are taken if `docker_host` is not set
+    #[arg(long)]
+    pub docker_context: Option<String>,
+
+    /// Comma-separated list of docker hosts that are monitored, each
+    /// optionally prefixed with a name (`name=url`). Takes precedence over
+    /// `docker_host`.
+    #[arg(long)]
+    pub docker_hosts: Option<String>,
+
+    /// Path of the directory containing the client certificates (`ca.pem`,
+    /// `cert.pem` and `key.pem`) for `https` docker hosts
+    #[arg(long)]
+    pub docker_cert_path: Option<String>,
+
+    /// Whether the certificate of `https` docker hosts is verified using the
+    /// ca certificate
+    #[arg(long)]
+    pub docker_tls_verify: Option<bool>,
+
+    /// Path of the ca certificate for `https` docker hosts (`ca.pem` in
+    /// `docker_cert_path` if not set)
+    #[arg(long)]
+    pub docker_ca: Option<String>,
+
+    /// Path of the client certificate for `https` docker hosts (`cert.pem` in
+    /// `docker_cert_path` if not set)
+    #[arg(long)]
+    pub docker_cert: Option<String>,
+
+    /// Path of the client key for `https` docker hosts (`key.pem` in
+    /// `docker_cert_path` if not set)
+    #[arg(long)]
+    pub docker_key: Option<String>,
+
+    /// Path of the private key used to connect to `ssh` docker hosts (the
+    /// default keys of ssh are used if not set)
+    #[arg(long)]
+    pub docker_ssh_identity: Option<String>,
+
+    /// Path of the docker daemon socket (deprecated, use `docker_host`)
+    #[arg(long)]
+    pub docker_path: Option<String>,
+
+    /// Comma-separated list of docker socket paths that are probed in order
+    /// if `docker_host` is not set. Defaults to `docker_path` followed by the
+    /// sockets of rootless docker and podman.
+    #[arg(long)]
+    pub docker_paths: Option<String>,
+
+    /// Duration for which the docker daemon is retried at startup (`0` to
+    /// wait forever). Fail on the first error if not set.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub startup_timeout: Option<Duration>,
+
+    /// Duration between healthcheck pings
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub ping_interval: Option<Duration>,
+
+    /// Duration across which the periodic healthcheck pings are spread (`0`
+    /// to send all pings at once)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub ping_spread: Option<Duration>,
+
+    /// Which healthcheck pings should be sent
+    #[arg(long)]
+    pub ping_mode: Option<PingMode>,
+
+    /// Suffix appended to the ping url for healthy pings. Suffixes starting
+    /// with `?` or `&` are added to the query of the url, other suffixes are
+    /// appended to its path.
+    #[arg(long)]
+    pub ping_suffix_healthy: Option<String>,
+
+    /// Suffix appended to the ping url for unhealthy pings
+    #[arg(long)]
+    pub ping_suffix_unhealthy: Option<String>,
+
+    /// Suffix appended to the ping url for starting pings
+    #[arg(long)]
+    pub ping_suffix_starting: Option<String>,
+
+    /// Whether pings are only logged instead of being sent
+    #[arg(long)]
+    pub dry_run: Option<bool>,
+
+    /// Duration between keep-alive pings of all healthcheck urls in `both`
+    /// ping mode
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub keepalive_interval: Option<Duration>,
+
+    /// Whether the healthcheck urls should be pinged right after the initial
+    /// container fetch instead of only after the first ping interval
+    #[arg(long)]
+    pub ping_on_startup: Option<bool>,
+
+    /// Whether the periodic pings are sent at multiples of the ping interval
+    /// since the unix epoch instead of relative to the startup
+    #[arg(long)]
+    pub align_pings: Option<bool>,
+
+    /// Number of retries for failed healthcheck pings
+    #[arg(long)]
+    pub ping_retries: Option<u8>,
+
+    /// Maximum size of ping bodies in bytes, longer bodies are truncated
+    #[arg(long)]
+    pub ping_body_limit: Option<usize>,
+
+    /// Comma separated list of static resolutions of the hosts of the ping
+    /// urls in the form `host=ip[:port]`, which bypass dns
+    #[arg(long)]
+    pub ping_resolve: Option<String>,
+
+    /// Whether the last successful dns resolution of the hosts of the ping
+    /// urls is used if a lookup fails
+    #[arg(long)]
+    pub ping_dns_cache: Option<bool>,
+
+    /// Which ip version is used to connect to the hosts of the ping urls
+    #[arg(long)]
+    pub ping_ip_version: Option<IpVersion>,
+
+    /// Duration after which idle connections to the hosts of the ping urls
+    /// are closed. Should exceed `ping_interval`, otherwise the connections
+    /// are closed right before the next periodic pings and have to be
+    /// reestablished.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub ping_pool_idle_timeout: Option<Duration>,
+
+    /// Maximum number of idle connections kept per host of the ping urls
+    /// (`0` to disable connection reuse)
+    #[arg(long)]
+    pub ping_pool_max_idle_per_host: Option<usize>,
+
+    /// Interval of tcp keep-alive probes on connections to the hosts of the
+    /// ping urls, which keeps idle connections from being dropped by
+    /// firewalls and nat gateways between the periodic pings (`0` to
+    /// disable)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub ping_tcp_keepalive: Option<Duration>,
+
+    /// Duration after which the ping timeout expires
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub ping_timeout: Option<Duration>,
+
+    /// Duration between reloading the full container list from the docker
+    /// daemon (`0` to disable the periodic fetch)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub fetch_interval: Option<Duration>,
+
+    /// Duration after which the container fetch timeout expires
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub fetch_timeout: Option<Duration>,
+
+    /// Maximum random delay before the initial container fetch and pings
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub startup_jitter: Option<Duration>,
+
+    /// Maximum percentage by which each ping and fetch interval is randomly
+    /// lengthened or shortened
+    #[arg(long)]
+    pub interval_jitter: Option<u8>,
+
+    /// Maximum number of containers that are inspected concurrently while
+    /// reloading the full container list
+    #[arg(long)]
+    pub fetch_concurrency: Option<usize>,
+
+    /// Maximum number of exec probes that run concurrently on a docker host
+    #[arg(long)]
+    pub exec_probe_concurrency: Option<usize>,
+
+    /// Interval in which the docker stats of containers with resource
+    /// thresholds are sampled
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub stats_interval: Option<Duration>,
+
+    /// Number of retries for failed docker container inspect requests
+    #[arg(long)]
+    pub inspect_retries: Option<u8>,
+
+    /// Duration to wait before the first retry of a failed docker container
+    /// inspect request (doubled for each further retry)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub inspect_retry_delay: Option<Duration>,
+
+    /// Duration after which the timeout for handling a docker event expires
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub event_timeout: Option<Duration>,
+
+    /// Maximum number of docker events that are handled concurrently
+    #[arg(long)]
+    pub event_concurrency: Option<usize>,
+
+    /// Maximum number of docker events to buffer until the initial container
+    /// fetch has completed
+    #[arg(long)]
+    pub event_buffer_size: Option<usize>,
+
+    /// Behavior if the event buffer is full
+    #[arg(long)]
+    pub event_buffer_overflow: Option<BufferOverflow>,
+
+    /// Number of consecutive unhealthy observations required before a
+    /// container is reported as unhealthy (can be overridden per container
+    /// using the `healthchecks.flap-threshold` label)
+    #[arg(long)]
+    pub flap_threshold: Option<u32>,
+
+    /// Number of consecutive failed healthchecks required before a container
+    /// is reported as unhealthy (can be overridden per container using the
+    /// `healthchecks.min-failing-streak` label)
+    #[arg(long)]
+    pub min_failing_streak: Option<usize>,
+
+    /// Duration to wait before sending an unhealthy ping after the last
+    /// container with a ping url died. The ping is cancelled if a
+    /// container with the same ping url is started in the meantime.
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub die_grace: Option<Duration>,
+
+    /// Which health status is considered the 'worst' when aggregating the
+    /// health statuses of multiple containers with the same ping url
+    #[arg(long)]
+    pub severity_order: Option<SeverityOrder>,
+
+    /// Whether containers that are restarting (e.g. in a crash loop) are
+    /// reported as unhealthy
+    #[arg(long)]
+    pub restarting_unhealthy: Option<bool>,
+
+    /// Maximum number of restarts of a container within one hour before it is
+    /// reported as unhealthy (unlimited if not set)
+    #[arg(long)]
+    pub max_restarts_per_hour: Option<usize>,
+
+    /// Duration after which a container that is continuously starting is
+    /// reported as healthy (disabled if not set)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub starting_as_healthy_after: Option<Duration>,
+
+    /// Whether tasks of swarm services should be aggregated per service. In
+    /// swarm mode, the ping url can also be set using a service label.
+    #[arg(long)]
+    pub swarm_mode: Option<bool>,
+
+    /// Whether the health of swarm services is computed across the cluster
+    /// by the reachable manager with the lowest node id, instead of pinging
+    /// the tasks running on each node
+    #[arg(long)]
+    pub swarm_aggregation: Option<bool>,
+
+    /// Whether stopped containers should be considered at startup. An
+    /// unhealthy ping is sent to the ping urls of exited containers without a
+    /// running counterpart.
+    #[arg(long)]
+    pub include_stopped_on_startup: Option<bool>,
+
+    /// Path of a file in which the state of the healthchecks checks is
+    /// persisted across restarts (disabled if not set)
+    #[arg(long)]
+    pub state_file: Option<String>,
+
+    /// Duration after which a persisted state is considered stale and
+    /// ignored
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub state_max_age: Option<Duration>,
+
+    /// Maximum number of health transitions that are stored per container
+    #[arg(long)]
+    pub health_history_size: Option<usize>,
+
+    /// Duration between summaries of the monitored containers in the log (`0`
+    /// to disable)
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub summary_interval: Option<Duration>,
+
+    /// Path of a file to which the internal state is written on SIGUSR1
+    /// (logged if not set)
+    #[arg(long)]
+    pub dump_file: Option<String>,
+
+    /// Whether ping urls are redacted in dumps of the internal state
+    #[arg(long)]
+    pub dump_redact_urls: Option<bool>,
+
+    /// Address on which the http status endpoint is served (e.g.
+    /// `0.0.0.0:8080`, disabled if not set)
+    #[arg(long)]
+    pub status_addr: Option<String>,
+
+    /// Bearer token required to access the http status endpoint
+    #[arg(long)]
+    pub status_token: Option<String>,
+
+    /// Bearer token required to access the http admin endpoints (disabled if
+    /// not set)
+    #[arg(long)]
+    pub admin_token: Option<String>,
+
+    /// Healthchecks url that is pinged after every completed round of pings
+    /// to monitor docker-healthchecks itself (disabled if not set)
+    #[arg(long)]
+    pub self_ping_url: Option<String>,
+
+    /// Healthchecks url that is pinged after every round of pings to monitor
+    /// the docker daemons (disabled if not set)
+    #[arg(long)]
+    pub docker_check_url: Option<String>,
+
+    /// Url to which a json summary of the health statuses of all monitored
+    /// containers is posted after every round of pings (disabled if not set)
+    #[arg(long)]
+    pub summary_url: Option<String>,
+
+    /// Whether only the summary is sent instead of pinging the ping urls of
+    /// the containers
+    #[arg(long)]
+    pub summary_only: Option<bool>,
+
+    /// Duration after which an unreachable docker daemon is reported as a
+    /// failure to the self ping url and in the summary
+    #[arg(long, value_name = "DURATION", value_parser = duration::parse)]
+    #[serde(serialize_with = "duration::serialize_option")]
+    pub docker_down_threshold: Option<Duration>,
+
+    /// What happens to the pings of the containers while the docker daemon
+    /// is unreachable
+    #[arg(long)]
+    pub on_docker_lost: Option<DockerLostAction>,
+}
+
+/// Whether the containers are monitored continuously or only checked once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunMode {
+    /// Handle docker events and ping the healthcheck urls periodically
+    Daemon,
+
+    /// Fetch the containers, ping all healthcheck urls once and exit
+    Oneshot,
+}
+
+/// Connection to the docker daemon
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DockerHost {
+    /// Path of a unix socket
+    Unix(String),
+
+    /// Unencrypted tcp connection to `host:port`
+    Tcp(String),
+
+    /// Tls connection to `host:port`
+    Tls(String),
+
+    /// Tunnel to the docker daemon of a remote host via ssh (`ssh://` url)
+    Ssh(String),
+
+    /// Paths of unix sockets that are probed in order, the first one whose
+    /// daemon responds is used
+    Probe(Vec<String>),
+}
+
+impl DockerHost {
+    /// Parse a connection string of a docker daemon
+    fn parse(host: &str) -> Result<Self> {
+        let Some((scheme, address)) = host.split_once("://") else {
+            bail!("docker host `{host}` has no scheme (expected e.g. unix:///var/run/docker.sock)");
+        };
+        ensure!(!address.is_empty(), "docker host `{host}` has no address");
+        match scheme {
+            "unix" => Ok(Self::Unix(address.to_owned())),
+            "tcp" | "http" | "https" | "ssh" => {
+                ensure!(
+                    !address.contains('/'),
+                    "docker host `{host}` must not contain a path"
+                );
+                Ok(match scheme {
+                    "https" => Self::Tls(address.to_owned()),
+                    "ssh" => Self::Ssh(host.to_owned()),
+                    _ => Self::Tcp(address.to_owned()),
+                })
+            }
+            _ => bail!(
+                "docker host `{host}` has an unsupported scheme `{scheme}` (expected unix, tcp, \
+                 http, https or ssh)"
+            ),
+        }
+    }
+
+    /// Return the name of the docker host that is used if no name is
+    /// configured
+    fn default_name(&self) -> String {
+        match self {
+            Self::Unix(_) | Self::Probe(_) => "local".to_owned(),
+            Self::Tcp(address) | Self::Tls(address) => address.clone(),
+            Self::Ssh(url) => url.trim_start_matches("ssh://").to_owned(),
+        }
+    }
+}
+
+/// Certificates for tls connections to the docker daemon
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    /// Path of the ca certificate
+    pub ca: Option<PathBuf>,
+
+    /// Path of the client certificate
+    pub cert: PathBuf,
+
+    /// Path of the client key
+    pub key: PathBuf,
+}
+
+/// Verbosity of the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogLevel {
+    /// Only log errors
+    Error,
+
+    /// Log errors and warnings
+    Warn,
+
+    /// Log informational messages, warnings and errors
+    Info,
+
+    /// Also log debug messages
+    Debug,
+
+    /// Log everything
+    Trace,
+}
+
+impl LogLevel {
+    /// Return the name of the log level as used in log filter directives
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+/// Which healthcheck pings should be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum PingMode {
+    /// Ping all healthcheck urls periodically and on every health status
+    /// update
+    Interval,
+
+    /// Only ping a healthcheck url if its health status changed
+    Transitions,
+
+    /// Ping on changes of the health status and send slow periodic keep-alive
+    /// pings
+    Both,
+}
+
+/// What happens to the pings of the containers while the docker daemon is
+/// unreachable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DockerLostAction {
+    /// Keep pinging the last known health status of the containers
+    Keep,
+
+    /// Suspend the periodic pings, so that the checks become late
+    Stop,
+
+    /// Send one unhealthy ping to all ping urls once the docker daemon has
+    /// been unreachable for longer than the threshold, and suspend the pings
+    /// until the containers have been fetched again
+    Fail,
+}
+
+/// Which ip version is used for outgoing connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpVersion {
+    /// Use all addresses of a host
+    Auto,
+
+    /// Only use the ipv4 addresses of a host
+    V4,
+
+    /// Only use the ipv6 addresses of a host
+    V6,
+}
+
+impl IpVersion {
+    /// Return whether the given address may be used
+    #[must_use]
+    pub fn allows(self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Auto => true,
+            Self::V4 => addr.is_ipv4(),
+            Self::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Which health status is considered the 'worst' when aggregating the health
+/// statuses of multiple containers with the same ping url
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeverityOrder {
+    /// Healthy < Starting < Unhealthy
+    UnhealthyWorst,
+
+    /// Healthy < Unhealthy < Starting
+    StartingWorst,
+}
+
+/// Behavior if the event buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum BufferOverflow {
+    /// Drop the oldest buffered event to make room for the new one
+    DropOldest,
+
+    /// Drop the new event
+    DropNewest,
+}
+
+impl Config {
+    /// Check that the configuration values are valid and report all
+    /// violations at once
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = self.docker_host_errors();
+        errors.extend(self.event_errors());
+        errors.extend(self.request_errors());
+        errors.extend(self.url_errors());
+        errors.extend(self.ping_client_errors());
+        let mut check = |valid: bool, message: String| {
+            if !valid {
+                errors.push(message);
+            }
+        };
+        let fmt = humantime::format_duration;
+
+        check(
+            self.ping_interval >= MIN_PING_INTERVAL,
+            format!(
+                "ping_interval must be at least {} (got {})",
+                fmt(MIN_PING_INTERVAL),
+                fmt(self.ping_interval)
+            ),
+        );
+        if self.ping_interval < Duration::from_secs(1) {
+            warn!(
+                "ping_interval is less than one second ({}), healthchecks.io might rate limit \
+                 the pings",
+                fmt(self.ping_interval)
+            );
+        }
+        check(
+            !self.ping_timeout.is_zero(),
+            "ping_timeout must not be zero".into(),
+        );
+        check(
+            self.ping_timeout <= self.ping_interval,
+            format!(
+                "ping_timeout must not exceed ping_interval (got {} and {})",
+                fmt(self.ping_timeout),
+                fmt(self.ping_interval)
+            ),
+        );
+        check(
+            self.ping_spread.is_zero() || self.ping_spread < self.ping_timeout,
+            format!(
+                "ping_spread must be less than ping_timeout (got {} and {})",
+                fmt(self.ping_spread),
+                fmt(self.ping_timeout)
+            ),
+        );
+        check(
+            self.keepalive_interval >= Duration::from_secs(1),
+            format!(
+                "keepalive_interval must be at least one second (got {})",
+                fmt(self.keepalive_interval)
+            ),
+        );
+        check(
+            self.fetch_interval.is_zero() || self.fetch_interval >= Duration::from_secs(1),
+            format!(
+                "fetch_interval must be zero or at least one second (got {})",
+                fmt(self.fetch_interval)
+            ),
+        );
+        check(
+            !self.fetch_timeout.is_zero(),
+            "fetch_timeout must not be zero".into(),
+        );
+        check(
+            self.fetch_interval.is_zero() || self.fetch_timeout <= self.fetch_interval,
+            format!(
+                "fetch_timeout must not exceed fetch_interval (got {} and {})",
+                fmt(self.fetch_timeout),
+                fmt(self.fetch_interval)
+            ),
+        );
+        check(
+            self.interval_jitter < 100,
+            format!(
+                "interval_jitter must be less than 100 percent (got {})",
+                self.interval_jitter
+            ),
+        );
+
+        ensure!(errors.is_empty(), "{}", errors.join("\n"));
+        Ok(())
+    }
+
+    /// Take the docker host and its certificates from the configured docker
+    /// context, unless the docker host is set explicitly (like the docker
+    /// cli, where `DOCKER_HOST` takes precedence over `DOCKER_CONTEXT`)
+    fn apply_docker_context(&mut self) -> Result<()> {
+        let Some(name) = self.docker_context.as_deref() else {
+            return Ok(());
+        };
+        if name == "default" || self.docker_host.is_some() {
+            return Ok(());
+        }
+        let context = docker::context::load(name)
+            .with_context(|| format!("could not load docker context `{name}`"))?;
+        // tcp endpoints with certificates are connected to via tls
+        self.docker_host = Some(match context.host.strip_prefix("tcp://") {
+            Some(address) if context.cert_path.is_some() => format!("https://{address}"),
+            _ => context.host,
+        });
+        if self.docker_cert_path.is_none() {
+            self.docker_cert_path = context
+                .cert_path
+                .map(|path| path.to_string_lossy().into_owned());
+        }
+        if context.skip_tls_verify {
+            self.docker_tls_verify = false;
+        }
+        Ok(())
+    }
+
+    /// Parse the connection string of the docker daemon
+    pub fn docker_host(&self) -> Result<DockerHost> {
+        let Some(host) = &self.docker_host else {
+            return Ok(DockerHost::Probe(self.docker_socket_paths()?));
+        };
+        DockerHost::parse(host)
+    }
 
-    /// Number of seconds between reloading the full container list from the
-    /// docker daemon
-    pub fetch_interval: u64,
+    /// Return the docker socket paths that are probed if `docker_host` is not
+    /// set
+    fn docker_socket_paths(&self) -> Result<Vec<String>> {
+        if let Some(paths) = &self.docker_paths {
+            let paths: Vec<_> = paths
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(str::to_owned)
+                .collect();
+            ensure!(!paths.is_empty(), "docker_paths must not be empty");
+            return Ok(paths);
+        }
+
+        ensure!(
+            !self.docker_path.is_empty(),
+            "docker_path must not be empty"
+        );
+        let mut paths = vec![self.docker_path.clone()];
+        let runtime_dirs = std::env::var("XDG_RUNTIME_DIR").ok().into_iter().chain(
+            std::fs::metadata("/proc/self")
+                .ok()
+                .map(|proc| format!("/run/user/{}", proc.uid())),
+        );
+        for dir in runtime_dirs {
+            paths.push(format!("{dir}/docker.sock"));
+            paths.push(format!("{dir}/podman/podman.sock"));
+        }
+        paths.push("/run/podman/podman.sock".to_owned());
+        let mut seen = HashSet::new();
+        paths.retain(|path| seen.insert(path.clone()));
+        Ok(paths)
+    }
 
-    /// Number of seconds after which the container fetch timeout expires
-    pub fetch_timeout: u64,
+    /// Return the names and connections of all docker hosts that should be
+    /// monitored. The host is unnamed if `docker_hosts` is not set.
+    pub fn docker_hosts(&self) -> Result<Vec<(Option<String>, DockerHost)>> {
+        let Some(hosts) = &self.docker_hosts else {
+            return Ok(vec![(None, self.docker_host()?)]);
+        };
+        let mut names = HashSet::new();
+        let hosts = hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (name, host) = match entry.split_once('=') {
+                    Some((name, url)) if !name.contains("://") => {
+                        (name.trim().to_owned(), DockerHost::parse(url.trim())?)
+                    }
+                    _ => {
+                        let host = DockerHost::parse(entry)?;
+                        (host.default_name(), host)
+                    }
+                };
+                ensure!(
+                    names.insert(name.clone()),
+                    "docker host name `{name}` is used more than once"
+                );
+                Ok((Some(name), host))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ensure!(!hosts.is_empty(), "docker_hosts must not be empty");
+        Ok(hosts)
+    }
+
+    /// Parse the static resolutions of the hosts of the ping urls. A missing
+    /// port is set to `0`, in which case the port of the url is used.
+    pub fn ping_resolutions(&self) -> Result<Vec<(String, SocketAddr)>> {
+        let Some(resolve) = &self.ping_resolve else {
+            return Ok(Vec::new());
+        };
+        resolve
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (host, addr) = entry
+                    .split_once('=')
+                    .map(|(host, addr)| (host.trim(), addr.trim()))
+                    .filter(|(host, _)| !host.is_empty())
+                    .with_context(|| format!("ping_resolve entry `{entry}` is not host=addr"))?;
+                let addr = addr
+                    .parse()
+                    .or_else(|_| addr.parse().map(|ip| SocketAddr::new(ip, 0)))
+                    .with_context(|| {
+                        format!("ping_resolve entry `{entry}` has an invalid address")
+                    })?;
+                Ok((host.to_owned(), addr))
+            })
+            .collect()
+    }
 
-    /// Number of seconds after which the timeout for handling a docker event
-    /// expires
-    pub event_timeout: u64,
+    /// Return the paths of the certificates for `https` docker hosts, if
+    /// configured
+    pub fn docker_tls_files(&self) -> Option<TlsFiles> {
+        let dir = self.docker_cert_path.as_deref().map(Path::new);
+        let file = |path: &Option<String>, name| {
+            path.as_deref()
+                .map(PathBuf::from)
+                .or_else(|| dir.map(|dir| dir.join(name)))
+        };
+        Some(TlsFiles {
+            ca: file(&self.docker_ca, "ca.pem"),
+            cert: file(&self.docker_cert, "cert.pem")?,
+            key: file(&self.docker_key, "key.pem")?,
+        })
+    }
+
+    /// Return the log filter directives for the configured log levels
+    #[must_use]
+    pub fn log_filter(&self) -> String {
+        let mut filter = self.log_level.as_str().to_owned();
+        if let Some(level) = self.log_level_docker_api {
+            filter.push_str(",docker_api=");
+            filter.push_str(level.as_str());
+        }
+        filter
+    }
+
+    /// Check the urls of the self ping, the docker check and the summary and
+    /// return all violations
+    fn url_errors(&self) -> Vec<String> {
+        let mut errors: Vec<_> = [
+            ("self_ping_url", &self.self_ping_url),
+            ("docker_check_url", &self.docker_check_url),
+            ("summary_url", &self.summary_url),
+        ]
+        .into_iter()
+        .filter(|(_, url)| {
+            url.as_deref()
+                .is_some_and(|url| reqwest::Url::parse(url).is_err())
+        })
+        .map(|(name, _)| format!("{name} is not a valid url"))
+        .collect();
+        if self.summary_only && self.summary_url.is_none() {
+            errors.push("summary_only requires summary_url to be set".into());
+        }
+        errors
+    }
+
+    /// Check the limits for requests to the docker daemons and return all
+    /// violations
+    fn request_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.fetch_concurrency < 1 {
+            errors.push("fetch_concurrency must be at least one".into());
+        }
+        if self.exec_probe_concurrency < 1 {
+            errors.push("exec_probe_concurrency must be at least one".into());
+        }
+        if self.stats_interval < Duration::from_secs(1) {
+            errors.push("stats_interval must be at least one second".into());
+        }
+        if self.ping_body_limit < MIN_PING_BODY_LIMIT {
+            errors.push(format!(
+                "ping_body_limit must be at least {MIN_PING_BODY_LIMIT} bytes"
+            ));
+        }
+        errors
+    }
+
+    /// Check the settings of the ping client and return all violations
+    fn ping_client_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Err(err) = self.ping_resolutions() {
+            errors.push(format!("{err:#}"));
+        }
+        if self.ping_pool_idle_timeout.is_zero() {
+            errors.push("ping_pool_idle_timeout must not be zero".into());
+        } else if self.ping_pool_idle_timeout <= self.ping_interval {
+            warn!(
+                "ping_pool_idle_timeout ({}) does not exceed ping_interval ({}), idle \
+                 connections are closed before the next pings",
+                humantime::format_duration(self.ping_pool_idle_timeout),
+                humantime::format_duration(self.ping_interval)
+            );
+        }
+        errors
+    }
+
+    /// Check the settings of the event handler and return all violations
+    fn event_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.event_timeout.is_zero() {
+            errors.push("event_timeout must not be zero".into());
+        }
+        if self.event_concurrency < 1 {
+            errors.push("event_concurrency must be at least one".into());
+        }
+        errors
+    }
+
+    /// Check the connection settings of the docker daemon and return all
+    /// violations
+    fn docker_host_errors(&self) -> Vec<String> {
+        let hosts = match self.docker_hosts() {
+            Ok(hosts) => hosts,
+            Err(err) => return vec![format!("{err:#}")],
+        };
+        let mut errors = Vec::new();
+        if hosts
+            .iter()
+            .any(|(_, host)| matches!(host, DockerHost::Tls(_)))
+        {
+            match self.docker_tls_files() {
+                None => errors.push(
+                    "docker_cert_path (or docker_cert and docker_key) must be set for https \
+                     docker hosts"
+                        .into(),
+                ),
+                Some(files) if self.docker_tls_verify && files.ca.is_none() => errors.push(
+                    "docker_ca or docker_cert_path must be set to verify https docker hosts".into(),
+                ),
+                Some(_) => {}
+            }
+        }
+        errors
+    }
+
+    /// Return the names of the options that differ from the given
+    /// configuration but can only be changed by restarting
+    #[must_use]
+    pub fn restart_required(&self, new: &Self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        let mut check = |name, unchanged: bool| {
+            if !unchanged {
+                changed.push(name);
+            }
+        };
+        check("mode", self.mode == new.mode);
+        check("log_level", self.log_level == new.log_level);
+        check(
+            "log_level_docker_api",
+            self.log_level_docker_api == new.log_level_docker_api,
+        );
+        check("docker_host", self.docker_host == new.docker_host);
+        check("docker_context", self.docker_context == new.docker_context);
+        check("docker_hosts", self.docker_hosts == new.docker_hosts);
+        check(
+            "docker_cert_path",
+            self.docker_cert_path == new.docker_cert_path,
+        );
+        check(
+            "docker_tls_verify",
+            self.docker_tls_verify == new.docker_tls_verify,
+        );
+        check("docker_ca", self.docker_ca == new.docker_ca);
+        check("docker_cert", self.docker_cert == new.docker_cert);
+        check("docker_key", self.docker_key == new.docker_key);
+        check(
+            "docker_ssh_identity",
+            self.docker_ssh_identity == new.docker_ssh_identity,
+        );
+        check("docker_path", self.docker_path == new.docker_path);
+        check("docker_paths", self.docker_paths == new.docker_paths);
+        check("startup_jitter", self.startup_jitter == new.startup_jitter);
+        check("dry_run", self.dry_run == new.dry_run);
+        check("status_addr", self.status_addr == new.status_addr);
+        check("status_token", self.status_token == new.status_token);
+        check("admin_token", self.admin_token == new.admin_token);
+        check(
+            "startup_timeout",
+            self.startup_timeout == new.startup_timeout,
+        );
+        check(
+            "ping_on_startup",
+            self.ping_on_startup == new.ping_on_startup,
+        );
+        check(
+            "exec_probe_concurrency",
+            self.exec_probe_concurrency == new.exec_probe_concurrency,
+        );
+        check("stats_interval", self.stats_interval == new.stats_interval);
+        check("ping_resolve", self.ping_resolve == new.ping_resolve);
+        check("ping_dns_cache", self.ping_dns_cache == new.ping_dns_cache);
+        check(
+            "ping_ip_version",
+            self.ping_ip_version == new.ping_ip_version,
+        );
+        check(
+            "ping_pool_idle_timeout",
+            self.ping_pool_idle_timeout == new.ping_pool_idle_timeout,
+        );
+        check(
+            "ping_pool_max_idle_per_host",
+            self.ping_pool_max_idle_per_host == new.ping_pool_max_idle_per_host,
+        );
+        check(
+            "ping_tcp_keepalive",
+            self.ping_tcp_keepalive == new.ping_tcp_keepalive,
+        );
+        check("event_timeout", self.event_timeout == new.event_timeout);
+        check(
+            "event_concurrency",
+            self.event_concurrency == new.event_concurrency,
+        );
+        check(
+            "event_buffer_size",
+            self.event_buffer_size == new.event_buffer_size,
+        );
+        check(
+            "event_buffer_overflow",
+            self.event_buffer_overflow == new.event_buffer_overflow,
+        );
+        check("severity_order", self.severity_order == new.severity_order);
+        check("swarm_mode", self.swarm_mode == new.swarm_mode);
+        check(
+            "swarm_aggregation",
+            self.swarm_aggregation == new.swarm_aggregation,
+        );
+        check(
+            "include_stopped_on_startup",
+            self.include_stopped_on_startup == new.include_stopped_on_startup,
+        );
+        check("state_file", self.state_file == new.state_file);
+        check("state_max_age", self.state_max_age == new.state_max_age);
+        check(
+            "health_history_size",
+            self.health_history_size == new.health_history_size,
+        );
+        changed
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            mode: RunMode::Daemon,
+            log_level: LogLevel::Error,
+            log_level_docker_api: None,
+            docker_host: None,
+            docker_context: None,
+            docker_hosts: None,
+            docker_cert_path: None,
+            docker_tls_verify: true,
+            docker_ca: None,
+            docker_cert: None,
+            docker_key: None,
+            docker_ssh_identity: None,
             docker_path: "/var/run/docker.sock".to_owned(),
-            ping_interval: 60,
+            docker_paths: None,
+            startup_timeout: None,
+            ping_interval: Duration::from_secs(60),
+            ping_spread: Duration::ZERO,
+            ping_mode: PingMode::Interval,
+            ping_suffix_healthy: String::new(),
+            ping_suffix_unhealthy: "/fail".into(),
+            ping_suffix_starting: "/start".into(),
+            dry_run: false,
+            keepalive_interval: Duration::from_secs(3600),
+            ping_on_startup: true,
+            align_pings: false,
             ping_retries: 5,
-            ping_timeout: 50,
-            fetch_interval: 600,
-            fetch_timeout: 300,
-            event_timeout: 60,
+            ping_body_limit: 10_000,
+            ping_resolve: None,
+            ping_dns_cache: false,
+            ping_ip_version: IpVersion::Auto,
+            ping_pool_idle_timeout: Duration::from_secs(120),
+            ping_pool_max_idle_per_host: 32,
+            ping_tcp_keepalive: Duration::from_secs(30),
+            ping_timeout: Duration::from_secs(50),
+            fetch_interval: Duration::from_secs(600),
+            fetch_timeout: Duration::from_secs(300),
+            startup_jitter: Duration::ZERO,
+            interval_jitter: 0,
+            fetch_concurrency: 16,
+            exec_probe_concurrency: 4,
+            stats_interval: Duration::from_secs(30),
+            inspect_retries: 2,
+            inspect_retry_delay: Duration::from_millis(500),
+            event_timeout: Duration::from_secs(60),
+            event_concurrency: 8,
+            event_buffer_size: 1000,
+            event_buffer_overflow: BufferOverflow::DropOldest,
+            flap_threshold: 1,
+            min_failing_streak: 0,
+            die_grace: Duration::ZERO,
+            severity_order: SeverityOrder::UnhealthyWorst,
+            restarting_unhealthy: true,
+            max_restarts_per_hour: None,
+            starting_as_healthy_after: None,
+            swarm_mode: false,
+            swarm_aggregation: false,
+            include_stopped_on_startup: false,
+            state_file: None,
+            state_max_age: Duration::from_secs(86400),
+            health_history_size: 50,
+            summary_interval: Duration::from_secs(900),
+            dump_file: None,
+            dump_redact_urls: true,
+            status_addr: None,
+            status_token: None,
+            admin_token: None,
+            self_ping_url: None,
+            docker_check_url: None,
+            summary_url: None,
+            summary_only: false,
+            docker_down_threshold: Duration::from_secs(300),
+            on_docker_lost: DockerLostAction::Keep,
+        }
+    }
+}
+
+/// load configuration from the given overrides, environment variables, files
+/// referenced by `*_FILE` variables and the configuration file (in this order
+/// of precedence). Prefixed variables take precedence over the deprecated
+/// unprefixed ones.
+pub fn load(overrides: Option<&Overrides>) -> Result<Config> {
+    load_from(&std::env::vars().collect(), overrides)
+}
+
+/// Load the configuration like [`load`], taking the environment variables from
+/// the given map instead of the environment of the process
+fn load_from(env: &Env, overrides: Option<&Overrides>) -> Result<Config> {
+    for (name, prefixed) in legacy_variables(env)? {
+        warn!("environment variable {name} is deprecated, use {prefixed} instead");
+    }
+    let mut builder = config::Config::builder();
+    let path = overrides
+        .and_then(|overrides| overrides.config_file.clone())
+        .or_else(|| config_file(env));
+    if let Some(path) = path {
+        let file = config::Config::builder()
+            .add_source(config::File::from(path.as_path()))
+            .build()
+            .with_context(|| format!("could not load configuration file {}", path.display()))?;
+        warn_unknown_keys(&path, &file)?;
+        builder = builder.add_source(file);
+    }
+    builder = builder
+        .add_source(file_variables(env)?)
+        .add_source(config::Environment::default().source(Some(env.clone())))
+        .add_source(config::Environment::with_prefix(ENV_PREFIX).source(Some(env.clone())));
+    if let Some(overrides) = overrides {
+        builder = builder.add_source(override_source(overrides)?);
+    }
+    let mut config: Config = builder.build()?.try_deserialize()?;
+    config.apply_docker_context()?;
+    Ok(config)
+}
+
+/// Create a configuration source from the contents of the files referenced by
+/// `<NAME>_FILE` environment variables (e.g. docker secrets)
+fn file_variables(env: &Env) -> Result<config::Config> {
+    let known = known_keys()?;
+    let prefix = format!("{ENV_PREFIX}_");
+    let mut variables: Vec<_> = env
+        .iter()
+        .filter_map(|(name, path)| {
+            let key = name.strip_suffix("_FILE")?;
+            let prefixed = key.starts_with(&prefix);
+            let key = key.strip_prefix(&prefix).unwrap_or(key).to_lowercase();
+            known
+                .contains_key(&key)
+                .then_some((prefixed, key, name, path))
+        })
+        .collect();
+    // read the prefixed variables last, so that they take precedence
+    variables.sort();
+    let mut values = serde_json::Map::new();
+    for (_, key, name, path) in variables {
+        let value = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {name} from {path}"))?;
+        values.insert(key, value.trim().into());
+    }
+    Ok(config::Config::try_from(&values)?)
+}
+
+/// Create a configuration source from the overrides that have been set
+fn override_source(overrides: &Overrides) -> Result<config::Config> {
+    let serde_json::Value::Object(mut values) = serde_json::to_value(overrides)? else {
+        return Ok(config::Config::default());
+    };
+    values.retain(|_, value| !value.is_null());
+    Ok(config::Config::try_from(&values)?)
+}
+
+/// Return the path of the configuration file that should be loaded, if any
+fn config_file(env: &Env) -> Option<PathBuf> {
+    env.get(&format!("{ENV_PREFIX}_CONFIG_FILE"))
+        .or_else(|| env.get("CONFIG_FILE"))
+        .map(PathBuf::from)
+        .or_else(|| {
+            let path = Path::new(DEFAULT_CONFIG_FILE);
+            path.is_file().then(|| path.to_owned())
+        })
+}
+
+/// Log a warning listing all keys of the configuration file that do not
+/// correspond to a configuration option
+fn warn_unknown_keys(path: &Path, file: &config::Config) -> Result<()> {
+    let known = known_keys()?;
+    let mut unknown = file
+        .clone()
+        .try_deserialize::<config::Map<String, config::Value>>()?
+        .into_keys()
+        .filter(|key| !known.contains_key(&key.to_lowercase()))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        unknown.sort();
+        warn!(
+            "ignoring unknown keys in configuration file {}: {}",
+            path.display(),
+            unknown.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Return the names of all configuration options
+fn known_keys() -> Result<serde_json::Map<String, serde_json::Value>> {
+    let serde_json::Value::Object(keys) = serde_json::to_value(Config::default())? else {
+        return Ok(serde_json::Map::new());
+    };
+    Ok(keys)
+}
+
+/// Return the configuration variables that are only set without the prefix,
+/// together with the name of the prefixed variable
+fn legacy_variables(env: &Env) -> Result<Vec<(String, String)>> {
+    let keys = known_keys()?;
+    let mut legacy: Vec<_> = env
+        .keys()
+        .filter(|name| {
+            keys.contains_key(&name.to_lowercase()) && !DOCKER_VARIABLES.contains(&name.as_str())
+        })
+        .map(|name| (name.clone(), format!("{ENV_PREFIX}_{name}")))
+        .filter(|(_, prefixed)| !env.contains_key(prefixed))
+        .collect();
+    legacy.sort();
+    Ok(legacy)
+}
+
+/// (De)serialization of durations as humantime strings (e.g. `1h30m`), plain
+/// integers are interpreted as seconds
+mod duration {
+    use std::{fmt, time::Duration};
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    /// Serialize a duration as a humantime string
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_duration(*value))
+    }
+
+    /// Serialize an optional duration as a humantime string
+    #[allow(clippy::ref_option)]
+    pub fn serialize_option<S: Serializer>(
+        value: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Parse a duration from a humantime string or a number of seconds
+    pub fn parse(value: &str) -> Result<Duration, String> {
+        let value = value.trim();
+        match value.parse() {
+            Ok(secs) => Ok(Duration::from_secs(secs)),
+            Err(_) => humantime::parse_duration(value)
+                .map_err(|err| format!("invalid duration `{value}`: {err}")),
+        }
+    }
+
+    /// Deserialize a duration from a humantime string or a number of seconds
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        deserializer.deserialize_any(Visitor)
+    }
+
+    /// Deserialize an optional duration from a humantime string or a number
+    /// of seconds
+    pub fn deserialize_option<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        /// Duration inside of an option
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "deserialize")] Duration);
+
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(value)| value))
+    }
+
+    /// Visitor for durations
+    struct Visitor;
+
+    impl de::Visitor<'_> for Visitor {
+        type Value = Duration;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a duration like `30s`, `5m` or `1h30m` or a number of seconds")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Duration, E> {
+            Ok(Duration::from_secs(v))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Duration, E> {
+            u64::try_from(v)
+                .map(Duration::from_secs)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Duration, E> {
+            parse(v).map_err(E::custom)
+        }
+    }
+}
+
+/// Serialization of secrets, which are redacted so that printing the
+/// configuration does not expose them
+mod redact {
+    use serde::Serializer;
+
+    use crate::dump::redact_url;
+
+    /// Serialize a secret as `<redacted>` if it is set
+    #[allow(clippy::ref_option)]
+    pub fn secret<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(_) => serializer.serialize_str("<redacted>"),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    /// Serialize a url with its secret path redacted if it is set
+    #[allow(clippy::ref_option)]
+    pub fn url<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(url) => serializer.serialize_str(&redact_url(url)),
+            None => serializer.serialize_none(),
         }
     }
 }
 
-/// load configuration from environment variables
-pub fn load() -> Result<Config> {
-    Ok(config::Config::builder()
-        .add_source(config::Environment::default())
-        .build()?
-        .try_deserialize()?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return the default configuration with the given ping interval and
+    /// timeout in milliseconds
+    fn with_ping_times(interval: u64, timeout: u64) -> Config {
+        Config {
+            ping_interval: Duration::from_millis(interval),
+            ping_timeout: Duration::from_millis(timeout),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn sub_second_ping_intervals_are_valid() {
+        with_ping_times(500, 400).validate().unwrap();
+        with_ping_times(100, 100).validate().unwrap();
+    }
+
+    #[test]
+    fn ping_intervals_below_the_minimum_are_invalid() {
+        let err = with_ping_times(99, 50).validate().unwrap_err();
+        assert!(err.to_string().contains("ping_interval must be at least"));
+    }
+
+    #[test]
+    fn ping_timeouts_must_not_exceed_the_interval() {
+        let err = with_ping_times(500, 600).validate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("ping_timeout must not exceed ping_interval"));
+    }
+
+    /// Validate the given configuration and return its only violation
+    fn violation(config: &Config) -> String {
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(err.lines().count(), 1, "{err}");
+        err
+    }
+
+    #[test]
+    fn ping_timeouts_must_not_be_zero() {
+        assert_eq!(
+            violation(&with_ping_times(60_000, 0)),
+            "ping_timeout must not be zero"
+        );
+    }
+
+    #[test]
+    fn ping_spreads_must_be_less_than_the_ping_timeout() {
+        let config = Config {
+            ping_spread: Duration::from_secs(50),
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "ping_spread must be less than ping_timeout (got 50s and 50s)"
+        );
+    }
+
+    #[test]
+    fn keepalive_intervals_must_be_at_least_one_second() {
+        let config = Config {
+            keepalive_interval: Duration::from_millis(500),
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "keepalive_interval must be at least one second (got 500ms)"
+        );
+    }
+
+    #[test]
+    fn fetch_intervals_must_be_zero_or_at_least_one_second() {
+        let config = Config {
+            fetch_interval: Duration::from_millis(500),
+            fetch_timeout: Duration::from_millis(100),
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "fetch_interval must be zero or at least one second (got 500ms)"
+        );
+    }
+
+    #[test]
+    fn fetch_timeouts_must_not_be_zero() {
+        let config = Config {
+            fetch_timeout: Duration::ZERO,
+            ..Config::default()
+        };
+        assert_eq!(violation(&config), "fetch_timeout must not be zero");
+    }
+
+    #[test]
+    fn fetch_timeouts_must_not_exceed_the_interval() {
+        let config = Config {
+            fetch_interval: Duration::from_secs(60),
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "fetch_timeout must not exceed fetch_interval (got 5m and 1m)"
+        );
+
+        // the timeout is not limited if containers are never fetched again
+        Config {
+            fetch_interval: Duration::ZERO,
+            ..Config::default()
+        }
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn interval_jitters_must_be_less_than_100_percent() {
+        let config = Config {
+            interval_jitter: 100,
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "interval_jitter must be less than 100 percent (got 100)"
+        );
+    }
+
+    #[test]
+    fn fetch_concurrencies_must_be_at_least_one() {
+        let config = Config {
+            fetch_concurrency: 0,
+            ..Config::default()
+        };
+        assert_eq!(violation(&config), "fetch_concurrency must be at least one");
+    }
+
+    #[test]
+    fn exec_probe_concurrencies_must_be_at_least_one() {
+        let config = Config {
+            exec_probe_concurrency: 0,
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "exec_probe_concurrency must be at least one"
+        );
+    }
+
+    #[test]
+    fn stats_intervals_must_be_at_least_one_second() {
+        let config = Config {
+            stats_interval: Duration::from_millis(500),
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "stats_interval must be at least one second"
+        );
+    }
+
+    #[test]
+    fn ping_body_limits_must_fit_the_truncation_marker() {
+        let config = Config {
+            ping_body_limit: MIN_PING_BODY_LIMIT - 1,
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            format!("ping_body_limit must be at least {MIN_PING_BODY_LIMIT} bytes")
+        );
+    }
+
+    #[test]
+    fn ping_resolutions_must_be_valid() {
+        for (resolve, error) in [
+            (
+                "hc.example",
+                "ping_resolve entry `hc.example` is not host=addr",
+            ),
+            (
+                "hc.example=invalid",
+                "ping_resolve entry `hc.example=invalid` has an invalid address",
+            ),
+        ] {
+            let config = Config {
+                ping_resolve: Some(resolve.into()),
+                ..Config::default()
+            };
+            assert!(violation(&config).starts_with(error), "{resolve}");
+        }
+    }
+
+    #[test]
+    fn ping_pool_idle_timeouts_must_not_be_zero() {
+        let config = Config {
+            ping_pool_idle_timeout: Duration::ZERO,
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "ping_pool_idle_timeout must not be zero"
+        );
+    }
+
+    #[test]
+    fn event_timeouts_must_not_be_zero() {
+        let config = Config {
+            event_timeout: Duration::ZERO,
+            ..Config::default()
+        };
+        assert_eq!(violation(&config), "event_timeout must not be zero");
+    }
+
+    #[test]
+    fn event_concurrencies_must_be_at_least_one() {
+        let config = Config {
+            event_concurrency: 0,
+            ..Config::default()
+        };
+        assert_eq!(violation(&config), "event_concurrency must be at least one");
+    }
+
+    #[test]
+    fn urls_must_be_valid() {
+        let config = Config {
+            self_ping_url: Some("hc.example/check".into()),
+            ..Config::default()
+        };
+        assert_eq!(violation(&config), "self_ping_url is not a valid url");
+    }
+
+    #[test]
+    fn summary_only_requires_a_summary_url() {
+        let config = Config {
+            summary_only: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            violation(&config),
+            "summary_only requires summary_url to be set"
+        );
+    }
+
+    #[test]
+    fn docker_hosts_must_be_valid() {
+        let config = Config {
+            docker_host: Some("ftp://docker.example".into()),
+            ..Config::default()
+        };
+        assert!(violation(&config).contains("has an unsupported scheme `ftp`"));
+    }
+
+    #[test]
+    fn https_docker_hosts_require_certificates() {
+        let config = Config {
+            docker_host: Some("https://docker.example:2376".into()),
+            ..Config::default()
+        };
+        assert!(violation(&config).starts_with("docker_cert_path (or docker_cert and docker_key)"));
+
+        // the certificate authority is only required to verify the docker host
+        let config = Config {
+            docker_cert: Some("cert.pem".into()),
+            docker_key: Some("key.pem".into()),
+            ..config
+        };
+        assert!(violation(&config).starts_with("docker_ca or docker_cert_path must be set"));
+        Config {
+            docker_tls_verify: false,
+            ..config
+        }
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn all_violations_are_reported_at_once() {
+        let config = Config {
+            fetch_concurrency: 0,
+            event_timeout: Duration::ZERO,
+            interval_jitter: 100,
+            ..with_ping_times(500, 600)
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(err.lines().count(), 4, "{err}");
+    }
+
+    /// Path of a file in the test fixtures
+    macro_rules! fixture {
+        ($name:literal) => {
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/", $name)
+        };
+    }
+
+    /// Build an environment from the given variables. The configuration file
+    /// is set to an empty one unless given, so that the tests do not depend
+    /// on the files of the host.
+    fn env(vars: &[(&str, &str)]) -> Env {
+        let mut env: Env = vars
+            .iter()
+            .map(|&(name, value)| (name.into(), value.into()))
+            .collect();
+        env.entry("DHC_CONFIG_FILE".into())
+            .or_insert_with(|| fixture!("empty.toml").into());
+        env
+    }
+
+    #[test]
+    fn prefixed_variables_take_precedence_over_legacy_ones() {
+        let env = env(&[("PING_INTERVAL", "10"), ("DHC_PING_INTERVAL", "20")]);
+        let config = load_from(&env, None).unwrap();
+        assert_eq!(config.ping_interval, Duration::from_secs(20));
+        assert_eq!(legacy_variables(&env).unwrap(), []);
+    }
+
+    #[test]
+    fn legacy_variables_are_used_with_a_warning() {
+        let env = env(&[
+            ("PING_INTERVAL", "10"),
+            ("DOCKER_HOST", "unix:///docker.sock"),
+        ]);
+        let config = load_from(&env, None).unwrap();
+        assert_eq!(config.ping_interval, Duration::from_secs(10));
+        assert_eq!(config.docker_host.as_deref(), Some("unix:///docker.sock"));
+        // the variables of the docker cli are not deprecated
+        assert_eq!(
+            legacy_variables(&env).unwrap(),
+            [("PING_INTERVAL".into(), "DHC_PING_INTERVAL".into())]
+        );
+    }
+
+    #[test]
+    fn defaults_are_used_without_other_sources() {
+        let config = load_from(&env(&[]), None).unwrap();
+        assert_eq!(config.ping_interval, Config::default().ping_interval);
+        assert_eq!(config.ping_retries, Config::default().ping_retries);
+    }
+
+    #[test]
+    fn config_files_take_precedence_over_defaults() {
+        let toml = load_from(&env(&[("DHC_CONFIG_FILE", fixture!("config.toml"))]), None).unwrap();
+        assert_eq!(toml.ping_interval, Duration::from_secs(30));
+        assert_eq!(toml.ping_retries, 3);
+        assert_eq!(toml.ping_suffix_unhealthy, "/toml-fail");
+        assert_eq!(
+            toml.ping_suffix_starting,
+            Config::default().ping_suffix_starting
+        );
+
+        let yaml = load_from(&env(&[("DHC_CONFIG_FILE", fixture!("config.yaml"))]), None).unwrap();
+        assert_eq!(yaml.ping_interval, Duration::from_secs(45));
+        assert_eq!(yaml.ping_retries, 4);
+        assert_eq!(yaml.ping_suffix_unhealthy, "/yaml-fail");
+    }
+
+    #[test]
+    fn environment_variables_take_precedence_over_config_files() {
+        let env = env(&[
+            ("DHC_CONFIG_FILE", fixture!("config.toml")),
+            ("DHC_PING_RETRIES", "5"),
+        ]);
+        let config = load_from(&env, None).unwrap();
+        assert_eq!(config.ping_retries, 5);
+        assert_eq!(config.ping_interval, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn flags_take_precedence_over_all_other_sources() {
+        let env = env(&[
+            ("DHC_CONFIG_FILE", fixture!("config.toml")),
+            ("DHC_PING_RETRIES", "5"),
+            ("DHC_PING_SUFFIX_UNHEALTHY", "/env-fail"),
+        ]);
+        let overrides = Overrides {
+            ping_retries: Some(6),
+            ..Overrides::default()
+        };
+        let config = load_from(&env, Some(&overrides)).unwrap();
+        assert_eq!(config.ping_retries, 6);
+        assert_eq!(config.ping_suffix_unhealthy, "/env-fail");
+        assert_eq!(config.ping_interval, Duration::from_secs(30));
+        assert_eq!(
+            config.ping_suffix_starting,
+            Config::default().ping_suffix_starting
+        );
+    }
+
+    #[test]
+    fn config_file_flags_take_precedence_over_environment_variables() {
+        let env = env(&[("DHC_CONFIG_FILE", fixture!("config.toml"))]);
+        let overrides = Overrides {
+            config_file: Some(fixture!("config.yaml").into()),
+            ..Overrides::default()
+        };
+        let config = load_from(&env, Some(&overrides)).unwrap();
+        assert_eq!(config.ping_interval, Duration::from_secs(45));
+    }
+
+    #[test]
+    fn durations_accept_humantime_strings_and_seconds() {
+        let env = env(&[
+            ("DHC_PING_SPREAD", "1m30s"),
+            ("DHC_KEEPALIVE_INTERVAL", "2h"),
+            ("DHC_DIE_GRACE", "45"),
+            ("DHC_STATE_MAX_AGE", "1d"),
+            ("DHC_SUMMARY_INTERVAL", "0"),
+            ("DHC_INSPECT_RETRY_DELAY", "250ms"),
+        ]);
+        let config = load_from(&env, None).unwrap();
+        assert_eq!(config.ping_spread, Duration::from_secs(90));
+        assert_eq!(config.keepalive_interval, Duration::from_secs(7200));
+        assert_eq!(config.die_grace, Duration::from_secs(45));
+        assert_eq!(config.state_max_age, Duration::from_secs(86400));
+        assert_eq!(config.summary_interval, Duration::ZERO);
+        assert_eq!(config.inspect_retry_delay, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn ip_versions_are_parsed() {
+        for (value, expected) in [
+            ("auto", IpVersion::Auto),
+            ("v4", IpVersion::V4),
+            ("v6", IpVersion::V6),
+        ] {
+            let config = load_from(&env(&[("DHC_PING_IP_VERSION", value)]), None).unwrap();
+            assert_eq!(config.ping_ip_version, expected);
+        }
+        assert_eq!(Config::default().ping_ip_version, IpVersion::Auto);
+        assert!(load_from(&env(&[("DHC_PING_IP_VERSION", "v5")]), None).is_err());
+
+        let overrides = Overrides {
+            ping_ip_version: Some(IpVersion::V4),
+            ..Overrides::default()
+        };
+        let env = env(&[("DHC_PING_IP_VERSION", "v6")]);
+        let config = load_from(&env, Some(&overrides)).unwrap();
+        assert_eq!(config.ping_ip_version, IpVersion::V4);
+    }
+
+    #[test]
+    fn parse_errors_name_the_file_and_line() {
+        for path in [fixture!("invalid.toml"), fixture!("invalid.yaml")] {
+            let err = load_from(&env(&[("DHC_CONFIG_FILE", path)]), None)
+                .err()
+                .unwrap();
+            let err = format!("{err:#}");
+            assert!(err.contains(path), "{err}");
+            assert!(err.contains("line 2"), "{err}");
+        }
+    }
+
+    #[test]
+    fn file_variables_are_read_and_trimmed() {
+        let env = env(&[("DHC_STATUS_TOKEN_FILE", fixture!("secret.txt"))]);
+        let config = load_from(&env, None).unwrap();
+        assert_eq!(config.status_token.as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn direct_variables_take_precedence_over_file_variables() {
+        let env = env(&[
+            ("DHC_STATUS_TOKEN", "direct"),
+            ("DHC_STATUS_TOKEN_FILE", fixture!("secret.txt")),
+        ]);
+        let config = load_from(&env, None).unwrap();
+        assert_eq!(config.status_token.as_deref(), Some("direct"));
+    }
+
+    #[test]
+    fn unreadable_file_variables_are_errors() {
+        let path = fixture!("missing.txt");
+        let env = env(&[("DHC_STATUS_TOKEN_FILE", path)]);
+        let err = load_from(&env, None).err().unwrap().to_string();
+        assert_eq!(
+            err,
+            format!("could not read DHC_STATUS_TOKEN_FILE from {path}")
+        );
+    }
+
+    #[test]
+    fn docker_hosts_are_parsed() {
+        let cases = [
+            (
+                "unix:///var/run/docker.sock",
+                DockerHost::Unix("/var/run/docker.sock".into()),
+            ),
+            (
+                "tcp://localhost:2375",
+                DockerHost::Tcp("localhost:2375".into()),
+            ),
+            (
+                "http://localhost:2375",
+                DockerHost::Tcp("localhost:2375".into()),
+            ),
+            (
+                "https://localhost:2376",
+                DockerHost::Tls("localhost:2376".into()),
+            ),
+            ("ssh://user@host", DockerHost::Ssh("ssh://user@host".into())),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(DockerHost::parse(host).unwrap(), expected, "{host}");
+        }
+    }
+
+    #[test]
+    fn invalid_docker_hosts_are_errors() {
+        let cases = [
+            ("/var/run/docker.sock", "has no scheme"),
+            ("tcp://", "has no address"),
+            ("tcp://localhost:2375/path", "must not contain a path"),
+            ("ftp://localhost", "has an unsupported scheme `ftp`"),
+        ];
+        for (host, expected) in cases {
+            let err = DockerHost::parse(host).unwrap_err().to_string();
+            assert!(err.contains(expected), "{host}: {err}");
+        }
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_printed() {
+        let config = Config {
+            status_token: Some("status-secret".into()),
+            admin_token: Some("admin-secret".into()),
+            self_ping_url: Some("https://hc.example/ping/self-secret".into()),
+            docker_check_url: Some("https://hc.example/ping/docker-secret".into()),
+            summary_url: Some("https://hc.example/summary-secret".into()),
+            ..Config::default()
+        };
+        let printed = serde_json::to_value(&config).unwrap();
+        assert_eq!(printed["status_token"], "<redacted>");
+        assert_eq!(printed["admin_token"], "<redacted>");
+        assert_eq!(printed["self_ping_url"], "https://hc.example/<redacted>");
+        assert_eq!(printed["docker_check_url"], "https://hc.example/<redacted>");
+        assert_eq!(printed["summary_url"], "https://hc.example/<redacted>");
+        assert!(!printed.to_string().contains("secret\""));
+
+        let unset = serde_json::to_value(Config::default()).unwrap();
+        assert!(unset["status_token"].is_null());
+        assert!(unset["self_ping_url"].is_null());
+    }
 }
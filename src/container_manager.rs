@@ -1,20 +1,87 @@
 //! Manage monitored docker containers
 
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, bail, Context, Result};
-use docker_api::{models::ContainerInspect200Response, opts::ContainerListOpts, Docker};
-use futures_util::future::join_all;
-use tokio::sync::RwLock;
-use tracing::{error, info};
+use ::metrics::{counter, histogram};
+use anyhow::{anyhow, bail, Context, Error, Result};
+use docker_api::{
+    models::ContainerInspect200Response,
+    opts::{ContainerFilter, ContainerListOpts, ContainerStatus},
+};
+use futures_util::{future::join_all, stream, StreamExt};
+use regex::Regex;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    spawn,
+    sync::{
+        mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        Mutex, OnceCell, RwLock, RwLockReadGuard, RwLockWriteGuard, Semaphore,
+    },
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tracing::{debug, error, info, warn, Instrument};
+
+use crate::{
+    config::{Config, DockerLostAction, SeverityOrder},
+    docker::runtime::ContainerRuntime,
+    dump::{redact_url, ContainerDump, Dump},
+    healthchecks::Healthchecks,
+    log_pattern::{self, LogMatch, LogPattern},
+    metrics,
+    probe::{
+        self, Probe, ProbeContext, ProbeKind, ProbeNetwork, ProbeResult, ProbeTarget, StatusRange,
+    },
+    reachability::Reachability,
+    state,
+    stats::{self, ResourceBreach, ResourceLimits},
+    swarm,
+};
+
+/// Duration for which died containers are remembered, so that delayed health
+/// status events for them can be ignored
+const TOMBSTONE_DURATION: Duration = Duration::from_secs(60);
+
+/// Time window in which restarts are counted for `max_restarts_per_hour`
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Time window in which transitions to unhealthy are counted as flaps in the
+/// summary log
+const FLAP_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// Number of characters of a container id that are shown in logs
+const SHORT_ID_LENGTH: usize = 12;
 
-use crate::healthchecks::Healthchecks;
+/// Probe, log pattern or resource limits of a container and the task checking
+/// it
+type RunningTask<T> = (Arc<T>, JoinHandle<()>);
+
+/// Mapping from container ids to the tasks checking their probe, log pattern
+/// or resource limits
+type RunningTasks<T> = std::sync::Mutex<HashMap<Arc<str>, RunningTask<T>>>;
+
+/// Name and labels of a swarm service
+type ServiceInfo = (String, HashMap<String, String>);
+
+/// Names and labels of the swarm services inspected while fetching
+/// containers, so that each service is only inspected once per fetch
+#[derive(Default)]
+struct ServiceCache(std::sync::Mutex<HashMap<String, Arc<OnceCell<ServiceInfo>>>>);
 
 /// Docker container health status
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Health {
     /// Healthy indicates that the container is running correctly
     Healthy,
@@ -26,284 +93,3738 @@ pub enum Health {
     Starting,
 }
 
+impl Health {
+    /// Return the severity of the health status according to the given order.
+    /// A higher severity indicates a 'worse' health status.
+    fn severity(self, order: SeverityOrder) -> u8 {
+        match (self, order) {
+            (Self::Healthy, _) => 0,
+            (Self::Starting, SeverityOrder::UnhealthyWorst)
+            | (Self::Unhealthy, SeverityOrder::StartingWorst) => 1,
+            (Self::Unhealthy, SeverityOrder::UnhealthyWorst)
+            | (Self::Starting, SeverityOrder::StartingWorst) => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Healthy => "healthy",
+            Self::Unhealthy => "unhealthy",
+            Self::Starting => "starting",
+        })
+    }
+}
+
+/// Change of the reported health status of a container
+#[derive(Debug, Clone, Serialize)]
+pub struct Transition {
+    /// Unix timestamp of the transition
+    pub time: u64,
+
+    /// Reported health status before the transition (`None` if the container
+    /// was not monitored)
+    pub old: Option<Health>,
+
+    /// Reported health status after the transition (`None` if the container
+    /// died)
+    pub new: Option<Health>,
+
+    /// Type of the docker event that caused the transition
+    pub source: &'static str,
+}
+
+/// How the health status of a container is determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MonitorMode {
+    /// The health status is reported by the docker healthcheck of the
+    /// container (containers without a healthcheck are considered healthy)
+    Healthcheck,
+
+    /// The container is considered healthy as long as it is running. Health
+    /// status events are ignored.
+    Liveness,
+
+    /// The health status is determined by an http, tcp or exec probe of the container. It
+    /// is starting until the first probe has completed.
+    Probe,
+}
+
 /// Monitored docker container
 #[derive(Debug)]
 struct Container {
     /// healthchecks url of the container
-    ping_url: String,
+    ping_url: Arc<str>,
+
+    /// Name of the container
+    name: Option<String>,
+
+    /// Image reference of the container
+    image: Option<String>,
+
+    /// Time at which the container was created
+    created: Option<String>,
+
+    /// Time at which the container was last started
+    started_at: Option<String>,
 
     /// health status of the container (`None` if the container has no
     /// healthcheck)
     health: Option<Health>,
+
+    /// Whether the container was running when it was inspected
+    running: bool,
+
+    /// How the health status of the container is determined
+    mode: MonitorMode,
+
+    /// Number of invalid healthchecks labels of the container
+    invalid_labels: usize,
+
+    /// Whether the container was restarting when it was inspected and
+    /// restarting containers are reported as unhealthy
+    restarting: bool,
+
+    /// Name of the swarm service this container is a task of (only set in
+    /// swarm mode)
+    service: Option<Arc<str>>,
+
+    /// Generation of the last observation of the health status. Observations
+    /// of older generations are discarded.
+    updated: u64,
+
+    /// Number of consecutive unhealthy observations
+    unhealthy_streak: u32,
+
+    /// Health status of the container before the current unhealthy streak
+    /// started
+    previous_health: Option<Health>,
+
+    /// Number of consecutive unhealthy observations required before the
+    /// container is reported as unhealthy
+    flap_threshold: u32,
+
+    /// Number of times the container has been restarted by the docker daemon
+    restart_count: usize,
+
+    /// Number of consecutive failed healthchecks of the container
+    failing_streak: usize,
+
+    /// Number of consecutive failed healthchecks required before the
+    /// container is reported as unhealthy
+    min_failing_streak: usize,
+
+    /// Times at which restarts of the container have been observed within the
+    /// restart window
+    restarts: VecDeque<Instant>,
+
+    /// Maximum number of restarts within the restart window before the
+    /// container is reported as unhealthy
+    max_restarts: Option<usize>,
+
+    /// Whether the container exceeded the maximum number of restarts at the
+    /// time of the last observation. This is only updated on observations,
+    /// so that the reported health status of a container cannot change
+    /// without an update.
+    restart_limit_exceeded: bool,
+
+    /// Progress of the container if it is starting
+    starting: Option<Starting>,
+
+    /// Probe of the container, if its health status is determined by
+    /// probing it
+    probe: Option<Arc<Probe>>,
+
+    /// Output of the last exec probe of the container
+    probe_output: Option<String>,
+
+    /// Pattern of log lines that mark the container as unhealthy
+    log_pattern: Option<Arc<LogPattern>>,
+
+    /// Time at which a log line last matched the log pattern, and the line
+    log_match: Option<(Instant, String)>,
+
+    /// Resource thresholds of the container
+    resource_limits: Option<Arc<ResourceLimits>>,
+
+    /// Description of the resource threshold the container currently exceeds
+    resource_breach: Option<String>,
 }
 
-/// Stores monitored and ignored containers
-struct ManagedContainers {
-    /// Mapping from container id to container data for monitored containers
-    monitored_containers: HashMap<String, Container>,
+/// Progress of a container that is continuously starting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Starting {
+    /// The container has been starting since the given time
+    Since(SystemTime),
 
-    /// Set of containers without healthchecks label. These containers can be
-    /// safely ignored, as it is not possible to add labels to running
-    /// containers.
-    ignored_containers: HashSet<String>,
+    /// The container has been starting for longer than the configured
+    /// duration and is reported as healthy
+    Expired,
 }
 
-/// Manager for monitored docker containers
-pub struct ContainerManager {
-    /// Docker daemon interface
-    docker: Docker,
+impl Container {
+    /// Record an observation of the health status of the container of the
+    /// given generation. Observations that are older than the last recorded
+    /// one are discarded. Returns whether the observation has been recorded.
+    fn observe(&mut self, health: Option<Health>, generation: u64) -> bool {
+        if generation < self.updated {
+            return false;
+        }
+        if health == Some(Health::Unhealthy) {
+            if self.health != Some(Health::Unhealthy) {
+                self.previous_health = self.health;
+            }
+            self.unhealthy_streak = self.unhealthy_streak.saturating_add(1);
+        } else {
+            self.unhealthy_streak = 0;
+        }
+        if health != Some(Health::Starting) {
+            self.starting = None;
+        } else if self.health != Some(Health::Starting) {
+            self.starting = Some(Starting::Since(SystemTime::now()));
+        }
+        self.health = health;
+        self.updated = generation;
+        self.check_restarts();
+        true
+    }
 
-    /// RwLocked monitored and ignored containers
-    containers: Arc<RwLock<ManagedContainers>>,
+    /// Forget restarts outside of the restart window and check whether the
+    /// container exceeded the maximum number of restarts
+    fn check_restarts(&mut self) {
+        while self
+            .restarts
+            .front()
+            .is_some_and(|restart| restart.elapsed() >= RESTART_WINDOW)
+        {
+            self.restarts.pop_front();
+        }
+        self.restart_limit_exceeded = self
+            .max_restarts
+            .is_some_and(|max_restarts| self.restarts.len() > max_restarts);
+    }
 
-    /// Healthchecks.io interface
-    healthchecks: Healthchecks,
-}
+    /// Take over the observation history of an older version of this
+    /// container and record the health status of this version as a new
+    /// observation. If the health status of the older version has been
+    /// observed more recently (e.g. by an event that arrived while this
+    /// version was being fetched), it is kept instead.
+    fn inherit(&mut self, old: &Self) {
+        let health = self.health;
+        let updated = self.updated;
+        self.health = old.health;
+        self.updated = old.updated;
+        self.unhealthy_streak = old.unhealthy_streak;
+        self.previous_health = old.previous_health;
+        self.starting = old.starting;
+        self.probe_output.clone_from(&old.probe_output);
+        self.log_match.clone_from(&old.log_match);
+        self.resource_breach.clone_from(&old.resource_breach);
 
-impl ContainerManager {
-    /// Create a new container manager
-    pub fn new(docker: Docker, healthchecks: Healthchecks) -> Self {
-        Self {
-            docker,
-            containers: Arc::new(RwLock::new(ManagedContainers {
-                monitored_containers: HashMap::new(),
-                ignored_containers: HashSet::new(),
-            })),
-            healthchecks,
+        // record the restarts since the older version was inspected
+        self.restarts.clone_from(&old.restarts);
+        let now = Instant::now();
+        for _ in old.restart_count..self.restart_count {
+            self.restarts.push_back(now);
+        }
+
+        // the health status of probed containers is only known from the probes
+        if self.mode == MonitorMode::Probe || !self.observe(health, updated) {
+            self.check_restarts();
         }
     }
 
-    /// Ping the healthcheck urls of all monitored containers
-    pub async fn ping_healthchecks(&self) {
-        info!("pinging healthchecks");
-        join_all(
-            self.get_status_map()
-                .await
-                .iter()
-                .map(|(label, health)| async {
-                    if let Err(err) = self
-                        .healthchecks
-                        .ping(label, health)
-                        .await
-                        .context("failed to ping healthchecks")
-                    {
-                        error!("{err:#}");
-                    }
-                }),
-        )
-        .await;
+    /// Take over the restarts of an older version of this container that died
+    /// and count the start of this version as a restart
+    fn restarted(&mut self, mut restarts: VecDeque<Instant>) {
+        restarts.push_back(Instant::now());
+        self.restarts = restarts;
+        self.check_restarts();
     }
 
-    /// Reload all docker containers from the daemon
-    pub async fn fetch_containers(&self) -> Result<()> {
-        info!("fetching containers");
-        let mut containers = HashMap::new();
-        let mut ignored_containers = HashSet::new();
-        for summary in self
-            .docker
-            .containers()
-            .list(&ContainerListOpts::default())
-            .await
-            .context("failed to list containers")?
-        {
-            let id = summary
-                .id
-                .ok_or_else(|| anyhow!("container summary has no id"))?;
-            if let Some(container) = self
-                .fetch_container(&id)
-                .await
-                .context("failed to fetch container")?
-            {
-                containers.insert(id, container);
-            } else {
-                ignored_containers.insert(id);
-            }
+    /// Return the probe of the container, if it is running and probed
+    fn active_probe(&self) -> Option<&Arc<Probe>> {
+        self.probe.as_ref().filter(|_| self.running)
+    }
+
+    /// Return the log pattern of the container, if it is running and has one
+    fn active_log_pattern(&self) -> Option<&Arc<LogPattern>> {
+        self.log_pattern.as_ref().filter(|_| self.running)
+    }
+
+    /// Return the resource thresholds of the container, if it is running and
+    /// has any
+    fn active_resource_limits(&self) -> Option<&Arc<ResourceLimits>> {
+        self.resource_limits.as_ref().filter(|_| self.running)
+    }
+
+    /// Return a human-readable reference to the container
+    fn display_name(&self, id: &str) -> String {
+        match &self.name {
+            Some(name) => format!("{name} ({id})"),
+            None => id.to_owned(),
         }
-        let mut cont = self.containers.write().await;
-        cont.monitored_containers = containers;
-        cont.ignored_containers = ignored_containers;
-        Ok(())
     }
 
-    /// Handle container start events
-    pub async fn container_started(&self, id: String) -> Result<()> {
-        // ignore containers without healthchecks label
-        if self
-            .containers
-            .read()
-            .await
-            .ignored_containers
-            .contains(&id)
+    /// Describe the container for the body of healthcheck pings, followed by
+    /// the exceeded resource threshold, the log line that matched its log
+    /// pattern and the output of its last exec probe, indented
+    fn describe(&self, id: &str) -> String {
+        let mut description = format!(
+            "{}: {:?} (image: {}, restarts: {}, failing streak: {})",
+            self.display_name(id),
+            self.effective_health(),
+            self.image.as_deref().unwrap_or("unknown"),
+            self.restart_count,
+            self.failing_streak
+        );
+        if let Some(reason) = &self.resource_breach {
+            description.push_str("\n    resources: ");
+            description.push_str(reason);
+        }
+        if let Some((_, line)) = &self.log_match {
+            description.push_str("\n    log: ");
+            description.push_str(line);
+        }
+        for line in self.probe_output.iter().flat_map(|output| output.lines()) {
+            description.push_str("\n    ");
+            description.push_str(line);
+        }
+        description
+    }
+
+    /// Return the health status that is reported for this container. An
+    /// unhealthy status is only reported after `flap_threshold` consecutive
+    /// unhealthy observations and `min_failing_streak` consecutive failed
+    /// healthchecks, until then the previous status is reported.
+    /// Containers that are restarting, restarted too often, logged a line
+    /// matching their log pattern or exceed their resource thresholds are
+    /// always reported as unhealthy, containers that have been starting for
+    /// too long are reported as healthy.
+    fn effective_health(&self) -> Health {
+        if self.restarting
+            || self.restart_limit_exceeded
+            || self.log_match.is_some()
+            || self.resource_breach.is_some()
         {
-            return Ok(());
+            return Health::Unhealthy;
+        }
+        if self.starting == Some(Starting::Expired) {
+            return Health::Healthy;
         }
+        let health = if self.health == Some(Health::Unhealthy)
+            && (self.unhealthy_streak < self.flap_threshold
+                || self.failing_streak < self.min_failing_streak)
+        {
+            self.previous_health
+        } else {
+            self.health
+        };
+        health.unwrap_or(Health::Healthy)
+    }
+}
 
-        // try to get information about the new container
-        if let Some(container) = self.fetch_container(&id).await? {
-            // add the container to the collection of monitored containers
-            let label = container.ping_url.clone();
-            self.containers
-                .write()
-                .await
-                .monitored_containers
-                .insert(id, container);
+/// Number of containers (or swarm services) per health status
+#[derive(Debug, Default, Clone, Copy)]
+struct HealthCounts {
+    /// Number of healthy containers
+    healthy: usize,
 
-            // send a ping to the corresponding ping url
-            self.ping_one(&label).await?;
-        } else {
-            // ignore the container if it has no healthchecks label
-            self.containers.write().await.ignored_containers.insert(id);
+    /// Number of unhealthy containers
+    unhealthy: usize,
+
+    /// Number of starting containers
+    starting: usize,
+}
+
+impl HealthCounts {
+    /// Return a mutable reference to the counter of the given health status
+    fn counter(&mut self, health: Health) -> &mut usize {
+        match health {
+            Health::Healthy => &mut self.healthy,
+            Health::Unhealthy => &mut self.unhealthy,
+            Health::Starting => &mut self.starting,
         }
+    }
 
-        Ok(())
+    /// Return the total number of containers
+    fn total(&self) -> usize {
+        self.healthy + self.unhealthy + self.starting
     }
 
-    /// Handle container die events
-    pub async fn container_died(&self, id: &String) -> Result<()> {
-        // ignore containers without healthchecks label and remove them from the set of
-        // ignored containers
-        if self.containers.write().await.ignored_containers.remove(id) {
-            return Ok(());
+    /// Describe the number of containers per health status, e.g. `3/5
+    /// healthy, 1 starting, 1 unhealthy`
+    fn summary(&self) -> String {
+        format!(
+            "{}/{} healthy, {} starting, {} unhealthy",
+            self.healthy,
+            self.total(),
+            self.starting,
+            self.unhealthy
+        )
+    }
+
+    /// Return the health statuses with a non-zero count
+    fn present(&self) -> impl Iterator<Item = Health> {
+        [
+            (Health::Healthy, self.healthy),
+            (Health::Unhealthy, self.unhealthy),
+            (Health::Starting, self.starting),
+        ]
+        .into_iter()
+        .filter(|&(_, count)| count > 0)
+        .map(|(health, _)| health)
+    }
+
+    /// Return the 'worst' health status
+    fn worst(&self, order: SeverityOrder) -> Option<Health> {
+        self.present().max_by_key(|health| health.severity(order))
+    }
+
+    /// Return the 'best' health status
+    fn best(&self, order: SeverityOrder) -> Option<Health> {
+        self.present().min_by_key(|health| health.severity(order))
+    }
+}
+
+/// Stores monitored and ignored containers
+struct ManagedContainers {
+    /// Mapping from container id to container data for monitored containers.
+    /// Must only be modified using the methods below to keep the url index
+    /// consistent.
+    monitored_containers: HashMap<Arc<str>, Container>,
+
+    /// Set of containers without healthchecks label. These containers can be
+    /// safely ignored, as it is not possible to add labels to running
+    /// containers.
+    ignored_containers: HashSet<Arc<str>>,
+
+    /// Mapping from ids of recently died containers to the time of their
+    /// death
+    tombstones: HashMap<Arc<str>, Instant>,
+
+    /// Mapping from ids of died containers to the time of their death and
+    /// their restarts within the restart window, so that the restarts are
+    /// still counted when the container is started again
+    restart_history: HashMap<Arc<str>, (Instant, VecDeque<Instant>)>,
+
+    /// Mapping from ping urls to the health statuses of the containers (or
+    /// swarm services) with this ping url
+    url_index: HashMap<Arc<str>, HealthCounts>,
+
+    /// Mapping from ping urls and swarm service names to the health statuses
+    /// of the tasks of the service
+    service_index: HashMap<(Arc<str>, Arc<str>), HealthCounts>,
+
+    /// Order of the health statuses used for aggregation
+    severity_order: SeverityOrder,
+
+    /// Time of the last successful container fetch
+    last_fetch: Option<SystemTime>,
+
+    /// Mapping from container ids to their most recent health transitions
+    history: HashMap<Arc<str>, VecDeque<Transition>>,
+
+    /// Maximum number of health transitions stored per container
+    history_size: usize,
+
+    /// Name of the docker host, if multiple hosts are monitored
+    host: Option<Arc<str>>,
+}
+
+impl ManagedContainers {
+    /// Create an empty collection of containers
+    fn new(severity_order: SeverityOrder, history_size: usize, host: Option<Arc<str>>) -> Self {
+        Self {
+            monitored_containers: HashMap::new(),
+            ignored_containers: HashSet::new(),
+            tombstones: HashMap::new(),
+            restart_history: HashMap::new(),
+            url_index: HashMap::new(),
+            service_index: HashMap::new(),
+            severity_order,
+            last_fetch: None,
+            history: HashMap::new(),
+            history_size,
+            host,
         }
+    }
 
-        // remove the container from the collection of monitored containers
-        let mut containers = self.containers.write().await;
-        if let Some(container) = containers.monitored_containers.remove(id) {
-            drop(containers);
+    /// Add a monitored container, replacing any previous container with the
+    /// same id
+    fn insert(&mut self, id: Arc<str>, container: Container) {
+        self.remove(&id);
+        self.index_add(&container);
+        self.monitored_containers.insert(id, container);
+    }
 
-            // send an unhealthy ping to the corresponding ping url,
-            // if this was the last container with this ping url
-            if !self
-                .get_status_map()
-                .await
-                .contains_key(&container.ping_url)
-            {
-                self.healthchecks
-                    .ping(&container.ping_url, &Health::Unhealthy)
-                    .await?;
+    /// Remove a monitored container
+    fn remove(&mut self, id: &str) -> Option<Container> {
+        let container = self.monitored_containers.remove(id)?;
+        self.index_remove(&container);
+        Some(container)
+    }
+
+    /// Modify a monitored container. Returns `None` if the container is not
+    /// monitored.
+    fn update<T>(&mut self, id: &str, f: impl FnOnce(&mut Container) -> T) -> Option<T> {
+        // keep the interned id, and reindex the container as its urls might change
+        let (id, mut container) = self.monitored_containers.remove_entry(id)?;
+        self.index_remove(&container);
+        let result = f(&mut container);
+        self.index_add(&container);
+        self.monitored_containers.insert(id, container);
+        Some(result)
+    }
+
+    /// Replace all monitored containers. The health transitions of containers
+    /// that are neither monitored nor recently died are forgotten.
+    fn replace(&mut self, containers: HashMap<Arc<str>, Container>) {
+        self.history
+            .retain(|id, _| containers.contains_key(id) || self.tombstones.contains_key(id));
+        self.url_index.clear();
+        self.service_index.clear();
+        for container in containers.values() {
+            self.index_add(container);
+        }
+        self.monitored_containers = containers;
+    }
+
+    /// Record a health transition of a container. Changes between two health
+    /// statuses are logged, while containers appearing and disappearing are
+    /// logged by the event handler.
+    fn record(
+        &mut self,
+        id: &Arc<str>,
+        old: Option<Health>,
+        new: Option<Health>,
+        source: &'static str,
+    ) {
+        if let (Some(from), Some(to)) = (old, new) {
+            if from != to {
+                let name = self
+                    .monitored_containers
+                    .get(id)
+                    .and_then(|container| container.name.as_deref());
+                info!(
+                    container.id = short_id(id),
+                    container.name = name,
+                    from = ?from,
+                    to = ?to,
+                    source,
+                    "health status changed"
+                );
             }
         }
-        Ok(())
+        if self.history_size == 0 {
+            return;
+        }
+        let history = self.history.entry(id.clone()).or_default();
+        if history.len() >= self.history_size {
+            history.pop_front();
+        }
+        history.push_back(Transition {
+            time: state::unix_time(),
+            old,
+            new,
+            source,
+        });
     }
 
-    /// Handle container health update events
-    pub async fn container_health_update(&self, id: String, health: Health) -> Result<()> {
-        // ignore containers without healthchecks label
-        if self
-            .containers
-            .read()
-            .await
-            .ignored_containers
-            .contains(&id)
-        {
-            return Ok(());
+    /// Report the containers that have been starting for at least the given
+    /// duration as healthy
+    fn expire_starting(&mut self, after: Duration) {
+        let expired: Vec<_> = self
+            .monitored_containers
+            .iter()
+            .filter(|(_, container)| {
+                let Some(Starting::Since(since)) = container.starting else {
+                    return false;
+                };
+                since.elapsed().is_ok_and(|elapsed| elapsed >= after)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            let updated = self.update(&id, |container| {
+                container.starting = Some(Starting::Expired);
+            });
+            if updated.is_none() {
+                continue;
+            }
+            self.record(
+                &id,
+                Some(Health::Starting),
+                Some(Health::Healthy),
+                "starting-timeout",
+            );
         }
+    }
 
-        // try to find the container in the collection of monitored containers,
-        // otherwise fetch its data from the docker daemon
-        let mut containers = self.containers.write().await;
-        let label = if let Some(container) = containers.monitored_containers.get_mut(&id) {
-            // update the health status
-            container.health = Some(health);
-            container.ping_url.clone()
-        } else if let Some(container) = self.fetch_container(&id).await? {
-            // add the container to the collection of monitored containers
-            let label = container.ping_url.clone();
-            containers.monitored_containers.insert(id, container);
-            label
-        } else {
-            // ignore the container if it has no healthchecks label
-            containers.ignored_containers.insert(id);
-            return Ok(());
-        };
-        drop(containers);
+    /// Forget the log matches of containers whose cool-down has elapsed, so
+    /// that their underlying health status is reported again
+    fn expire_log_matches(&mut self) {
+        let expired: Vec<_> = self
+            .monitored_containers
+            .iter()
+            .filter(|(_, container)| {
+                let Some((matched, _)) = container.log_match else {
+                    return false;
+                };
+                container
+                    .log_pattern
+                    .as_ref()
+                    .map_or(true, |pattern| matched.elapsed() >= pattern.cooldown)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            let health = self.update(&id, |container| {
+                container.log_match = None;
+                container.effective_health()
+            });
+            self.record(&id, Some(Health::Unhealthy), health, "log-pattern-cooldown");
+        }
+    }
 
-        // send a ping to the corresponding ping url
-        self.ping_one(&label).await
-    }
-
-    /// Return a mapping from ping urls to their current health status
-    /// If there are multiple containers with the same ping url,
-    /// the 'worst' health status is used.
-    async fn get_status_map(&self) -> HashMap<String, Health> {
-        let mut status = HashMap::new();
-        for container in self.containers.read().await.monitored_containers.values() {
-            let health: Health = container.health.unwrap_or(Health::Healthy);
-            if let Some(h) = status.get_mut(&container.ping_url) {
-                // another container with the same ping url already exists
-                // update the health status if the health status of the current containers is
-                // 'worse'
-                if health > *h {
-                    *h = health;
+    /// Return the number of transitions of a container to unhealthy within
+    /// the flap window
+    fn flaps(&self, id: &str) -> usize {
+        let since = state::unix_time().saturating_sub(FLAP_WINDOW.as_secs());
+        self.history.get(id).map_or(0, |history| {
+            history
+                .iter()
+                .filter(|transition| {
+                    transition.time >= since && transition.new == Some(Health::Unhealthy)
+                })
+                .count()
+        })
+    }
+
+    /// Add the health status of a container to the url index
+    fn index_add(&mut self, container: &Container) {
+        self.index_modify(container, |counts, health| *counts.counter(health) += 1);
+    }
+
+    /// Remove the health status of a container from the url index
+    fn index_remove(&mut self, container: &Container) {
+        self.index_modify(container, |counts, health| *counts.counter(health) -= 1);
+    }
+
+    /// Apply a modification of the health counts of a container to the url
+    /// index. If there are multiple containers with the same ping url, the
+    /// 'worst' health status is used. Tasks of the same swarm service are
+    /// aggregated first using the 'best' health status, so that a service is
+    /// healthy as long as it has at least one healthy task.
+    fn index_modify(&mut self, container: &Container, f: impl Fn(&mut HealthCounts, Health)) {
+        let health = container.effective_health();
+        let url = &container.ping_url;
+        let counts = match &container.service {
+            None => {
+                let counts = self.url_index.entry(url.clone()).or_default();
+                f(counts, health);
+                *counts
+            }
+            Some(service) => {
+                let key = (url.clone(), service.clone());
+                let service_counts = self.service_index.entry(key.clone()).or_default();
+                let before = service_counts.best(self.severity_order);
+                f(service_counts, health);
+                let after = service_counts.best(self.severity_order);
+                if service_counts.total() == 0 {
+                    self.service_index.remove(&key);
                 }
-            } else {
-                // this is the first container with this ping url
-                status.insert(container.ping_url.clone(), health);
+
+                let counts = self.url_index.entry(url.clone()).or_default();
+                if before != after {
+                    if let Some(before) = before {
+                        *counts.counter(before) -= 1;
+                    }
+                    if let Some(after) = after {
+                        *counts.counter(after) += 1;
+                    }
+                }
+                *counts
             }
+        };
+        if counts.total() == 0 {
+            self.url_index.remove(url);
         }
-        status
     }
 
-    /// Ping one url
-    async fn ping_one(&self, ping_url: &String) -> Result<()> {
-        let health = self
-            .get_status_map()
-            .await
-            .remove(ping_url)
-            .unwrap_or(Health::Unhealthy);
-        self.healthchecks.ping(ping_url, &health).await
+    /// Return the aggregated health status of a ping url
+    fn status(&self, ping_url: &str) -> Option<Health> {
+        self.url_index
+            .get(ping_url)
+            .and_then(|counts| counts.worst(self.severity_order))
     }
 
-    /// Fetch information about a container from the docker daemon.
-    /// Returns `None` if the container has no `healthchecks.url` label.
-    async fn fetch_container(&self, id: &str) -> Result<Option<Container>> {
-        let data = self
-            .docker
-            .containers()
-            .get(id)
-            .inspect()
-            .await
-            .with_context(|| format!("failed to inspect container {id}"))?;
+    /// Return the ping urls with their aggregated health status and a
+    /// description of the corresponding containers
+    fn pings(&self) -> Vec<(Arc<str>, Health, String)> {
+        let mut lines = HashMap::<_, Vec<_>>::new();
+        for (id, container) in &self.monitored_containers {
+            lines
+                .entry(&container.ping_url)
+                .or_default()
+                .push(container.describe(id));
+        }
+        self.url_index
+            .iter()
+            .filter_map(|(url, counts)| {
+                let mut body = lines.remove(url).unwrap_or_default();
+                body.sort_unstable();
+                body.insert(0, self.body_header(counts));
+                Some((
+                    url.clone(),
+                    counts.worst(self.severity_order)?,
+                    body.join("\n"),
+                ))
+            })
+            .collect()
+    }
 
-        if let Some(label) = get_label(&data).context("failed to get label of container")? {
-            Ok(Some(Container {
-                ping_url: label,
-                health: get_health(&data).context("failed to get health status of container")?,
-            }))
-        } else {
-            Ok(None)
+    /// Return a description of the containers with the given ping url
+    fn body(&self, ping_url: &str) -> String {
+        let mut body: Vec<_> = self
+            .monitored_containers
+            .iter()
+            .filter(|(_, container)| &*container.ping_url == ping_url)
+            .map(|(id, container)| container.describe(id))
+            .collect();
+        body.sort_unstable();
+        let counts = self.url_index.get(ping_url).copied().unwrap_or_default();
+        body.insert(0, self.body_header(&counts));
+        body.join("\n")
+    }
+
+    /// Return the first line of the body of healthcheck pings, prefixed with
+    /// the name of the docker host
+    fn body_header(&self, counts: &HealthCounts) -> String {
+        match &self.host {
+            Some(host) => format!("{host}: {}", counts.summary()),
+            None => counts.summary(),
         }
     }
 }
 
-/// Extract the health status from a container inspect response
-fn get_health(data: &ContainerInspect200Response) -> Result<Option<Health>> {
-    let status = data
-        .state
-        .as_ref()
-        .ok_or_else(|| anyhow!("container inspect state object is empty"))?
-        .health
-        .as_ref()
-        .and_then(|health| health.status.as_ref())
-        .map(std::string::String::as_str);
+/// Manager for monitored docker containers
+pub struct ContainerManager {
+    /// Container runtime from which the containers are loaded
+    runtime: Arc<dyn ContainerRuntime>,
 
-    Ok(match status {
-        None | Some("none") => None,
-        Some("starting") => Some(Health::Starting),
-        Some("healthy") => Some(Health::Healthy),
-        Some("unhealthy") => Some(Health::Unhealthy),
-        Some(status) => bail!("invalid health status: {status}"),
-    })
-}
+    /// `RwLock`ed monitored and ignored containers
+    containers: Arc<RwLock<ManagedContainers>>,
 
-/// Extract the `healthchecks.url` label from a container inspect response
-fn get_label(data: &ContainerInspect200Response) -> Result<Option<String>> {
-    let labels = data
-        .config
-        .as_ref()
-        .ok_or_else(|| anyhow!("container inspect config object is empty"))?
-        .labels
-        .as_ref()
-        .ok_or_else(|| anyhow!("container inspect config labels object is empty"))?;
-    Ok(labels.get("healthchecks.url").cloned())
+    /// Healthchecks.io interface
+    healthchecks: Arc<Healthchecks>,
+
+    /// Mapping from ping urls to tasks that send a delayed unhealthy ping
+    pending_fails: Arc<Mutex<HashMap<Arc<str>, JoinHandle<()>>>>,
+
+    /// Whether containers should be grouped by their swarm service
+    swarm_mode: bool,
+
+    /// Whether the health of swarm services is computed across the cluster
+    /// instead of from the local tasks
+    swarm_aggregation: bool,
+
+    /// Ping urls of the swarm services pinged by the last aggregation
+    service_urls: std::sync::Mutex<Vec<Arc<str>>>,
+
+    /// Whether the lost docker daemon has been reported to all ping urls
+    /// since the last successful fetch
+    docker_lost_reported: AtomicBool,
+
+    /// Settings that can be changed at runtime
+    settings: std::sync::RwLock<Settings>,
+
+    /// Whether the docker daemon is reachable
+    reachability: Reachability,
+
+    /// Held while all containers are being fetched
+    fetching: Mutex<()>,
+
+    /// Mapping from ids of probed containers to their probe and the task
+    /// probing them
+    probes: RunningTasks<Probe>,
+
+    /// Resources shared by the probes
+    probe_context: ProbeContext,
+
+    /// Receiver of the results of the probes, until it is taken by the event
+    /// handler
+    probe_receiver: std::sync::Mutex<Option<UnboundedReceiver<ProbeResult>>>,
+
+    /// Mapping from ids of containers with a log pattern to their pattern and
+    /// the task following their logs
+    log_followers: RunningTasks<LogPattern>,
+
+    /// Sender of the log lines that matched the log pattern of their
+    /// container
+    log_matches: Sender<LogMatch>,
+
+    /// Receiver of the log lines that matched the log pattern of their
+    /// container, until it is taken by the event handler
+    log_match_receiver: std::sync::Mutex<Option<Receiver<LogMatch>>>,
+
+    /// Mapping from ids of containers with resource thresholds to their
+    /// thresholds and the task sampling their docker stats
+    stats_samplers: RunningTasks<ResourceLimits>,
+
+    /// Interval in which the docker stats of the containers are sampled
+    stats_interval: Duration,
+
+    /// Sender of the changes of resource threshold breaches
+    resource_breaches: UnboundedSender<ResourceBreach>,
+
+    /// Receiver of the changes of resource threshold breaches, until it is
+    /// taken by the event handler
+    resource_breach_receiver: std::sync::Mutex<Option<UnboundedReceiver<ResourceBreach>>>,
+}
+
+/// Settings of the container manager that can be changed at runtime
+#[derive(Clone, Copy)]
+struct Settings {
+    /// Default number of consecutive unhealthy observations required before
+    /// a container is reported as unhealthy
+    flap_threshold: u32,
+
+    /// Delay before an unhealthy ping is sent after the last container with a
+    /// ping url died
+    die_grace: Duration,
+
+    /// Time window across which the periodic pings are spread
+    ping_spread: Duration,
+
+    /// Maximum number of containers that are inspected concurrently during a
+    /// full fetch
+    fetch_concurrency: usize,
+
+    /// Default number of consecutive failed healthchecks required before a
+    /// container is reported as unhealthy
+    min_failing_streak: usize,
+
+    /// Whether restarting containers are reported as unhealthy
+    restarting_unhealthy: bool,
+
+    /// Maximum number of restarts per hour before a container is reported as
+    /// unhealthy
+    max_restarts_per_hour: Option<usize>,
+
+    /// Number of retries for failed container inspect requests
+    inspect_retries: u8,
+
+    /// Duration after which a container that is continuously starting is
+    /// reported as healthy
+    starting_as_healthy_after: Option<Duration>,
+
+    /// Delay before the first retry of a failed container inspect request
+    inspect_retry_delay: Duration,
+
+    /// Whether the full container list is reloaded periodically
+    periodic_fetch: bool,
+
+    /// Duration after which an unreachable docker daemon is reported
+    docker_down_threshold: Duration,
+
+    /// What happens to the pings while the docker daemon is unreachable
+    on_docker_lost: DockerLostAction,
+
+    /// Whether the ping urls of the containers are not pinged, as only a
+    /// summary is sent
+    summary_only: bool,
+}
+
+impl From<&Config> for Settings {
+    fn from(config: &Config) -> Self {
+        Self {
+            flap_threshold: config.flap_threshold,
+            die_grace: config.die_grace,
+            ping_spread: config.ping_spread,
+            fetch_concurrency: config.fetch_concurrency,
+            min_failing_streak: config.min_failing_streak,
+            restarting_unhealthy: config.restarting_unhealthy,
+            max_restarts_per_hour: config.max_restarts_per_hour,
+            inspect_retries: config.inspect_retries,
+            inspect_retry_delay: config.inspect_retry_delay,
+            periodic_fetch: !config.fetch_interval.is_zero(),
+            starting_as_healthy_after: config.starting_as_healthy_after,
+            docker_down_threshold: config.docker_down_threshold,
+            on_docker_lost: config.on_docker_lost,
+            summary_only: config.summary_only,
+        }
+    }
+}
+
+impl Drop for ContainerManager {
+    fn drop(&mut self) {
+        // stop probing the containers, following their logs and sampling their
+        // stats
+        let probes = self
+            .probes
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, (_, handle)) in probes.drain() {
+            handle.abort();
+        }
+        let log_followers = self
+            .log_followers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, (_, handle)) in log_followers.drain() {
+            handle.abort();
+        }
+        let stats_samplers = self
+            .stats_samplers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, (_, handle)) in stats_samplers.drain() {
+            handle.abort();
+        }
+    }
+}
+
+impl ContainerManager {
+    /// Create a new container manager
+    pub fn new(
+        runtime: impl ContainerRuntime + 'static,
+        healthchecks: Arc<Healthchecks>,
+        config: &Config,
+        host: Option<&str>,
+    ) -> Self {
+        let runtime: Arc<dyn ContainerRuntime> = Arc::new(runtime);
+        let (probe_results, probe_receiver) = unbounded_channel();
+        let probe_context = ProbeContext {
+            client: Client::new(),
+            runtime: runtime.clone(),
+            exec_permits: Arc::new(Semaphore::new(config.exec_probe_concurrency)),
+            results: probe_results,
+        };
+        let (log_matches, log_match_receiver) = channel(log_pattern::MATCH_BUFFER_SIZE);
+        let (resource_breaches, resource_breach_receiver) = unbounded_channel();
+        Self {
+            runtime,
+            containers: Arc::new(RwLock::new(ManagedContainers::new(
+                config.severity_order,
+                config.health_history_size,
+                host.map(Arc::from),
+            ))),
+            healthchecks,
+            pending_fails: Arc::new(Mutex::new(HashMap::new())),
+            swarm_mode: config.swarm_mode,
+            swarm_aggregation: config.swarm_aggregation,
+            service_urls: std::sync::Mutex::new(Vec::new()),
+            docker_lost_reported: AtomicBool::new(false),
+            settings: std::sync::RwLock::new(config.into()),
+            reachability: Reachability::new(),
+            fetching: Mutex::new(()),
+            probes: std::sync::Mutex::new(HashMap::new()),
+            probe_context,
+            probe_receiver: std::sync::Mutex::new(Some(probe_receiver)),
+            log_followers: std::sync::Mutex::new(HashMap::new()),
+            log_matches,
+            log_match_receiver: std::sync::Mutex::new(Some(log_match_receiver)),
+            stats_samplers: std::sync::Mutex::new(HashMap::new()),
+            stats_interval: config.stats_interval,
+            resource_breaches,
+            resource_breach_receiver: std::sync::Mutex::new(Some(resource_breach_receiver)),
+        }
+    }
+
+    /// Cancel all delayed unhealthy pings. The tasks sending them hold a
+    /// reference to the registry of pending pings, so they have to be aborted
+    /// explicitly.
+    pub async fn shutdown(&self) {
+        for (url, handle) in self.pending_fails.lock().await.drain() {
+            debug!(url = %redact_url(&url), "cancelling delayed unhealthy ping");
+            handle.abort();
+        }
+    }
+
+    /// Apply the settings of the given configuration that can be changed at
+    /// runtime. Per-container defaults are applied on the next fetch.
+    pub fn reconfigure(&self, config: &Config) {
+        *self
+            .settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = config.into();
+    }
+
+    /// Return the current settings
+    fn settings(&self) -> Settings {
+        *self.settings.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Take the receiver of the results of the probes. The results are
+    /// only received once.
+    pub fn take_probe_results(&self) -> Option<UnboundedReceiver<ProbeResult>> {
+        self.probe_receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Store the output of the last exec probe of a container, which is
+    /// included in the body of healthcheck pings
+    pub async fn set_probe_output(&self, id: &str, output: Option<String>) {
+        if let Some(container) = self
+            .containers
+            .write()
+            .await
+            .monitored_containers
+            .get_mut(id)
+        {
+            container.probe_output = output;
+        }
+    }
+
+    /// Take the receiver of the log lines that matched the log pattern of
+    /// their container. The matches are only received once.
+    pub fn take_log_matches(&self) -> Option<Receiver<LogMatch>> {
+        self.log_match_receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Mark a container as unhealthy, because a line of its logs matched its
+    /// log pattern, and ping its url
+    pub async fn log_pattern_matched(&self, id: &str, line: String) -> Result<()> {
+        let mut containers = write_containers(&self.containers).await;
+        let Some((label, old)) = containers.update(id, |container| {
+            let old = container.effective_health();
+            container.log_match = Some((Instant::now(), line));
+            (container.ping_url.clone(), old)
+        }) else {
+            return Ok(());
+        };
+        containers.record(
+            &id.into(),
+            Some(old),
+            Some(Health::Unhealthy),
+            "log-pattern",
+        );
+        drop(containers);
+        self.ping_one(&label, false).await?;
+        Ok(())
+    }
+
+    /// Take the receiver of the changes of resource threshold breaches. The
+    /// changes are only received once.
+    pub fn take_resource_breaches(&self) -> Option<UnboundedReceiver<ResourceBreach>> {
+        self.resource_breach_receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Mark a container as unhealthy while it exceeds its resource
+    /// thresholds, and ping its url when this changes
+    pub async fn resource_breach_changed(&self, id: &str, reason: Option<String>) -> Result<()> {
+        let mut containers = write_containers(&self.containers).await;
+        let Some((label, old, new)) = containers.update(id, |container| {
+            let old = container.effective_health();
+            container.resource_breach = reason;
+            (
+                container.ping_url.clone(),
+                old,
+                container.effective_health(),
+            )
+        }) else {
+            return Ok(());
+        };
+        containers.record(&id.into(), Some(old), Some(new), "resource-threshold");
+        drop(containers);
+        self.ping_one(&label, false).await?;
+        Ok(())
+    }
+
+    /// Start probing the running containers that have a probe, following the
+    /// logs of those with a log pattern and sampling the stats of those with
+    /// resource thresholds, and stop the tasks of containers that died or are
+    /// no longer monitored
+    fn sync_probes(&self, containers: &ManagedContainers) {
+        sync_tasks(
+            &self.probes,
+            containers,
+            Container::active_probe,
+            |probe, id, container| {
+                probe::spawn_probe(
+                    probe.clone(),
+                    self.probe_context.clone(),
+                    id.clone(),
+                    container.name.clone(),
+                )
+            },
+        );
+        sync_tasks(
+            &self.log_followers,
+            containers,
+            Container::active_log_pattern,
+            |pattern, id, container| {
+                log_pattern::spawn_log_follower(
+                    pattern.clone(),
+                    self.runtime.clone(),
+                    id.clone(),
+                    container.name.as_deref(),
+                    self.log_matches.clone(),
+                )
+            },
+        );
+        sync_tasks(
+            &self.stats_samplers,
+            containers,
+            Container::active_resource_limits,
+            |limits, id, container| {
+                stats::spawn_stats_sampler(
+                    limits.clone(),
+                    self.runtime.clone(),
+                    self.stats_interval,
+                    id.clone(),
+                    container.name.as_deref(),
+                    self.resource_breaches.clone(),
+                )
+            },
+        );
+    }
+
+    /// Return the reachability of the docker daemon
+    pub fn reachability(&self) -> &Reachability {
+        &self.reachability
+    }
+
+    /// Return for how long the docker daemon has been unreachable, if this
+    /// exceeds the configured threshold
+    pub fn docker_down(&self) -> Option<Duration> {
+        self.reachability
+            .down_for()
+            .filter(|&down| down >= self.settings().docker_down_threshold)
+    }
+
+    /// Ping the docker daemon to check whether it is reachable
+    pub async fn ping_docker(&self, ping_timeout: Duration) {
+        match timeout(ping_timeout, self.runtime.ping()).await {
+            Ok(Ok(_)) => self.reachability.succeeded(),
+            Ok(Err(err)) => {
+                debug!(error = %err, "could not ping docker daemon");
+                self.reachability.failed();
+            }
+            Err(_) => {
+                debug!("docker daemon ping timed out");
+                self.reachability.failed();
+            }
+        }
+    }
+
+    /// Ping the healthcheck urls of all monitored containers. Keep-alive pings
+    /// are sent even if the health status has not changed. The pings are
+    /// spread across the configured time window using a stable offset for
+    /// each url. Return the number of failed pings.
+    pub async fn ping_healthchecks(&self, keepalive: bool) -> usize {
+        info!("pinging healthchecks");
+        let settings = self.settings();
+        let (mut pings, order) = {
+            let mut containers = write_containers(&self.containers).await;
+            if let Some(after) = settings.starting_as_healthy_after {
+                containers.expire_starting(after);
+            }
+            containers.expire_log_matches();
+            (containers.pings(), containers.severity_order)
+        };
+        let generation = self.healthchecks.next_generation();
+        let mut failed = 0;
+        if self.swarm_aggregation {
+            match self.service_pings().await {
+                Ok(service_pings) => merge_pings(&mut pings, service_pings, order),
+                Err(err) => {
+                    error!(
+                        error = %format_args!("{err:#}"),
+                        "failed to aggregate swarm services"
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        let Some(docker_lost) = self.docker_lost_pings(settings.on_docker_lost, &mut pings) else {
+            return failed;
+        };
+        if settings.summary_only {
+            return failed;
+        }
+        let results = join_all(pings.iter().map(|(label, health, body)| async move {
+            sleep(self.ping_offset(label)).await;
+            let result = if keepalive {
+                self.healthchecks
+                    .keepalive(label, health, body, generation)
+                    .await
+            } else {
+                self.healthchecks
+                    .ping(label, health, body, generation)
+                    .await
+            };
+            if let Err(err) = result {
+                error!(
+                    url = %redact_url(label),
+                    error = %format_args!("{err:#}"),
+                    "failed to ping healthchecks"
+                );
+                return false;
+            }
+            true
+        }))
+        .await;
+        failed += results.into_iter().filter(|&success| !success).count();
+        if docker_lost && failed == 0 {
+            self.docker_lost_reported.store(true, Ordering::Relaxed);
+        }
+        failed
+    }
+
+    /// Apply the configured action to the pings while the docker daemon is
+    /// unreachable, as the health of the containers is unknown. Return `None`
+    /// if no pings should be sent, otherwise whether the pings report the
+    /// lost docker daemon.
+    fn docker_lost_pings(
+        &self,
+        action: DockerLostAction,
+        pings: &mut [(Arc<str>, Health, String)],
+    ) -> Option<bool> {
+        match action {
+            DockerLostAction::Keep => Some(false),
+            DockerLostAction::Stop => {
+                if self.reachability.down_for().is_some() {
+                    info!("docker daemon unreachable, suspending pings");
+                    return None;
+                }
+                Some(false)
+            }
+            DockerLostAction::Fail => {
+                if self.docker_lost_reported.load(Ordering::Relaxed) {
+                    info!("docker daemon lost, suspending pings until the next fetch");
+                    return None;
+                }
+                let Some(down) = self.docker_down() else {
+                    return Some(false);
+                };
+                let body = format!(
+                    "docker daemon unreachable for {}",
+                    humantime::format_duration(down)
+                );
+                for (_, health, ping_body) in pings {
+                    *health = Health::Unhealthy;
+                    ping_body.clone_from(&body);
+                }
+                Some(true)
+            }
+        }
+    }
+
+    /// Compute the health of the swarm services across the cluster and return
+    /// the pings of their urls. Return no pings if this instance is not the
+    /// leading manager, so that each service is pinged by exactly one
+    /// instance.
+    async fn service_pings(&self) -> Result<Vec<(Arc<str>, Health, String)>> {
+        if !swarm::is_leader(&*self.runtime).await? {
+            debug!("not the leading swarm manager, skipping service aggregation");
+            self.service_urls
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clear();
+            return Ok(Vec::new());
+        }
+        let mut services = HashMap::<Arc<str>, (HealthCounts, Vec<String>)>::new();
+        for service in swarm::service_health(&*self.runtime).await? {
+            let (counts, lines) = services.entry(service.url.into()).or_default();
+            *counts.counter(service.health) += 1;
+            lines.push(format!(
+                "service {}: {}/{} tasks running",
+                service.name, service.running, service.desired
+            ));
+        }
+        *self
+            .service_urls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = services.keys().cloned().collect();
+        let containers = read_containers(&self.containers).await;
+        Ok(services
+            .into_iter()
+            .filter_map(|(url, (counts, mut lines))| {
+                lines.sort_unstable();
+                lines.insert(0, containers.body_header(&counts));
+                Some((
+                    url,
+                    counts.worst(containers.severity_order)?,
+                    lines.join("\n"),
+                ))
+            })
+            .collect())
+    }
+
+    /// Return the number of monitored and ignored containers
+    pub async fn counts(&self) -> (usize, usize) {
+        let containers = read_containers(&self.containers).await;
+        (
+            containers.monitored_containers.len(),
+            containers.ignored_containers.len(),
+        )
+    }
+
+    /// Return the ping urls of all monitored containers and aggregated swarm
+    /// services
+    pub async fn ping_urls(&self) -> Vec<Arc<str>> {
+        let mut urls: Vec<_> = read_containers(&self.containers)
+            .await
+            .url_index
+            .keys()
+            .cloned()
+            .collect();
+        urls.extend(
+            self.service_urls
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .cloned(),
+        );
+        urls
+    }
+
+    /// Return the given ping url if it belongs to a monitored container
+    pub async fn monitored_url(&self, url: &str) -> Option<Arc<str>> {
+        read_containers(&self.containers)
+            .await
+            .url_index
+            .get_key_value(url)
+            .map(|(url, _)| url.clone())
+    }
+
+    /// Return the ping url of the monitored container with the given name or
+    /// (abbreviated) id
+    pub async fn container_url(&self, container: &str) -> Option<Arc<str>> {
+        read_containers(&self.containers)
+            .await
+            .monitored_containers
+            .iter()
+            .find(|(id, data)| {
+                data.name.as_deref() == Some(container)
+                    || (container.len() >= SHORT_ID_LENGTH && id.starts_with(container))
+            })
+            .map(|(_, data)| data.ping_url.clone())
+    }
+
+    /// Return the time of the last successful container fetch
+    pub async fn last_fetch(&self) -> Option<SystemTime> {
+        read_containers(&self.containers).await.last_fetch
+    }
+
+    /// Take a snapshot of the internal state. The containers are only locked
+    /// while they are copied.
+    pub async fn dump(&self) -> Dump {
+        let containers = read_containers(&self.containers).await;
+        let mut monitored: Vec<_> = containers
+            .monitored_containers
+            .iter()
+            .map(|(id, container)| ContainerDump {
+                id: id.to_string(),
+                name: container.name.clone(),
+                image: container.image.clone(),
+                created: container.created.clone(),
+                started_at: container.started_at.clone(),
+                history: containers
+                    .history
+                    .get(id)
+                    .map(|history| history.iter().cloned().collect())
+                    .unwrap_or_default(),
+                url: container.ping_url.to_string(),
+                health: container.health,
+                effective_health: container.effective_health(),
+            })
+            .collect();
+        let mut ignored: Vec<_> = containers
+            .ignored_containers
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let last_fetch = containers.last_fetch.map(state::to_unix);
+        let host = containers.host.as_deref().map(str::to_owned);
+        drop(containers);
+
+        monitored.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+        ignored.sort_unstable();
+        Dump {
+            host,
+            containers: monitored,
+            ignored_containers: ignored,
+            last_fetch,
+            healthchecks: self.healthchecks.snapshot().await,
+            ping_attempts: self.healthchecks.attempts().await,
+        }
+    }
+
+    /// Log a summary of the monitored containers and the given numbers of
+    /// sent and failed pings since the last summary
+    pub async fn log_summary(&self, last_event: Option<u64>, pings: Option<(u64, u64)>) {
+        let containers = read_containers(&self.containers).await;
+        let monitored = containers.monitored_containers.len();
+        let urls = containers.url_index.len();
+        let ignored = containers.ignored_containers.len();
+        let misconfigured = containers
+            .monitored_containers
+            .values()
+            .filter(|container| container.invalid_labels > 0)
+            .count();
+        let last_fetch = containers.last_fetch.map(state::to_unix);
+        let mut flapping: Vec<_> = containers
+            .monitored_containers
+            .iter()
+            .map(|(id, container)| (container.display_name(id), containers.flaps(id)))
+            .filter(|&(_, flaps)| flaps > 0)
+            .collect();
+        drop(containers);
+
+        let (sent, failed) = pings.unzip();
+        let periodic_fetch = self.settings().periodic_fetch;
+        let last_fetch = format_age(last_fetch);
+        let last_event = format_age(last_event);
+        if let Some(down) = self.docker_down() {
+            warn!(
+                monitored,
+                urls,
+                ignored,
+                misconfigured,
+                pings.sent = sent,
+                pings.failed = failed,
+                last_fetch,
+                periodic_fetch,
+                last_event,
+                docker_down = %humantime::format_duration(down),
+                "summary, docker daemon unreachable"
+            );
+        } else {
+            info!(
+                monitored,
+                urls,
+                ignored,
+                misconfigured,
+                pings.sent = sent,
+                pings.failed = failed,
+                last_fetch,
+                periodic_fetch,
+                last_event,
+                "summary"
+            );
+        }
+        if !flapping.is_empty() {
+            flapping.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            let flapping: Vec<_> = flapping
+                .iter()
+                .map(|(name, flaps)| format!("{name} ({flaps})"))
+                .collect();
+            info!(
+                containers = flapping.join(", "),
+                "containers that became unhealthy within the last 24 hours"
+            );
+        }
+    }
+
+    /// Return the delay of the periodic ping to the given url
+    fn ping_offset(&self, ping_url: &str) -> Duration {
+        let Ok(window) = u64::try_from(self.settings().ping_spread.as_millis()) else {
+            return Duration::ZERO;
+        };
+        if window == 0 {
+            return Duration::ZERO;
+        }
+        let mut hasher = DefaultHasher::new();
+        ping_url.hash(&mut hasher);
+        Duration::from_millis(hasher.finish() % window)
+    }
+
+    /// Reload all docker containers from the daemon
+    pub async fn fetch_containers(&self) -> Result<()> {
+        let _fetching = self.fetching.lock().await;
+        self.fetch_locked().await
+    }
+
+    /// Reload all docker containers from the daemon, unless a fetch is
+    /// already running, in which case wait for it to finish instead. Return
+    /// whether a new fetch has been started.
+    pub async fn fetch_coalesced(&self) -> Result<bool> {
+        if let Ok(_fetching) = self.fetching.try_lock() {
+            return self.fetch_locked().await.map(|()| true);
+        }
+        drop(self.fetching.lock().await);
+        Ok(false)
+    }
+
+    /// Reload all docker containers from the daemon while holding the fetch
+    /// lock
+    async fn fetch_locked(&self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.fetch_all().await;
+        histogram!(metrics::FETCH_DURATION).record(start.elapsed());
+        if result.is_ok() {
+            self.reachability.succeeded();
+            self.docker_lost_reported.store(false, Ordering::Relaxed);
+        } else {
+            self.reachability.failed();
+            counter!(metrics::FETCH_FAILURES).increment(1);
+        }
+        result
+    }
+
+    /// Inspect all docker containers and replace the managed containers
+    async fn fetch_all(&self) -> Result<()> {
+        info!("fetching containers");
+        let fetch_generation = self.healthchecks.next_generation();
+        let fetch_started = Instant::now();
+        let ids = self
+            .runtime
+            .list_containers(&ContainerListOpts::default())
+            .await
+            .context("failed to list containers")?
+            .into_iter()
+            .map(|summary| {
+                summary
+                    .id
+                    .map(Arc::from)
+                    .ok_or_else(|| anyhow!("container summary has no id"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // inspect multiple containers concurrently
+        let services = ServiceCache::default();
+        let results: Vec<_> = stream::iter(ids)
+            .map(|id| async {
+                let container = self.fetch_container(&id, &services).await;
+                (id, container)
+            })
+            .buffer_unordered(self.settings().fetch_concurrency)
+            .collect()
+            .await;
+
+        let mut containers = HashMap::new();
+        let mut ignored_containers = HashSet::new();
+        let mut failed = Vec::new();
+        for (id, container) in results {
+            match container {
+                Ok(Some(container)) => {
+                    containers.insert(id, container);
+                }
+                Ok(None) => {
+                    ignored_containers.insert(id);
+                }
+                // the container has been removed after the container list was fetched
+                Err(err) if is_not_found(&err) => {
+                    debug!(
+                        container.id = short_id(&id),
+                        "container disappeared while fetching containers"
+                    );
+                }
+                Err(err) => {
+                    error!(
+                        container.id = short_id(&id),
+                        error = %format_args!("{err:#}"),
+                        "failed to fetch container"
+                    );
+                    failed.push(id);
+                }
+            }
+        }
+
+        let mut cont = write_containers(&self.containers).await;
+        let mut previous = std::mem::take(&mut cont.monitored_containers);
+        // keep the previous state of containers that could not be inspected
+        for id in failed {
+            if let Some(container) = previous.remove(&id) {
+                containers.insert(id, container);
+            } else if cont.ignored_containers.contains(&id) {
+                ignored_containers.insert(id);
+            }
+        }
+        let mut transitions = Vec::new();
+        for (id, container) in &mut containers {
+            if let Some(old) = previous.get(id) {
+                container.inherit(old);
+                transitions.push((
+                    id.clone(),
+                    old.effective_health(),
+                    container.effective_health(),
+                ));
+            }
+        }
+        // keep containers that have been added by events during the fetch
+        for (id, old) in previous {
+            if old.updated > fetch_generation && !ignored_containers.contains(&id) {
+                containers.entry(id).or_insert(old);
+            }
+        }
+        // remove containers that died during the fetch
+        containers.retain(|id, _| {
+            cont.tombstones
+                .get(id)
+                .map_or(true, |died| *died < fetch_started)
+        });
+        cont.replace(containers);
+        self.sync_probes(&cont);
+        for (id, old, new) in transitions {
+            if old != new && cont.monitored_containers.contains_key(&id) {
+                cont.record(&id, Some(old), Some(new), "fetch");
+            }
+        }
+        cont.ignored_containers = ignored_containers;
+        cont.last_fetch = Some(SystemTime::now());
+        Ok(())
+    }
+
+    /// Send an unhealthy ping to the ping urls of exited containers for which
+    /// no running container with the same ping url is monitored. Exited
+    /// containers are not added to the collection of monitored containers.
+    pub async fn fail_stopped_containers(&self) -> Result<()> {
+        info!("checking stopped containers");
+        let ids = self
+            .runtime
+            .list_containers(
+                &ContainerListOpts::builder()
+                    .all(true)
+                    .filter([ContainerFilter::Status(ContainerStatus::Exited)])
+                    .build(),
+            )
+            .await
+            .context("failed to list stopped containers")?
+            .into_iter()
+            .filter_map(|summary| summary.id);
+
+        let mut urls = HashSet::new();
+        let services = ServiceCache::default();
+        for id in ids {
+            match self.fetch_container(&id, &services).await {
+                Ok(Some(container)) => {
+                    urls.insert(container.ping_url);
+                }
+                Ok(None) => {}
+                Err(err) if is_not_found(&err) => {}
+                Err(err) => error!(
+                    container.id = short_id(&id),
+                    error = %format_args!("{err:#}"),
+                    "failed to fetch stopped container"
+                ),
+            }
+        }
+
+        for url in urls {
+            let containers = read_containers(&self.containers).await;
+            if containers.status(&url).is_some() {
+                continue;
+            }
+            let generation = self.healthchecks.next_generation();
+            drop(containers);
+            info!(
+                url = %redact_url(&url),
+                "no running container for ping url of stopped container, sending unhealthy ping"
+            );
+            if let Err(err) = self
+                .healthchecks
+                .ping(&url, &Health::Unhealthy, "container exited", generation)
+                .await
+            {
+                error!(
+                    url = %redact_url(&url),
+                    error = %format_args!("{err:#}"),
+                    "failed to ping healthchecks"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle container start events. Replayed start events for already
+    /// monitored containers simply refresh the container data.
+    pub async fn container_started(&self, id: Arc<str>) -> Result<()> {
+        // the container may have been restarted after it died
+        write_containers(&self.containers)
+            .await
+            .tombstones
+            .remove(&id);
+
+        // ignore containers without healthchecks label
+        if read_containers(&self.containers)
+            .await
+            .ignored_containers
+            .contains(&id)
+        {
+            return Ok(());
+        }
+
+        // try to get information about the new container
+        if let Some(mut container) = self.fetch_container(&id, &ServiceCache::default()).await? {
+            // add the container to the collection of monitored containers, keeping
+            // the history of a restarted container
+            let label = container.ping_url.clone();
+            let mut containers = write_containers(&self.containers).await;
+            let old_health = containers.monitored_containers.get(&id).map(|old| {
+                container.inherit(old);
+                old.effective_health()
+            });
+            if old_health.is_none() {
+                if let Some((_, restarts)) = containers.restart_history.remove(&id) {
+                    container.restarted(restarts);
+                }
+            }
+            containers.record(&id, old_health, Some(container.effective_health()), "start");
+            containers.insert(id, container);
+            self.sync_probes(&containers);
+            drop(containers);
+            debug!("monitoring container");
+
+            // cancel a delayed unhealthy ping for the same ping url
+            if let Some(handle) = self.pending_fails.lock().await.remove(&label) {
+                info!(
+                    url = %redact_url(&label),
+                    "container started within grace period, cancelling fail ping"
+                );
+                handle.abort();
+            }
+
+            // send a ping to the corresponding ping url
+            self.ping_one(&label, false).await?;
+        } else {
+            // ignore the container if it has no healthchecks label
+            write_containers(&self.containers)
+                .await
+                .ignored_containers
+                .insert(id);
+        }
+
+        Ok(())
+    }
+
+    /// Handle container die events. Die events for unknown containers (e.g.
+    /// replayed events) are ignored.
+    pub async fn container_died(&self, id: Arc<str>) -> Result<()> {
+        // remember the container for some time to be able to ignore delayed events
+        let mut containers = write_containers(&self.containers).await;
+        containers
+            .tombstones
+            .retain(|_, died| died.elapsed() < TOMBSTONE_DURATION);
+        containers.tombstones.insert(id.clone(), Instant::now());
+
+        // ignore containers without healthchecks label and remove them from the set of
+        // ignored containers
+        if containers.ignored_containers.remove(&id) {
+            return Ok(());
+        }
+
+        // remove the container from the collection of monitored containers
+        let removed = containers.remove(&id);
+        self.sync_probes(&containers);
+        if let Some(container) = removed {
+            containers.record(&id, Some(container.effective_health()), None, "die");
+
+            // remember the restarts of the container until it is started again
+            containers
+                .restart_history
+                .retain(|_, (died, _)| died.elapsed() < RESTART_WINDOW);
+            containers
+                .restart_history
+                .insert(id.clone(), (Instant::now(), container.restarts.clone()));
+
+            // send an unhealthy ping to the corresponding ping url,
+            // if this was the last container with this ping url
+            if containers.status(&container.ping_url).is_some() {
+                return Ok(());
+            }
+            let generation = self.healthchecks.next_generation();
+            drop(containers);
+
+            let name = container.display_name(&id);
+            info!(url = %redact_url(&container.ping_url), "last container with ping url died");
+            if self.settings().die_grace.is_zero() {
+                self.healthchecks
+                    .ping(
+                        &container.ping_url,
+                        &Health::Unhealthy,
+                        &format!("{name}: died"),
+                        generation,
+                    )
+                    .await?;
+            } else {
+                self.schedule_fail(container.ping_url, name).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send an unhealthy ping to the given url after the die grace period,
+    /// unless a container with the same ping url has been started in the
+    /// meantime
+    async fn schedule_fail(&self, ping_url: Arc<str>, name: String) {
+        let mut pending = self.pending_fails.lock().await;
+        if pending.contains_key(&ping_url) {
+            return;
+        }
+
+        let handle = spawn({
+            let grace = self.settings().die_grace;
+            let url = ping_url.clone();
+            let containers = self.containers.clone();
+            let healthchecks = self.healthchecks.clone();
+            let pending_fails = self.pending_fails.clone();
+            async move {
+                sleep(grace).await;
+                pending_fails.lock().await.remove(&url);
+                let containers = read_containers(&containers).await;
+                if containers.status(&url).is_some() {
+                    return;
+                }
+                let generation = healthchecks.next_generation();
+                drop(containers);
+                if let Err(err) = healthchecks
+                    .ping(
+                        &url,
+                        &Health::Unhealthy,
+                        &format!("{name}: died"),
+                        generation,
+                    )
+                    .await
+                {
+                    error!(
+                        url = %redact_url(&url),
+                        error = %format_args!("{err:#}"),
+                        "failed to send delayed unhealthy ping"
+                    );
+                }
+            }
+            .in_current_span()
+        });
+        pending.insert(ping_url, handle);
+    }
+
+    /// Handle container update events. The container is fetched again and the
+    /// urls whose aggregated health status changed are pinged. If the
+    /// container lost its `healthchecks.url` label, it is ignored from now on,
+    /// and if it is not running, it is no longer monitored.
+    pub async fn container_updated(&self, id: Arc<str>) -> Result<()> {
+        let container = self.fetch_container(&id, &ServiceCache::default()).await?;
+
+        let mut containers = write_containers(&self.containers).await;
+        let mut urls: Vec<_> = containers
+            .monitored_containers
+            .get(&id)
+            .into_iter()
+            .chain(&container)
+            .map(|c| c.ping_url.clone())
+            .collect();
+        urls.dedup();
+        let before: Vec<_> = urls.iter().map(|url| containers.status(url)).collect();
+
+        let old = containers.remove(&id);
+        let old_health = old.as_ref().map(Container::effective_health);
+        match container {
+            Some(mut container) if container.running => {
+                containers.ignored_containers.remove(&id);
+                if let Some(old) = &old {
+                    container.inherit(old);
+                }
+                containers.record(
+                    &id,
+                    old_health,
+                    Some(container.effective_health()),
+                    "update",
+                );
+                containers.insert(id, container);
+            }
+            // stopped containers are monitored again when they are started
+            container => {
+                if old.is_some() {
+                    containers.record(&id, old_health, None, "update");
+                }
+                if container.is_none() {
+                    containers.ignored_containers.insert(id);
+                }
+            }
+        }
+        let changed: Vec<_> = urls
+            .into_iter()
+            .zip(before)
+            .filter(|(url, health)| containers.status(url) != *health)
+            .map(|(url, _)| url)
+            .collect();
+        self.sync_probes(&containers);
+        drop(containers);
+
+        // ping all affected urls whose aggregated health status changed
+        for url in changed {
+            self.ping_one(&url, false).await?;
+        }
+        Ok(())
+    }
+
+    /// Handle container health update events. The update is discarded if the
+    /// health status of the container has been observed after the event has
+    /// been received (e.g. by a concurrent fetch).
+    pub async fn container_health_update(&self, id: Arc<str>, health: Health) -> Result<()> {
+        let generation = self.healthchecks.next_generation();
+
+        // ignore containers without healthchecks label
+        let managed = read_containers(&self.containers).await;
+        if managed.ignored_containers.contains(&id) {
+            return Ok(());
+        }
+
+        // ignore delayed events of containers that have already died
+        if managed
+            .tombstones
+            .get(&id)
+            .is_some_and(|died| died.elapsed() < TOMBSTONE_DURATION)
+        {
+            debug!("ignoring health status update of died container");
+            return Ok(());
+        }
+        let min_failing_streak = managed
+            .monitored_containers
+            .get(&id)
+            .map_or(0, |container| container.min_failing_streak);
+        drop(managed);
+
+        // the failing streak is not included in health status events, so it has to be
+        // fetched from the docker daemon if required. a container only becomes healthy
+        // after a passing healthcheck, which resets the failing streak.
+        let failing_streak = match health {
+            Health::Unhealthy if min_failing_streak > 0 => {
+                Some(get_failing_streak(&self.inspect_container(&id).await?)?)
+            }
+            Health::Healthy => Some(0),
+            Health::Unhealthy | Health::Starting => None,
+        };
+
+        // try to find the container in the collection of monitored containers,
+        // otherwise fetch its data from the docker daemon
+        let mut containers = write_containers(&self.containers).await;
+        let updated = containers.update(&id, |container| {
+            let old = container.effective_health();
+            if let Some(failing_streak) = failing_streak {
+                container.failing_streak = failing_streak;
+            }
+            let applied = match container.mode {
+                // containers in liveness mode do not report their health status
+                MonitorMode::Liveness => {
+                    debug!("ignoring health status update of container in liveness mode");
+                    false
+                }
+                // discard the event if a newer health status has been observed in the
+                // meantime (e.g. by a concurrent fetch)
+                MonitorMode::Healthcheck | MonitorMode::Probe => {
+                    let applied = container.observe(Some(health), generation);
+                    if !applied {
+                        debug!("ignoring outdated health status update");
+                    }
+                    applied
+                }
+            };
+            (
+                applied,
+                container.ping_url.clone(),
+                old,
+                container.effective_health(),
+            )
+        });
+        if let Some((_, _, old, new)) = updated {
+            if old != new {
+                containers.record(&id, Some(old), Some(new), "health_status");
+            }
+        }
+        drop(containers);
+        let label = if let Some((applied, label, _, _)) = updated {
+            if !applied {
+                return Ok(());
+            }
+            debug!(health = ?health, "updated health status");
+            label
+        } else if let Some(container) = self.fetch_container(&id, &ServiceCache::default()).await? {
+            // ignore delayed events of containers that are no longer running
+            if !container.running {
+                debug!("ignoring health status update of stopped container");
+                return Ok(());
+            }
+
+            // add the container to the collection of monitored containers. the
+            // lock is not held while fetching, so other events can be processed
+            // in the meantime.
+            let label = container.ping_url.clone();
+            let mut cont = write_containers(&self.containers).await;
+            cont.record(
+                &id,
+                None,
+                Some(container.effective_health()),
+                "health_status",
+            );
+            cont.insert(id, container);
+            label
+        } else {
+            // ignore the container if it has no healthchecks label
+            write_containers(&self.containers)
+                .await
+                .ignored_containers
+                .insert(id);
+            return Ok(());
+        };
+
+        // send a ping to the corresponding ping url
+        self.ping_one(&label, false).await?;
+        Ok(())
+    }
+
+    /// Ping one url. Forced pings are sent even if the health status has not
+    /// changed or only a summary is sent. Return the health status of the
+    /// url.
+    pub async fn ping_one(&self, ping_url: &Arc<str>, force: bool) -> Result<Health> {
+        let containers = read_containers(&self.containers).await;
+        let health = containers.status(ping_url).unwrap_or(Health::Unhealthy);
+        let body = containers.body(ping_url);
+        let generation = self.healthchecks.next_generation();
+        drop(containers);
+        if force {
+            self.healthchecks
+                .keepalive(ping_url, &health, &body, generation)
+                .await?;
+        } else if !self.settings().summary_only {
+            self.healthchecks
+                .ping(ping_url, &health, &body, generation)
+                .await?;
+        }
+        Ok(health)
+    }
+
+    /// Return the name, ping url and reported health status of each monitored
+    /// container
+    pub async fn health_summary(&self) -> Vec<(String, Arc<str>, Health)> {
+        read_containers(&self.containers)
+            .await
+            .monitored_containers
+            .iter()
+            .map(|(id, container)| {
+                let name = container
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| short_id(id).to_owned());
+                (
+                    name,
+                    container.ping_url.clone(),
+                    container.effective_health(),
+                )
+            })
+            .collect()
+    }
+
+    /// Fetch information about a container from the docker daemon, taking
+    /// the swarm services from the given cache if they have been inspected
+    /// before. Returns `None` if the container has no `healthchecks.url` label
+    /// and an error if this could not be determined.
+    async fn fetch_container(
+        &self,
+        id: &str,
+        services: &ServiceCache,
+    ) -> Result<Option<Container>> {
+        let generation = self.healthchecks.next_generation();
+        let settings = self.settings();
+        let data = self.inspect_container(id).await?;
+
+        let labels = get_labels(&data)?;
+        // with swarm aggregation, tasks are covered by the health of their service
+        if self.swarm_aggregation && labels.contains_key("com.docker.swarm.service.id") {
+            return Ok(None);
+        }
+
+        // in swarm mode, tasks inherit the ping url from the labels of their service
+        let service = match labels.get("com.docker.swarm.service.id") {
+            Some(service_id) if self.swarm_mode => Some(
+                self.fetch_service(service_id, services)
+                    .await
+                    .with_context(|| format!("failed to fetch service of container {id}"))?,
+            ),
+            _ => None,
+        };
+        let Some(label) = labels.get("healthchecks.url").or_else(|| {
+            service
+                .as_ref()
+                .and_then(|(_, service_labels)| service_labels.get("healthchecks.url"))
+        }) else {
+            return Ok(None);
+        };
+
+        let mut invalid_labels = 0;
+        let flap_threshold = parse_label(
+            labels,
+            id,
+            "healthchecks.flap-threshold",
+            settings.flap_threshold,
+            &mut invalid_labels,
+        );
+        let min_failing_streak = parse_label(
+            labels,
+            id,
+            "healthchecks.min-failing-streak",
+            settings.min_failing_streak,
+            &mut invalid_labels,
+        );
+
+        let probe = parse_probe(labels, &data, id, &mut invalid_labels);
+        let mode = if probe.is_some() {
+            MonitorMode::Probe
+        } else {
+            parse_mode(labels, id, &mut invalid_labels)
+        };
+
+        let state = data
+            .state
+            .as_ref()
+            .ok_or_else(|| anyhow!("container inspect state object is empty"))?;
+        let mut container = Container {
+            ping_url: label.as_str().into(),
+            name: data
+                .name
+                .as_deref()
+                .map(|name| name.trim_start_matches('/').to_owned()),
+            image: data
+                .config
+                .as_ref()
+                .and_then(|config| config.image.clone())
+                .or_else(|| data.image.clone()),
+            created: data.created.clone(),
+            started_at: state.started_at.clone(),
+            health: None,
+            running: get_running(&data)?,
+            mode,
+            invalid_labels,
+            restarting: settings.restarting_unhealthy && state.restarting.unwrap_or(false),
+            updated: 0,
+            service: service.map(|(name, _)| name.into()),
+            unhealthy_streak: 0,
+            previous_health: None,
+            flap_threshold,
+            restart_count: data
+                .restart_count
+                .and_then(|count| count.try_into().ok())
+                .unwrap_or(0),
+            failing_streak: get_failing_streak(&data)?,
+            min_failing_streak,
+            restarts: VecDeque::new(),
+            max_restarts: settings.max_restarts_per_hour,
+            restart_limit_exceeded: false,
+            starting: None,
+            probe: probe.map(Arc::new),
+            probe_output: None,
+            log_pattern: parse_log_pattern(labels, id, &mut invalid_labels).map(Arc::new),
+            log_match: None,
+            resource_limits: parse_resource_limits(labels, id, &mut invalid_labels).map(Arc::new),
+            resource_breach: None,
+        };
+        // in liveness mode, the container is healthy as long as it is running, and
+        // probed containers are starting until they have been probed
+        let health = match mode {
+            MonitorMode::Healthcheck => {
+                get_health(&data).context("failed to get health status of container")?
+            }
+            MonitorMode::Liveness => None,
+            MonitorMode::Probe => Some(Health::Starting),
+        };
+        container.observe(health, generation);
+        Ok(Some(container))
+    }
+
+    /// Inspect a container and retry with exponential backoff if the request
+    /// fails. Containers that do not exist are not retried.
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspect200Response> {
+        let settings = self.settings();
+        let mut retries = settings.inspect_retries;
+        let mut delay = settings.inspect_retry_delay;
+        loop {
+            match self
+                .runtime
+                .inspect_container(id)
+                .await
+                .with_context(|| format!("failed to inspect container {id}"))
+            {
+                Ok(data) => return Ok(data),
+                Err(err) if retries == 0 || is_not_found(&err) => return Err(err),
+                Err(err) => {
+                    warn!(
+                        container.id = short_id(id),
+                        error = %format_args!("{err:#}"),
+                        retries,
+                        "container inspect failed, retrying"
+                    );
+                    retries -= 1;
+                    sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+            }
+        }
+    }
+
+    /// Fetch the name and labels of a swarm service from the docker daemon,
+    /// unless they are already in the given cache
+    async fn fetch_service(&self, id: &str, cache: &ServiceCache) -> Result<ServiceInfo> {
+        let cell = cache
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(id.to_owned())
+            .or_default()
+            .clone();
+        cell.get_or_try_init(|| self.inspect_service(id))
+            .await
+            .cloned()
+    }
+
+    /// Inspect a swarm service and return its name and labels
+    async fn inspect_service(&self, id: &str) -> Result<ServiceInfo> {
+        let spec = self
+            .runtime
+            .inspect_service(id)
+            .await
+            .with_context(|| format!("failed to inspect service {id}"))?
+            .spec
+            .ok_or_else(|| anyhow!("service inspect spec object is empty"))?;
+        Ok((
+            spec.name.unwrap_or_else(|| id.to_owned()),
+            spec.labels.unwrap_or_default(),
+        ))
+    }
+}
+
+/// Acquire a read lock on the managed containers and record the time spent
+/// waiting for it
+async fn read_containers(
+    containers: &RwLock<ManagedContainers>,
+) -> RwLockReadGuard<'_, ManagedContainers> {
+    let start = Instant::now();
+    let guard = containers.read().await;
+    histogram!(metrics::LOCK_WAIT, "lock" => "containers").record(start.elapsed());
+    guard
+}
+
+/// Acquire a write lock on the managed containers and record the time spent
+/// waiting for it
+async fn write_containers(
+    containers: &RwLock<ManagedContainers>,
+) -> RwLockWriteGuard<'_, ManagedContainers> {
+    let start = Instant::now();
+    let guard = containers.write().await;
+    histogram!(metrics::LOCK_WAIT, "lock" => "containers").record(start.elapsed());
+    guard
+}
+
+/// Parse a label of a container, falling back to the given default
+/// and counting the label as invalid if it cannot be parsed
+fn parse_label<T: FromStr>(
+    labels: &HashMap<String, String>,
+    id: &str,
+    name: &'static str,
+    default: T,
+    invalid_labels: &mut usize,
+) -> T
+where
+    T::Err: Display,
+{
+    let Some(value) = labels.get(name) else {
+        return default;
+    };
+    value.parse().unwrap_or_else(|err: T::Err| {
+        warn!(
+            container.id = short_id(id),
+            label = name,
+            error = %err,
+            "invalid label"
+        );
+        *invalid_labels += 1;
+        default
+    })
+}
+
+/// Start a task for each running container with a probe or log pattern that
+/// has none yet, and stop the tasks of containers that died, are no longer
+/// monitored or whose probe or log pattern changed
+fn sync_tasks<T: PartialEq + Display>(
+    tasks: &RunningTasks<T>,
+    containers: &ManagedContainers,
+    active: impl Fn(&Container) -> Option<&Arc<T>>,
+    start: impl Fn(&Arc<T>, &Arc<str>, &Container) -> JoinHandle<()>,
+) {
+    let mut tasks = tasks.lock().unwrap_or_else(PoisonError::into_inner);
+    tasks.retain(|id, (current, handle)| {
+        let keep = containers
+            .monitored_containers
+            .get(id)
+            .and_then(&active)
+            .is_some_and(|active| active == current);
+        if !keep {
+            debug!(container.id = short_id(id), probe = %current, "stopping probe");
+            handle.abort();
+        }
+        keep
+    });
+    for (id, container) in &containers.monitored_containers {
+        let Some(probe) = active(container) else {
+            continue;
+        };
+        if tasks.contains_key(id) {
+            continue;
+        }
+        debug!(container.id = short_id(id), probe = %probe, "starting probe");
+        let handle = start(probe, id, container);
+        tasks.insert(id.clone(), (probe.clone(), handle));
+    }
+}
+
+/// Merge the pings of aggregated swarm services into the pings of the
+/// containers. If a ping url is used by both, the worse health status is sent
+/// and the bodies are concatenated.
+fn merge_pings(
+    pings: &mut Vec<(Arc<str>, Health, String)>,
+    service_pings: Vec<(Arc<str>, Health, String)>,
+    order: SeverityOrder,
+) {
+    for (url, health, body) in service_pings {
+        match pings.iter_mut().find(|(existing, _, _)| *existing == url) {
+            Some((_, existing_health, existing_body)) => {
+                if health.severity(order) > existing_health.severity(order) {
+                    *existing_health = health;
+                }
+                existing_body.push('\n');
+                existing_body.push_str(&body);
+            }
+            None => pings.push((url, health, body)),
+        }
+    }
+}
+
+/// Parse the monitor mode of a container without probe from its labels,
+/// counting the label as invalid if it has an unknown value
+fn parse_mode(
+    labels: &HashMap<String, String>,
+    id: &str,
+    invalid_labels: &mut usize,
+) -> MonitorMode {
+    match labels.get("healthchecks.mode").map(String::as_str) {
+        None | Some("healthcheck") => MonitorMode::Healthcheck,
+        Some("liveness") => MonitorMode::Liveness,
+        Some(mode) => {
+            warn!(
+                container.id = short_id(id),
+                label = "healthchecks.mode",
+                value = mode,
+                "invalid label"
+            );
+            *invalid_labels += 1;
+            MonitorMode::Healthcheck
+        }
+    }
+}
+
+/// Parse the http, tcp or exec probe of a container from its labels, counting the
+/// labels as invalid if they cannot be parsed or the address of the container
+/// cannot be resolved
+fn parse_probe(
+    labels: &HashMap<String, String>,
+    data: &ContainerInspect200Response,
+    id: &str,
+    invalid_labels: &mut usize,
+) -> Option<Probe> {
+    let (label, value) = [
+        "healthchecks.probe.http",
+        "healthchecks.probe.tcp",
+        "healthchecks.probe.exec",
+    ]
+    .into_iter()
+    .find_map(|label| Some((label, labels.get(label)?)))?;
+    let interval: humantime::Duration = parse_label(
+        labels,
+        id,
+        "healthchecks.probe.interval",
+        probe::DEFAULT_INTERVAL.into(),
+        invalid_labels,
+    );
+    let timeout: humantime::Duration = parse_label(
+        labels,
+        id,
+        "healthchecks.probe.timeout",
+        probe::DEFAULT_TIMEOUT.into(),
+        invalid_labels,
+    );
+    let status = parse_label(
+        labels,
+        id,
+        "healthchecks.probe.status",
+        StatusRange::default(),
+        invalid_labels,
+    );
+    let network = ProbeNetwork {
+        settings: data.network_settings.as_ref(),
+        mode: data
+            .host_config
+            .as_ref()
+            .and_then(|config| config.get("NetworkMode")?.as_str()),
+        name: labels.get("healthchecks.probe.network").map(String::as_str),
+        target: parse_label(
+            labels,
+            id,
+            "healthchecks.probe.target",
+            ProbeTarget::default(),
+            invalid_labels,
+        ),
+    };
+    let kind = match label {
+        "healthchecks.probe.http" => network
+            .resolve_url(value)
+            .map(|url| ProbeKind::Http { url, status }),
+        "healthchecks.probe.tcp" => value
+            .parse()
+            .map_err(Error::from)
+            .and_then(|port| network.resolve(port))
+            .map(|(host, port)| ProbeKind::Tcp { host, port }),
+        _ => Ok(ProbeKind::Exec(value.clone())),
+    };
+    let kind = match kind {
+        Ok(kind) => kind,
+        Err(err) => {
+            warn!(
+                container.id = short_id(id),
+                label,
+                error = %format_args!("{err:#}"),
+                "invalid label"
+            );
+            *invalid_labels += 1;
+            return None;
+        }
+    };
+    Some(Probe {
+        kind,
+        interval: interval.into(),
+        timeout: timeout.into(),
+    })
+}
+
+/// Parse the log pattern of a container from its labels, counting the labels
+/// as invalid if they cannot be parsed
+fn parse_log_pattern(
+    labels: &HashMap<String, String>,
+    id: &str,
+    invalid_labels: &mut usize,
+) -> Option<LogPattern> {
+    let pattern = labels.get("healthchecks.log-pattern")?;
+    let cooldown: humantime::Duration = parse_label(
+        labels,
+        id,
+        "healthchecks.log-pattern.cooldown",
+        log_pattern::DEFAULT_COOLDOWN.into(),
+        invalid_labels,
+    );
+    let regex = Regex::new(pattern)
+        .map_err(|err| {
+            warn!(
+                container.id = short_id(id),
+                label = "healthchecks.log-pattern",
+                error = %err,
+                "invalid label"
+            );
+            *invalid_labels += 1;
+        })
+        .ok()?;
+    Some(LogPattern {
+        regex,
+        cooldown: cooldown.into(),
+    })
+}
+
+/// Parse the resource thresholds of a container from its labels, counting the
+/// labels as invalid if they cannot be parsed
+fn parse_resource_limits(
+    labels: &HashMap<String, String>,
+    id: &str,
+    invalid_labels: &mut usize,
+) -> Option<ResourceLimits> {
+    // missing and invalid thresholds are parsed as nan and ignored
+    let mut parse_percent = |name| {
+        let max: f64 = parse_label(labels, id, name, f64::NAN, invalid_labels);
+        max.is_finite().then_some(max)
+    };
+    let max_cpu = parse_percent("healthchecks.max-cpu");
+    let max_mem = parse_percent("healthchecks.max-mem");
+    if max_cpu.is_none() && max_mem.is_none() {
+        return None;
+    }
+    let cpu_window: humantime::Duration = parse_label(
+        labels,
+        id,
+        "healthchecks.max-cpu.window",
+        stats::DEFAULT_CPU_WINDOW.into(),
+        invalid_labels,
+    );
+    Some(ResourceLimits {
+        max_cpu,
+        max_mem,
+        cpu_window: cpu_window.into(),
+    })
+}
+
+/// Return the short form of a container id as shown by the docker cli
+#[must_use]
+pub fn short_id(id: &str) -> &str {
+    id.get(..SHORT_ID_LENGTH).unwrap_or(id)
+}
+
+/// Format the time since the given unix timestamp
+fn format_age(time: Option<u64>) -> String {
+    match time {
+        Some(time) => format!("{}s ago", state::unix_time().saturating_sub(time)),
+        None => "never".to_owned(),
+    }
+}
+
+/// Extract the health status from a container inspect response
+fn get_health(data: &ContainerInspect200Response) -> Result<Option<Health>> {
+    let status = data
+        .state
+        .as_ref()
+        .ok_or_else(|| anyhow!("container inspect state object is empty"))?
+        .health
+        .as_ref()
+        .and_then(|health| health.status.as_ref())
+        .map(std::string::String::as_str);
+
+    Ok(match status {
+        None | Some("none") => None,
+        Some("starting") => Some(Health::Starting),
+        Some("healthy") => Some(Health::Healthy),
+        Some("unhealthy") => Some(Health::Unhealthy),
+        Some(status) => bail!("invalid health status: {status}"),
+    })
+}
+
+/// Extract the number of consecutive failed healthchecks from a container
+/// inspect response
+fn get_failing_streak(data: &ContainerInspect200Response) -> Result<usize> {
+    Ok(data
+        .state
+        .as_ref()
+        .ok_or_else(|| anyhow!("container inspect state object is empty"))?
+        .health
+        .as_ref()
+        .and_then(|health| health.failing_streak)
+        .and_then(|streak| streak.try_into().ok())
+        .unwrap_or(0))
+}
+
+/// Return whether an error was caused by the docker daemon responding with 404
+/// Not Found
+fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<docker_api::Error>(),
+        Some(docker_api::Error::Fault { code, .. }) if code.as_u16() == 404
+    )
+}
+
+/// Extract the running state from a container inspect response
+fn get_running(data: &ContainerInspect200Response) -> Result<bool> {
+    Ok(data
+        .state
+        .as_ref()
+        .ok_or_else(|| anyhow!("container inspect state object is empty"))?
+        .running
+        .unwrap_or(false))
+}
+
+/// Extract the labels from a container inspect response
+fn get_labels(data: &ContainerInspect200Response) -> Result<&HashMap<String, String>> {
+    data.config
+        .as_ref()
+        .ok_or_else(|| anyhow!("container inspect config object is empty"))?
+        .labels
+        .as_ref()
+        .ok_or_else(|| anyhow!("container inspect config labels object is empty"))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::{
+        config::PingMode,
+        docker::fake::{self, FakeRuntime},
+    };
+
+    /// Start a healthchecks server that accepts all pings
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Create a container manager for the given runtime which does not retry
+    /// failed pings
+    fn manager(runtime: &FakeRuntime, config: Config) -> ContainerManager {
+        let config = Config {
+            ping_retries: 0,
+            ..config
+        };
+        let healthchecks = Arc::new(Healthchecks::new(Client::new(), &config));
+        ContainerManager::new(runtime.clone(), healthchecks, &config, None)
+    }
+
+    /// Return the paths of the pings received by the server in order
+    async fn pings(server: &MockServer) -> Vec<String> {
+        server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|request| request.url.path().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fetch_extracts_labels() {
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "web",
+            &[
+                ("healthchecks.url", "https://hc.example/a"),
+                ("healthchecks.flap-threshold", "3"),
+                ("healthchecks.min-failing-streak", "invalid"),
+            ],
+            Some("healthy"),
+        ));
+        runtime.insert(fake::container("b", "db", &[], Some("healthy")));
+        let manager = manager(&runtime, Config::default());
+        manager.fetch_containers().await.unwrap();
+
+        let containers = manager.containers.read().await;
+        assert_eq!(containers.ignored_containers, HashSet::from(["b".into()]));
+        let container = &containers.monitored_containers["a"];
+        assert_eq!(&*container.ping_url, "https://hc.example/a");
+        assert_eq!(container.name.as_deref(), Some("web"));
+        assert_eq!(container.image.as_deref(), Some("alpine:latest"));
+        assert_eq!(container.health, Some(Health::Healthy));
+        assert_eq!(container.mode, MonitorMode::Healthcheck);
+        assert_eq!(container.flap_threshold, 3);
+        assert_eq!(container.min_failing_streak, 0);
+        assert_eq!(container.invalid_labels, 1);
+        assert_eq!(
+            containers.status("https://hc.example/a"),
+            Some(Health::Healthy)
+        );
+    }
+
+    #[tokio::test]
+    async fn container_details_are_exposed() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "0123456789abcdef",
+            "web",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = manager(&runtime, Config::default());
+        manager
+            .container_started("0123456789abcdef".into())
+            .await
+            .unwrap();
+
+        let dump = manager.dump().await;
+        let [container] = &dump.containers[..] else {
+            panic!("expected one container");
+        };
+        assert_eq!(container.name.as_deref(), Some("web"));
+        assert_eq!(container.image.as_deref(), Some("alpine:latest"));
+        assert_eq!(
+            container.created.as_deref(),
+            Some("2024-01-01T00:00:00.000000000Z")
+        );
+        assert_eq!(
+            container.started_at.as_deref(),
+            Some("2024-01-01T00:00:01.000000000Z")
+        );
+
+        let summary = manager.health_summary().await;
+        assert_eq!(summary, [("web".to_owned(), url.into(), Health::Healthy)]);
+        let requests = server.received_requests().await.unwrap_or_default();
+        let body = String::from_utf8_lossy(&requests[0].body);
+        assert_eq!(
+            body.lines().nth(1),
+            Some("web (0123456789abcdef): Healthy (image: alpine:latest, restarts: 0, failing streak: 0)")
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_streaks_are_kept_as_reported() {
+        let server = server().await;
+        let url = format!("{}/a", server.uri());
+        let runtime = FakeRuntime::default();
+        let labels = [("healthchecks.url", url.as_str())];
+        runtime.insert(fake::container("a", "web", &labels, Some("starting")));
+        runtime.modify("a", |container| {
+            let health = container.state.as_mut().unwrap().health.as_mut().unwrap();
+            health.failing_streak = Some(2);
+        });
+        let manager = manager(&runtime, Config::default());
+        manager.fetch_containers().await.unwrap();
+        assert_eq!(
+            manager.containers.read().await.monitored_containers["a"].failing_streak,
+            2
+        );
+
+        // becoming healthy requires a passing healthcheck
+        manager
+            .container_health_update("a".into(), Health::Healthy)
+            .await
+            .unwrap();
+        assert_eq!(
+            manager.containers.read().await.monitored_containers["a"].failing_streak,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn unhealthy_statuses_require_a_failing_streak() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        // the label overrides the global threshold
+        for (id, threshold) in [("a", None), ("b", Some("3"))] {
+            let mut labels = vec![("healthchecks.url", url.as_str())];
+            labels.extend(threshold.map(|value| ("healthchecks.min-failing-streak", value)));
+            runtime.insert(fake::container(id, id, &labels, Some("healthy")));
+        }
+        let config = Config {
+            min_failing_streak: 2,
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+        manager.fetch_containers().await.unwrap();
+        let set_failing_streak = |streak| {
+            for id in ["a", "b"] {
+                runtime.modify(id, |container| {
+                    let health = container.state.as_mut().unwrap().health.as_mut().unwrap();
+                    health.status = Some("unhealthy".parse().unwrap());
+                    health.failing_streak = Some(streak);
+                });
+            }
+        };
+        let effective_health = || async {
+            let containers = manager.containers.read().await;
+            ["a", "b"].map(|id| containers.monitored_containers[id].effective_health())
+        };
+
+        set_failing_streak(2);
+        for id in ["a", "b"] {
+            manager
+                .container_health_update(id.into(), Health::Unhealthy)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            effective_health().await,
+            [Health::Unhealthy, Health::Healthy]
+        );
+
+        // the suppressed status is reported once the streak has been reached
+        set_failing_streak(3);
+        manager.fetch_containers().await.unwrap();
+        assert_eq!(
+            effective_health().await,
+            [Health::Unhealthy, Health::Unhealthy]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignored_containers_are_not_fetched_again() {
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container("a", "web", &[], None));
+        let manager = manager(&runtime, Config::default());
+        manager.fetch_containers().await.unwrap();
+
+        // labels cannot be added to existing containers, so a start event of an
+        // ignored container does not inspect it again
+        runtime.insert(fake::container(
+            "a",
+            "web",
+            &[("healthchecks.url", "https://hc.example/a")],
+            None,
+        ));
+        manager.container_started("a".into()).await.unwrap();
+        assert_eq!(manager.counts().await, (0, 1));
+
+        // the container is forgotten when it dies
+        manager.container_died("a".into()).await.unwrap();
+        assert_eq!(manager.counts().await, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn last_container_with_url_died() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        for id in ["a", "b"] {
+            runtime.insert(fake::container(
+                id,
+                id,
+                &[("healthchecks.url", &url)],
+                Some("healthy"),
+            ));
+        }
+        let manager = manager(&runtime, Config::default());
+        manager.fetch_containers().await.unwrap();
+
+        manager.container_died("a".into()).await.unwrap();
+        assert!(pings(&server).await.is_empty());
+        manager.container_died("b".into()).await.unwrap();
+        assert_eq!(pings(&server).await, ["/check/fail"]);
+        assert_eq!(manager.counts().await, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn stopped_containers_are_dropped_on_update() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "web",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = manager(&runtime, Config::default());
+        manager.fetch_containers().await.unwrap();
+
+        runtime.modify("a", |data| {
+            data.state.as_mut().unwrap().running = Some(false);
+        });
+        manager.container_updated("a".into()).await.unwrap();
+        assert_eq!(manager.counts().await, (0, 0));
+        assert_eq!(pings(&server).await, ["/check/fail"]);
+        let containers = manager.containers.read().await;
+        let transition = containers.history["a"].back().unwrap();
+        assert_eq!(
+            (transition.old, transition.new, transition.source),
+            (Some(Health::Healthy), None, "update")
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_cancels_delayed_fail_pings() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "web",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let config = Config {
+            die_grace: Duration::from_secs(1),
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+        manager.fetch_containers().await.unwrap();
+
+        manager.container_died("a".into()).await.unwrap();
+        assert_eq!(manager.pending_fails.lock().await.len(), 1);
+        manager.shutdown().await;
+        assert!(manager.pending_fails.lock().await.is_empty());
+        let pinged = timeout(Duration::from_secs(2), manager.healthchecks.wait_for_ping()).await;
+        assert!(pinged.is_err());
+        assert!(pings(&server).await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn containers_are_inspected_concurrently() {
+        let runtime = FakeRuntime::default();
+        for i in 0..6 {
+            let id = format!("c{i}");
+            let labels: &[_] = if i % 3 == 0 {
+                &[]
+            } else {
+                &[("healthchecks.url", "https://hc.example/check")]
+            };
+            runtime.insert(fake::container(&id, &id, labels, Some("healthy")));
+            runtime.delay_inspects(&id, Duration::from_secs(1));
+        }
+
+        for (concurrency, rounds) in [(2, 3), (6, 1)] {
+            let config = Config {
+                fetch_concurrency: concurrency,
+                ..Config::default()
+            };
+            let manager = manager(&runtime, config);
+            let start = tokio::time::Instant::now();
+            manager.fetch_containers().await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_secs(rounds));
+            assert_eq!(runtime.max_concurrent_inspects(), concurrency);
+
+            let containers = manager.containers.read().await;
+            let mut monitored: Vec<_> = containers.monitored_containers.keys().collect();
+            monitored.sort_unstable();
+            assert_eq!(
+                monitored,
+                [&"c1".into(), &"c2".into(), &"c4".into(), &"c5".into()]
+            );
+            assert_eq!(
+                containers.ignored_containers,
+                HashSet::from(["c0".into(), "c3".into()])
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_inspects_are_retried_with_backoff() {
+        let runtime = FakeRuntime::default();
+        for id in ["a", "b"] {
+            runtime.insert(fake::container(
+                id,
+                id,
+                &[("healthchecks.url", "https://hc.example/check")],
+                Some("healthy"),
+            ));
+        }
+        // the first container recovers on the last retry, the second one does not
+        runtime.fail_inspects("a", 500, 2);
+        runtime.fail_inspects("b", 500, 3);
+        let config = Config {
+            inspect_retries: 2,
+            inspect_retry_delay: Duration::from_secs(1),
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+
+        let start = tokio::time::Instant::now();
+        manager.fetch_containers().await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+
+        // containers that could not be inspected are not ignored
+        let containers = manager.containers.read().await;
+        let monitored: Vec<_> = containers.monitored_containers.keys().collect();
+        assert_eq!(monitored, [&"a".into()]);
+        assert!(containers.ignored_containers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetches_continue_when_an_inspect_fails() {
+        let runtime = FakeRuntime::default();
+        for id in ["a", "b", "c"] {
+            runtime.insert(fake::container(
+                id,
+                id,
+                &[("healthchecks.url", "https://hc.example/check")],
+                Some("healthy"),
+            ));
+        }
+        runtime.fail_inspects("c", 500, usize::MAX);
+        let config = Config {
+            inspect_retry_delay: Duration::from_millis(10),
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+        manager.fetch_containers().await.unwrap();
+
+        let containers = manager.containers.read().await;
+        let mut monitored: Vec<_> = containers.monitored_containers.keys().collect();
+        monitored.sort_unstable();
+        assert_eq!(monitored, [&"a".into(), &"b".into()]);
+        assert!(containers.ignored_containers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn services_are_inspected_once_per_fetch() {
+        let runtime = FakeRuntime::default();
+        runtime.insert_service(
+            "s",
+            "app",
+            &[("healthchecks.url", "https://hc.example/app")],
+        );
+        for id in ["a", "b", "c"] {
+            runtime.insert(fake::container(
+                id,
+                &format!("app.{id}"),
+                &[("com.docker.swarm.service.id", "s")],
+                Some("healthy"),
+            ));
+        }
+        let config = Config {
+            swarm_mode: true,
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+        manager.fetch_containers().await.unwrap();
+        assert_eq!(runtime.service_inspects(), 1);
+        manager.fetch_containers().await.unwrap();
+        assert_eq!(runtime.service_inspects(), 2);
+
+        let containers = manager.containers.read().await;
+        assert_eq!(containers.monitored_containers.len(), 3);
+        for container in containers.monitored_containers.values() {
+            assert_eq!(&*container.ping_url, "https://hc.example/app");
+            assert_eq!(container.service.as_deref(), Some("app"));
+        }
+    }
+
+    #[tokio::test]
+    async fn health_events_are_handled_during_slow_ping_rounds() {
+        let server = MockServer::start().await;
+        Mock::given(path("/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let slow = format!("{}/slow", server.uri());
+        let fast = format!("{}/fast", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &slow)],
+            Some("healthy"),
+        ));
+        runtime.insert(fake::container(
+            "b",
+            "b",
+            &[("healthchecks.url", &fast)],
+            Some("healthy"),
+        ));
+        let manager = Arc::new(manager(&runtime, Config::default()));
+        manager.fetch_containers().await.unwrap();
+
+        let round = spawn({
+            let manager = manager.clone();
+            async move { manager.ping_healthchecks(false).await }
+        });
+        sleep(Duration::from_millis(100)).await;
+        let update = manager.container_health_update("b".into(), Health::Unhealthy);
+        timeout(Duration::from_secs(5), update)
+            .await
+            .expect("health event blocked by the ping round")
+            .unwrap();
+        assert!(!round.is_finished());
+        assert_eq!(
+            manager.containers.read().await.status(&fast),
+            Some(Health::Unhealthy)
+        );
+        round.abort();
+    }
+
+    #[tokio::test]
+    async fn health_events_during_fetches_are_kept() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = Arc::new(manager(&runtime, Config::default()));
+        manager.fetch_containers().await.unwrap();
+
+        // the fetch inspects the container before the event is received, but
+        // completes after it has been handled
+        let release = runtime.hold_next_inspect();
+        let fetch = spawn({
+            let manager = manager.clone();
+            async move { manager.fetch_containers().await }
+        });
+        sleep(Duration::from_millis(100)).await;
+        manager
+            .container_health_update("a".into(), Health::Unhealthy)
+            .await
+            .unwrap();
+        release.send(()).unwrap();
+        fetch.await.unwrap().unwrap();
+
+        assert_eq!(
+            manager.containers.read().await.status(&url),
+            Some(Health::Unhealthy)
+        );
+        assert_eq!(pings(&server).await, ["/check/fail"]);
+    }
+
+    #[tokio::test]
+    async fn health_events_older_than_fetches_are_discarded() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[
+                ("healthchecks.url", &url),
+                ("healthchecks.min-failing-streak", "1"),
+            ],
+            Some("healthy"),
+        ));
+        let manager = Arc::new(manager(&runtime, Config::default()));
+        manager.fetch_containers().await.unwrap();
+
+        // the event is received before the fetch starts, but the inspect of its
+        // failing streak only completes after the fetch
+        let release = runtime.hold_next_inspect();
+        let event = spawn({
+            let manager = manager.clone();
+            async move {
+                manager
+                    .container_health_update("a".into(), Health::Unhealthy)
+                    .await
+            }
+        });
+        sleep(Duration::from_millis(100)).await;
+        manager.fetch_containers().await.unwrap();
+        release.send(()).unwrap();
+        event.await.unwrap().unwrap();
+
+        assert_eq!(
+            manager.containers.read().await.status(&url),
+            Some(Health::Healthy)
+        );
+        assert!(pings(&server).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn late_health_events_of_died_containers_are_ignored() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = manager(&runtime, Config::default());
+        manager.fetch_containers().await.unwrap();
+
+        // the container can still be inspected when the delayed health event of
+        // the died container arrives
+        manager.container_died("a".into()).await.unwrap();
+        manager
+            .container_health_update("a".into(), Health::Healthy)
+            .await
+            .unwrap();
+
+        assert_eq!(manager.counts().await, (0, 0));
+        assert_eq!(pings(&server).await, ["/check/fail"]);
+    }
+
+    #[tokio::test]
+    async fn containers_dying_during_fetches_are_not_added_again() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = Arc::new(manager(&runtime, Config::default()));
+        manager.fetch_containers().await.unwrap();
+
+        // the fetch inspects the container before it dies, but completes after
+        // the die event has been handled
+        let release = runtime.hold_next_inspect();
+        let fetch = spawn({
+            let manager = manager.clone();
+            async move { manager.fetch_containers().await }
+        });
+        sleep(Duration::from_millis(100)).await;
+        manager.container_died("a".into()).await.unwrap();
+        release.send(()).unwrap();
+        fetch.await.unwrap().unwrap();
+        manager.ping_healthchecks(false).await;
+
+        assert_eq!(manager.counts().await, (0, 0));
+        assert_eq!(pings(&server).await, ["/check/fail"]);
+    }
+
+    #[tokio::test]
+    async fn restarts_are_counted_across_die_events() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "web",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let config = Config {
+            max_restarts_per_hour: Some(2),
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+        manager.fetch_containers().await.unwrap();
+
+        for _ in 0..3 {
+            manager.container_died("a".into()).await.unwrap();
+            manager.container_started("a".into()).await.unwrap();
+        }
+        let containers = manager.containers.read().await;
+        assert_eq!(containers.monitored_containers["a"].restarts.len(), 3);
+        assert_eq!(containers.status(&url), Some(Health::Unhealthy));
+        drop(containers);
+        assert_eq!(
+            pings(&server).await,
+            [
+                "/check/fail",
+                "/check",
+                "/check/fail",
+                "/check",
+                "/check/fail",
+                "/check/fail",
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pings_are_spread_across_the_window() {
+        let runtime = FakeRuntime::default();
+        let urls: Vec<_> = (0..100)
+            .map(|i| format!("https://hc.example/{i}"))
+            .collect();
+        for (i, url) in urls.iter().enumerate() {
+            let id = format!("c{i}");
+            runtime.insert(fake::container(
+                &id,
+                &id,
+                &[("healthchecks.url", url)],
+                Some("healthy"),
+            ));
+        }
+        let config = Config {
+            dry_run: true,
+            ping_spread: Duration::from_secs(60),
+            ..Config::default()
+        };
+        let manager = Arc::new(manager(&runtime, config));
+        manager.fetch_containers().await.unwrap();
+
+        let start = tokio::time::Instant::now();
+        let round = spawn({
+            let manager = manager.clone();
+            async move { manager.ping_healthchecks(false).await }
+        });
+        let mut times = Vec::new();
+        for _ in &urls {
+            manager.healthchecks.wait_for_ping().await;
+            times.push(start.elapsed());
+        }
+        assert_eq!(round.await.unwrap(), 0);
+
+        // each url is pinged at its own offset
+        let mut offsets: Vec<_> = urls.iter().map(|url| manager.ping_offset(url)).collect();
+        offsets.sort_unstable();
+        assert_eq!(times, offsets);
+
+        // the offsets are spread evenly across the window
+        let mut quarters = [0; 4];
+        for time in times {
+            assert!(time < Duration::from_secs(60));
+            quarters[usize::try_from(time.as_secs() / 15).unwrap()] += 1;
+        }
+        assert!(quarters.iter().all(|&count| count >= 15), "{quarters:?}");
+    }
+
+    #[tokio::test]
+    async fn scenario_start_healthy_unhealthy_die() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("starting"),
+        ));
+        let manager = manager(&runtime, Config::default());
+
+        manager.container_started("a".into()).await.unwrap();
+        for health in [Health::Healthy, Health::Unhealthy] {
+            manager
+                .container_health_update("a".into(), health)
+                .await
+                .unwrap();
+        }
+        manager.container_died("a".into()).await.unwrap();
+
+        assert_eq!(
+            pings(&server).await,
+            ["/check/start", "/check", "/check/fail", "/check/fail"]
+        );
+    }
+
+    #[tokio::test]
+    async fn scenario_one_of_two_containers_with_the_same_url_dies() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        for id in ["a", "b"] {
+            runtime.insert(fake::container(
+                id,
+                id,
+                &[("healthchecks.url", &url)],
+                Some("healthy"),
+            ));
+        }
+        let manager = manager(&runtime, Config::default());
+
+        manager.container_started("a".into()).await.unwrap();
+        manager.container_started("b".into()).await.unwrap();
+        manager
+            .container_health_update("b".into(), Health::Unhealthy)
+            .await
+            .unwrap();
+        // the url is still covered by the other container, so it is only pinged
+        // again in the next ping round
+        manager.container_died("b".into()).await.unwrap();
+        manager.ping_healthchecks(false).await;
+
+        assert_eq!(
+            pings(&server).await,
+            ["/check", "/check", "/check/fail", "/check"]
+        );
+    }
+
+    #[tokio::test]
+    async fn scenario_liveness_and_healthcheck_containers_share_a_url() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        // the health status of containers in liveness mode is ignored
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[
+                ("healthchecks.url", &url),
+                ("healthchecks.mode", "liveness"),
+            ],
+            Some("unhealthy"),
+        ));
+        runtime.insert(fake::container(
+            "b",
+            "b",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = manager(&runtime, Config::default());
+
+        manager.container_started("a".into()).await.unwrap();
+        manager.container_started("b".into()).await.unwrap();
+        manager
+            .container_health_update("a".into(), Health::Unhealthy)
+            .await
+            .unwrap();
+        manager
+            .container_health_update("b".into(), Health::Unhealthy)
+            .await
+            .unwrap();
+        // the container in liveness mode is healthy as long as it is running
+        manager.container_died("b".into()).await.unwrap();
+        manager.ping_healthchecks(false).await;
+        manager.container_died("a".into()).await.unwrap();
+
+        assert_eq!(
+            pings(&server).await,
+            ["/check", "/check", "/check/fail", "/check", "/check/fail"]
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_bodies_count_the_replicas_of_shared_urls() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        for (id, health) in [("a", "healthy"), ("b", "healthy"), ("c", "starting")] {
+            runtime.insert(fake::container(
+                id,
+                id,
+                &[("healthchecks.url", &url)],
+                Some(health),
+            ));
+        }
+        let manager = manager(&runtime, Config::default());
+
+        manager.container_started("a".into()).await.unwrap();
+        manager.container_started("b".into()).await.unwrap();
+        manager
+            .container_health_update("b".into(), Health::Unhealthy)
+            .await
+            .unwrap();
+        manager.container_started("c".into()).await.unwrap();
+        manager.container_died("b".into()).await.unwrap();
+        manager.ping_healthchecks(false).await;
+
+        let counts: Vec<_> = server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|request| {
+                let body = String::from_utf8_lossy(&request.body);
+                body.lines().next().unwrap_or_default().to_owned()
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                "1/1 healthy, 0 starting, 0 unhealthy",
+                "2/2 healthy, 0 starting, 0 unhealthy",
+                "1/2 healthy, 0 starting, 1 unhealthy",
+                "1/3 healthy, 1 starting, 1 unhealthy",
+                "1/2 healthy, 1 starting, 0 unhealthy",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scenario_container_dies_before_any_health_event() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("starting"),
+        ));
+        let manager = manager(&runtime, Config::default());
+
+        manager.container_started("a".into()).await.unwrap();
+        manager.container_died("a".into()).await.unwrap();
+
+        assert_eq!(pings(&server).await, ["/check/start", "/check/fail"]);
+    }
+
+    #[tokio::test]
+    async fn scenario_transition_pings() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let config = Config {
+            ping_mode: PingMode::Transitions,
+            ..Config::default()
+        };
+        let manager = manager(&runtime, config);
+
+        manager.container_started("a".into()).await.unwrap();
+        // neither ping rounds nor health events without a change are reported
+        manager.ping_healthchecks(false).await;
+        manager
+            .container_health_update("a".into(), Health::Healthy)
+            .await
+            .unwrap();
+        manager
+            .container_health_update("a".into(), Health::Unhealthy)
+            .await
+            .unwrap();
+        manager.ping_healthchecks(false).await;
+        // keep-alive pings are sent regardless
+        manager.ping_healthchecks(true).await;
+        manager
+            .container_health_update("a".into(), Health::Healthy)
+            .await
+            .unwrap();
+        manager.container_died("a".into()).await.unwrap();
+
+        assert_eq!(
+            pings(&server).await,
+            [
+                "/check",
+                "/check/fail",
+                "/check/fail",
+                "/check",
+                "/check/fail"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn scenario_fetch_replaces_the_state() {
+        let server = server().await;
+        let url = format!("{}/check", server.uri());
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+        let manager = manager(&runtime, Config::default());
+
+        manager.container_started("a".into()).await.unwrap();
+        // the health event of this change is missed, so it is only picked up by
+        // the next fetch
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &url)],
+            Some("unhealthy"),
+        ));
+        manager.fetch_containers().await.unwrap();
+        manager.ping_healthchecks(false).await;
+        manager
+            .container_health_update("a".into(), Health::Healthy)
+            .await
+            .unwrap();
+
+        assert_eq!(pings(&server).await, ["/check", "/check/fail", "/check"]);
+    }
+
+    #[test]
+    fn flapping_health_is_debounced() {
+        let mut container = indexed_container("https://hc.example/a", Health::Healthy);
+        container.unhealthy_streak = 0;
+        container.flap_threshold = 3;
+
+        let (u, h) = (Health::Unhealthy, Health::Healthy);
+        let observed = [(u, h), (h, h), (u, h), (u, h), (u, u), (h, h)];
+        for (generation, (health, expected)) in (1..).zip(observed) {
+            assert!(container.observe(Some(health), generation));
+            assert_eq!(container.effective_health(), expected, "{generation}");
+        }
+    }
+
+    /// Create a container with the given ping url and health status
+    fn indexed_container(ping_url: &str, health: Health) -> Container {
+        Container {
+            ping_url: ping_url.into(),
+            name: None,
+            image: None,
+            created: None,
+            started_at: None,
+            health: Some(health),
+            running: true,
+            mode: MonitorMode::Healthcheck,
+            invalid_labels: 0,
+            restarting: false,
+            service: None,
+            updated: 0,
+            unhealthy_streak: 1,
+            previous_health: None,
+            flap_threshold: 1,
+            restart_count: 0,
+            failing_streak: 0,
+            min_failing_streak: 0,
+            restarts: VecDeque::new(),
+            max_restarts: None,
+            restart_limit_exceeded: false,
+            starting: None,
+            probe: None,
+            probe_output: None,
+            log_pattern: None,
+            log_match: None,
+            resource_limits: None,
+            resource_breach: None,
+        }
+    }
+
+    #[test]
+    fn shared_urls_report_the_most_severe_status() {
+        use Health::{Healthy as H, Starting as S, Unhealthy as U};
+        // statuses of two containers, and the aggregated status with unhealthy
+        // and starting as the worst status
+        let table = [
+            (H, H, H, H),
+            (H, U, U, U),
+            (H, S, S, S),
+            (U, H, U, U),
+            (U, U, U, U),
+            (U, S, U, S),
+            (S, H, S, S),
+            (S, U, U, S),
+            (S, S, S, S),
+        ];
+        for (a, b, unhealthy_worst, starting_worst) in table {
+            for (order, expected) in [
+                (SeverityOrder::UnhealthyWorst, unhealthy_worst),
+                (SeverityOrder::StartingWorst, starting_worst),
+            ] {
+                let mut containers = ManagedContainers::new(order, 0, None);
+                containers.insert("a".into(), indexed_container("https://hc.example", a));
+                containers.insert("b".into(), indexed_container("https://hc.example", b));
+                assert_eq!(
+                    containers.status("https://hc.example"),
+                    Some(expected),
+                    "{a:?} and {b:?} with {order:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn updates_keep_the_interned_id() {
+        let mut containers = ManagedContainers::new(SeverityOrder::UnhealthyWorst, 0, None);
+        let id: Arc<str> = "foo".into();
+        containers.insert(
+            id.clone(),
+            indexed_container("https://hc-ping.com/a", Health::Healthy),
+        );
+
+        containers.update("foo", |container| {
+            container.ping_url = "https://hc-ping.com/b".into();
+            container.health = Some(Health::Unhealthy);
+        });
+
+        let (key, _) = containers
+            .monitored_containers
+            .get_key_value("foo")
+            .unwrap();
+        assert!(Arc::ptr_eq(key, &id));
+        assert_eq!(containers.status("https://hc-ping.com/a"), None);
+        assert_eq!(
+            containers.status("https://hc-ping.com/b"),
+            Some(Health::Unhealthy)
+        );
+    }
+
+    /// Change of the monitored containers
+    #[derive(Debug, Clone)]
+    enum Op {
+        /// Add or replace a container with the given id, ping url and health
+        Insert(u8, u8, Health),
+
+        /// Remove the container with the given id
+        Remove(u8),
+    }
+
+    /// Generate a health status
+    fn health() -> impl Strategy<Value = Health> {
+        prop_oneof![
+            Just(Health::Healthy),
+            Just(Health::Unhealthy),
+            Just(Health::Starting)
+        ]
+    }
+
+    /// Generate a severity order
+    fn severity_order() -> impl Strategy<Value = SeverityOrder> {
+        prop_oneof![
+            Just(SeverityOrder::UnhealthyWorst),
+            Just(SeverityOrder::StartingWorst)
+        ]
+    }
+
+    /// Generate a change of the monitored containers, using few ids and urls
+    /// so that they collide often
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..8_u8, 0..3_u8, health()).prop_map(|(id, url, health)| Op::Insert(id, url, health)),
+            (0..8_u8).prop_map(Op::Remove),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn worst_is_the_most_severe_present_status(
+            healthy in 0..3_usize,
+            unhealthy in 0..3_usize,
+            starting in 0..3_usize,
+            order in severity_order(),
+        ) {
+            let counts = HealthCounts { healthy, unhealthy, starting };
+            let present = [
+                (Health::Healthy, healthy),
+                (Health::Unhealthy, unhealthy),
+                (Health::Starting, starting),
+            ]
+            .into_iter()
+            .filter(|&(_, count)| count > 0)
+            .map(|(health, _)| health);
+            prop_assert_eq!(
+                counts.worst(order).map(|health| health.severity(order)),
+                present.map(|health| health.severity(order)).max()
+            );
+        }
+
+        #[test]
+        fn url_index_matches_the_containers(
+            ops in vec(op(), 0..64),
+            order in severity_order(),
+        ) {
+            let mut containers = ManagedContainers::new(order, 0, None);
+            let mut model = HashMap::<Arc<str>, (String, Health)>::new();
+            for op in ops {
+                match op {
+                    Op::Insert(id, url, health) => {
+                        let id: Arc<str> = id.to_string().into();
+                        let url = format!("https://hc-ping.com/{url}");
+                        let before = containers.status(&url);
+                        let added = !model.contains_key(&id);
+                        containers.insert(id.clone(), indexed_container(&url, health));
+                        model.insert(id, (url.clone(), health));
+
+                        // adding a container never improves the aggregated status
+                        let after = containers.status(&url).map(|status| status.severity(order));
+                        if added {
+                            prop_assert!(after >= before.map(|status| status.severity(order)));
+                        }
+                    }
+                    Op::Remove(id) => {
+                        let id: Arc<str> = id.to_string().into();
+                        containers.remove(&id);
+                        if let Some((url, _)) = model.remove(&id) {
+                            // removing the last container removes the url
+                            if !model.values().any(|(other, _)| *other == url) {
+                                prop_assert_eq!(containers.status(&url), None);
+                                prop_assert!(!containers.url_index.contains_key(&*url));
+                            }
+                        }
+                    }
+                }
+
+                // each url appears once, with the worst status of its containers
+                let mut urls: Vec<_> = containers
+                    .pings()
+                    .into_iter()
+                    .map(|(url, ..)| url)
+                    .collect();
+                let count = urls.len();
+                urls.sort_unstable();
+                urls.dedup();
+                prop_assert_eq!(urls.len(), count);
+                let mut expected = HashMap::<&str, Health>::new();
+                for (url, health) in model.values() {
+                    let worst = expected.entry(url).or_insert(*health);
+                    if health.severity(order) > worst.severity(order) {
+                        *worst = *health;
+                    }
+                }
+                prop_assert_eq!(containers.url_index.len(), expected.len());
+                for (url, health) in expected {
+                    prop_assert_eq!(containers.status(url), Some(health));
+                }
+            }
+        }
+    }
 }
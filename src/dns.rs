@@ -0,0 +1,114 @@
+//! Dns resolver for the healthchecks pings, which can restrict the ip version
+//! and keep the last successful resolution of each host
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tokio::net::lookup_host;
+use tracing::warn;
+
+use crate::config::IpVersion;
+
+/// Mapping from host names to the addresses of their last successful
+/// resolution
+type Cache = Arc<Mutex<HashMap<String, Vec<SocketAddr>>>>;
+
+/// Resolver that uses the system resolver, only returns addresses of the
+/// configured ip version and optionally falls back to the last successful
+/// resolution of a host if a lookup fails, so that pings are not lost during
+/// short outages of the dns server
+pub struct PingResolver {
+    /// Ip version of the returned addresses
+    ip_version: IpVersion,
+
+    /// Last successful resolutions, if caching is enabled
+    cache: Option<Cache>,
+}
+
+impl PingResolver {
+    /// Create a new resolver
+    pub fn new(ip_version: IpVersion, cache: bool) -> Self {
+        Self {
+            ip_version,
+            cache: cache.then(Cache::default),
+        }
+    }
+}
+
+impl Resolve for PingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let ip_version = self.ip_version;
+        let cache = self.cache.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let result = lookup_host((host, 0)).await.and_then(|addrs| {
+                let addrs: Vec<_> = addrs.filter(|addr| ip_version.allows(addr)).collect();
+                if addrs.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no {ip_version:?} address found for {host}"),
+                    ));
+                }
+                Ok(addrs)
+            });
+            let Some(cache) = cache else {
+                return Ok(Box::new(result?.into_iter()) as Addrs);
+            };
+            let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+            let addrs = match result {
+                Ok(addrs) => {
+                    cache.insert(host.to_owned(), addrs.clone());
+                    addrs
+                }
+                Err(err) => {
+                    let Some(addrs) = cache.get(host) else {
+                        return Err(err.into());
+                    };
+                    warn!(host, error = %err, "dns lookup failed, using cached addresses");
+                    addrs.clone()
+                }
+            };
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resolve a host and return the addresses
+    async fn resolve(resolver: &PingResolver, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let name = host.parse().unwrap();
+        match resolver.resolve(name).await {
+            Ok(addrs) => Ok(addrs.collect()),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+
+    #[tokio::test]
+    async fn addresses_are_restricted_to_the_ip_version() {
+        let v4: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let v6: SocketAddr = "[::1]:0".parse().unwrap();
+        for (ip_version, allows) in [
+            (IpVersion::Auto, [true, true]),
+            (IpVersion::V4, [true, false]),
+            (IpVersion::V6, [false, true]),
+        ] {
+            let resolver = PingResolver::new(ip_version, false);
+            for (addr, allowed) in [v4, v6].into_iter().zip(allows) {
+                let result = resolve(&resolver, &addr.ip().to_string()).await;
+                if allowed {
+                    assert_eq!(result.unwrap(), [addr]);
+                } else {
+                    assert!(result.is_err(), "{ip_version:?} allowed {addr}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Docker contexts of the docker cli
+
+use std::{collections::HashMap, io::ErrorKind, path::PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use serde::Deserialize;
+
+/// Endpoint and tls configuration of a docker context
+pub struct Context {
+    /// Connection string of the docker daemon
+    pub host: String,
+
+    /// Directory containing the tls certificates of the context, if any
+    pub cert_path: Option<PathBuf>,
+
+    /// Whether the certificate of the docker daemon is not verified
+    pub skip_tls_verify: bool,
+}
+
+/// Metadata of a docker context (`contexts/meta/<id>/meta.json`)
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Meta {
+    /// Name of the context
+    name: String,
+
+    /// Endpoints of the context by type
+    #[serde(default)]
+    endpoints: HashMap<String, Endpoint>,
+}
+
+/// Endpoint of a docker context
+#[derive(Deserialize)]
+struct Endpoint {
+    /// Connection string of the docker daemon
+    #[serde(rename = "Host")]
+    host: Option<String>,
+
+    /// Whether the certificate of the docker daemon is not verified
+    #[serde(rename = "SkipTLSVerify", default)]
+    skip_tls_verify: bool,
+}
+
+/// Load the docker context with the given name from the configuration
+/// directory of the docker cli (`DOCKER_CONFIG` or `~/.docker`)
+pub fn load(name: &str) -> Result<Context> {
+    let contexts = config_dir()?.join("contexts");
+    let meta_dir = contexts.join("meta");
+    let entries = std::fs::read_dir(&meta_dir)
+        .with_context(|| format!("could not read docker contexts from {}", meta_dir.display()))?;
+    for entry in entries {
+        let entry = entry.with_context(|| {
+            format!("could not read docker contexts from {}", meta_dir.display())
+        })?;
+        let path = entry.path().join("meta.json");
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("could not read {}", path.display()))
+            }
+        };
+        let meta: Meta = serde_json::from_str(&json)
+            .with_context(|| format!("invalid docker context metadata in {}", path.display()))?;
+        if meta.name != name {
+            continue;
+        }
+
+        let Some(endpoint) = meta.endpoints.get("docker") else {
+            bail!(
+                "docker context in {} has no docker endpoint",
+                path.display()
+            );
+        };
+        let Some(host) = endpoint.host.clone() else {
+            bail!("docker endpoint in {} has no host", path.display());
+        };
+        let cert_path = contexts.join("tls").join(entry.file_name()).join("docker");
+        return Ok(Context {
+            host,
+            cert_path: cert_path.is_dir().then_some(cert_path),
+            skip_tls_verify: endpoint.skip_tls_verify,
+        });
+    }
+    bail!(
+        "docker context `{name}` not found in {}",
+        meta_dir.display()
+    )
+}
+
+/// Return the configuration directory of the docker cli
+fn config_dir() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("DOCKER_CONFIG") {
+        return Ok(dir.into());
+    }
+    let home = std::env::var_os("HOME")
+        .context("could not find the docker configuration directory, set DOCKER_CONFIG or HOME")?;
+    Ok(PathBuf::from(home).join(".docker"))
+}
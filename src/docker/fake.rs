@@ -0,0 +1,347 @@
+//! Container runtime serving canned inspect responses, used by the unit tests
+//! of the container manager and the oneshot mode
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use docker_api::{
+    models::{
+        ContainerInspect200Response, ContainerSummary, EventMessage, ExecInspect200Response, Node,
+        PingInfo, Service, SystemInfo, Task,
+    },
+    opts::{ContainerListOpts, EventsOpts},
+    Error, Result,
+};
+use futures_util::{future, stream, stream::BoxStream, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    sync::oneshot,
+    time::{sleep, Instant},
+};
+
+use super::runtime::{ContainerRuntime, ExecOutput};
+
+/// Container runtime whose containers and services are set by the tests.
+/// Clones share the same containers and services.
+#[derive(Clone, Default)]
+pub struct FakeRuntime {
+    /// Mapping from container ids to their inspect responses
+    containers: Arc<Mutex<HashMap<String, ContainerInspect200Response>>>,
+
+    /// Mapping from service ids to their inspect responses
+    services: Arc<Mutex<HashMap<String, Service>>>,
+
+    /// Number of service inspect requests
+    service_inspects: Arc<AtomicUsize>,
+
+    /// Delay of the inspect requests of individual containers
+    inspect_delays: Arc<Mutex<HashMap<String, Duration>>>,
+
+    /// Status code of the errors returned for inspect requests of individual
+    /// containers, and the number of inspect requests that are still to fail
+    failing_inspects: Arc<Mutex<HashMap<String, (u16, usize)>>>,
+
+    /// Number of container inspect requests in progress
+    inspecting: Arc<AtomicUsize>,
+
+    /// Maximum number of container inspect requests that were in progress at
+    /// the same time
+    max_inspecting: Arc<AtomicUsize>,
+
+    /// Signal the next container inspect request waits for before responding
+    held_inspect: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+
+    /// Whether pings and requests to list the containers fail
+    unreachable: Arc<AtomicBool>,
+
+    /// Scripted event streams, one of which is served per subscription
+    event_streams: Arc<Mutex<VecDeque<EventScript>>>,
+
+    /// Time and query string of each subscription to the event stream
+    subscriptions: Arc<Mutex<Vec<(Instant, String)>>>,
+}
+
+/// Events served by a single subscription to the event stream
+struct EventScript {
+    /// Events that are sent right away
+    events: Vec<EventMessage>,
+
+    /// Duration after which the stream ends once the events have been sent.
+    /// The stream stays open if not set.
+    end_after: Option<Duration>,
+}
+
+impl FakeRuntime {
+    /// Add a container or replace the container with the same id
+    pub fn insert(&self, container: ContainerInspect200Response) {
+        let id = container.id.clone().unwrap_or_default();
+        lock(&self.containers).insert(id, container);
+    }
+
+    /// Modify the inspect response of a container
+    pub fn modify(&self, id: &str, f: impl FnOnce(&mut ContainerInspect200Response)) {
+        if let Some(container) = lock(&self.containers).get_mut(id) {
+            f(container);
+        }
+    }
+
+    /// Add a swarm service with the given name and labels
+    pub fn insert_service(&self, id: &str, name: &str, labels: &[(&str, &str)]) {
+        let service = json!({
+            "ID": id,
+            "Spec": { "Name": name, "Labels": to_map(labels) },
+        });
+        let service =
+            serde_json::from_value(service).unwrap_or_else(|err| panic!("invalid fixture: {err}"));
+        lock(&self.services).insert(id.to_owned(), service);
+    }
+
+    /// Hold the next container inspect request until the returned sender is
+    /// used or dropped
+    pub fn hold_next_inspect(&self) -> oneshot::Sender<()> {
+        let (release, held) = oneshot::channel();
+        *lock(&self.held_inspect) = Some(held);
+        release
+    }
+
+    /// Delay all inspect requests of the given container
+    pub fn delay_inspects(&self, id: &str, delay: Duration) {
+        lock(&self.inspect_delays).insert(id.to_owned(), delay);
+    }
+
+    /// Make the given number of inspect requests of the given container fail
+    /// with the given status code
+    pub fn fail_inspects(&self, id: &str, status: u16, times: usize) {
+        lock(&self.failing_inspects).insert(id.to_owned(), (status, times));
+    }
+
+    /// Make pings and requests to list the containers fail, as if the docker
+    /// daemon could not be reached
+    pub fn set_unreachable(&self, unreachable: bool) {
+        self.unreachable.store(unreachable, Ordering::Relaxed);
+    }
+
+    /// Serve the given events on the next subscription to the event stream
+    /// that has no events scripted yet, and end the stream after the given
+    /// duration. Subscriptions without scripted events stay open and idle.
+    pub fn push_event_stream(&self, events: Vec<EventMessage>, end_after: Option<Duration>) {
+        lock(&self.event_streams).push_back(EventScript { events, end_after });
+    }
+
+    /// Return the time and query string of each subscription to the event
+    /// stream so far
+    pub fn subscriptions(&self) -> Vec<(Instant, String)> {
+        lock(&self.subscriptions).clone()
+    }
+
+    /// Return the maximum number of container inspect requests that were in
+    /// progress at the same time so far
+    pub fn max_concurrent_inspects(&self) -> usize {
+        self.max_inspecting.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of service inspect requests so far
+    pub fn service_inspects(&self) -> usize {
+        self.service_inspects.load(Ordering::Relaxed)
+    }
+}
+
+/// Create the inspect response of a running container with the given labels
+/// and health status (`None` if the container has no healthcheck)
+pub fn container(
+    id: &str,
+    name: &str,
+    labels: &[(&str, &str)],
+    health: Option<&str>,
+) -> ContainerInspect200Response {
+    let health = health.map(|status| json!({ "Status": status, "FailingStreak": 0 }));
+    let data = json!({
+        "Id": id,
+        "Name": format!("/{name}"),
+        "Created": "2024-01-01T00:00:00.000000000Z",
+        "Image": "sha256:0123456789abcdef",
+        "RestartCount": 0,
+        "Config": { "Image": "alpine:latest", "Labels": to_map(labels) },
+        "State": {
+            "Status": "running",
+            "Running": true,
+            "Restarting": false,
+            "StartedAt": "2024-01-01T00:00:01.000000000Z",
+            "Health": health,
+        },
+    });
+    serde_json::from_value(data).unwrap_or_else(|err| panic!("invalid fixture: {err}"))
+}
+
+/// Convert a list of labels to a json object
+fn to_map(labels: &[(&str, &str)]) -> Value {
+    labels
+        .iter()
+        .map(|&(key, value)| (key.to_owned(), Value::from(value)))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// Lock a mutex, ignoring poisoning
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Return the error of the docker daemon for unknown objects
+fn not_found(id: &str) -> Error {
+    Error::Fault {
+        code: 404.try_into().unwrap_or_default(),
+        message: format!("no such object: {id}"),
+    }
+}
+
+/// Return the error for requests the fake runtime does not support
+fn unsupported() -> Error {
+    Error::InvalidResponse("not supported by the fake runtime".to_owned())
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    async fn ping(&self) -> Result<PingInfo> {
+        if self.unreachable.load(Ordering::Relaxed) {
+            return Err(Error::InvalidResponse(
+                "docker daemon unreachable".to_owned(),
+            ));
+        }
+        Ok(PingInfo {
+            api_version: "1.43".to_owned(),
+            builder_version: None,
+            docker_experimental: false,
+            cache_control: String::new(),
+            pragma: String::new(),
+            os_type: "linux".to_owned(),
+            server: "fake".to_owned(),
+            date: String::new(),
+        })
+    }
+
+    /// List the running containers, or the stopped ones if the options filter
+    /// for exited containers
+    async fn list_containers(&self, opts: &ContainerListOpts) -> Result<Vec<ContainerSummary>> {
+        if self.unreachable.load(Ordering::Relaxed) {
+            return Err(Error::InvalidResponse(
+                "docker daemon unreachable".to_owned(),
+            ));
+        }
+        let exited = opts
+            .serialize()
+            .is_some_and(|query| query.contains("exited"));
+        lock(&self.containers)
+            .values()
+            .filter(|container| {
+                let running = container
+                    .state
+                    .as_ref()
+                    .and_then(|state| state.running)
+                    .unwrap_or(false);
+                running != exited
+            })
+            .map(|container| serde_json::from_value(json!({ "Id": container.id })))
+            .collect::<serde_json::Result<_>>()
+            .map_err(Error::from)
+    }
+
+    fn events(&self, opts: &EventsOpts) -> BoxStream<'_, Result<EventMessage>> {
+        let query = opts.serialize().unwrap_or_default();
+        lock(&self.subscriptions).push((Instant::now(), query));
+        let script = lock(&self.event_streams).pop_front();
+        let Some(EventScript { events, end_after }) = script else {
+            return stream::pending().boxed();
+        };
+        let end = match end_after {
+            Some(after) => stream::once(sleep(after))
+                .filter_map(|()| future::ready(None))
+                .boxed(),
+            None => stream::pending().boxed(),
+        };
+        stream::iter(events.into_iter().map(Ok)).chain(end).boxed()
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspect200Response> {
+        let inspecting = self.inspecting.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_inspecting.fetch_max(inspecting, Ordering::Relaxed);
+        let held = lock(&self.held_inspect).take();
+        if let Some(held) = held {
+            held.await.ok();
+        }
+        let delay = lock(&self.inspect_delays).get(id).copied();
+        if let Some(delay) = delay {
+            sleep(delay).await;
+        }
+        self.inspecting.fetch_sub(1, Ordering::Relaxed);
+        if let Some((status, times)) = lock(&self.failing_inspects).get_mut(id) {
+            if *times > 0 {
+                *times -= 1;
+                return Err(Error::Fault {
+                    code: (*status).try_into().unwrap_or_default(),
+                    message: format!("inspect of {id} failed"),
+                });
+            }
+        }
+        lock(&self.containers)
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found(id))
+    }
+
+    async fn inspect_service(&self, id: &str) -> Result<Service> {
+        self.service_inspects.fetch_add(1, Ordering::Relaxed);
+        lock(&self.services)
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found(id))
+    }
+
+    async fn create_exec(&self, _container_id: &str, _command: &[String]) -> Result<String> {
+        Err(unsupported())
+    }
+
+    async fn start_exec(&self, _id: &str) -> Result<ExecOutput> {
+        Err(unsupported())
+    }
+
+    async fn inspect_exec(&self, _id: &str) -> Result<ExecInspect200Response> {
+        Err(unsupported())
+    }
+
+    async fn follow_logs(
+        &self,
+        _id: &str,
+        _since: u64,
+        _on_chunk: &mut (dyn FnMut(Vec<u8>) + Send),
+    ) -> Result<()> {
+        Err(unsupported())
+    }
+
+    async fn container_stats(&self, _id: &str) -> Result<serde_json::Value> {
+        Err(unsupported())
+    }
+
+    async fn info(&self) -> Result<SystemInfo> {
+        Err(unsupported())
+    }
+
+    async fn list_nodes(&self) -> Result<Vec<Node>> {
+        Ok(Vec::new())
+    }
+
+    async fn list_services(&self) -> Result<Vec<Service>> {
+        Ok(lock(&self.services).values().cloned().collect())
+    }
+
+    async fn list_tasks(&self, _service_id: &str) -> Result<Vec<Task>> {
+        Ok(Vec::new())
+    }
+}
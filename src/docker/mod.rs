@@ -0,0 +1,95 @@
+//! Connection to the docker daemon
+
+use std::{
+    fs::DirBuilder,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use docker_api::Docker;
+use tracing::{debug, info};
+
+use crate::{
+    config::{Config, DockerHost},
+    startup,
+};
+
+pub mod context;
+#[cfg(test)]
+pub(crate) mod fake;
+pub mod runtime;
+mod ssh;
+mod tls;
+
+/// Create a docker client for the given docker host and wait until the
+/// daemon responds to a ping
+pub async fn connect(
+    config: &Config,
+    host: &DockerHost,
+    deadline: startup::Deadline,
+) -> Result<Docker> {
+    let docker = match host.clone() {
+        DockerHost::Unix(path) => Docker::unix(path),
+        DockerHost::Tcp(host) => Docker::tcp(host)?,
+        DockerHost::Tls(host) => {
+            let files = config
+                .docker_tls_files()
+                .context("no docker client certificates configured")?;
+            let cert_dir = tls::cert_dir(&files)?;
+            Docker::tls(host, cert_dir, config.docker_tls_verify)
+                .context("failed to load docker client certificates")?
+        }
+        DockerHost::Ssh(url) => {
+            Docker::unix(ssh::tunnel(&url, config.docker_ssh_identity.as_deref())?)
+        }
+        DockerHost::Probe(paths) => {
+            return deadline
+                .retry("connecting to the docker daemon", || probe(&paths))
+                .await;
+        }
+    };
+    let version = deadline
+        .retry("pinging the docker daemon", || async {
+            docker.ping().await.context("could not ping docker daemon")
+        })
+        .await?;
+    debug!("connected to docker: {version:?}");
+    Ok(docker)
+}
+
+/// Connect to the first of the given docker sockets whose daemon responds to
+/// a ping. The error lists the reasons why each socket could not be used.
+async fn probe(paths: &[String]) -> Result<Docker> {
+    let mut failures = Vec::new();
+    for path in paths {
+        if !Path::new(path).exists() {
+            failures.push(format!("{path}: socket does not exist"));
+            continue;
+        }
+        let docker = Docker::unix(path);
+        match docker.ping().await {
+            Ok(version) => {
+                info!("using docker socket {path}");
+                debug!("connected to docker: {version:?}");
+                return Ok(docker);
+            }
+            Err(err) => failures.push(format!("{path}: {err}")),
+        }
+    }
+    bail!(
+        "could not connect to any docker socket:\n  {}",
+        failures.join("\n  ")
+    )
+}
+
+/// Create a temporary directory which is only accessible by the current user
+fn private_dir() -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("docker-healthchecks-{}", std::process::id()));
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    Ok(dir)
+}
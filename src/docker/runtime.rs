@@ -0,0 +1,171 @@
+//! Container runtime interface used by the container manager
+
+use async_trait::async_trait;
+use docker_api::{
+    api::{TaskFilter, TaskListOpts},
+    conn::TtyChunk,
+    models::{
+        ContainerInspect200Response, ContainerSummary, EventMessage, ExecInspect200Response, Node,
+        PingInfo, Service, SystemInfo, Task,
+    },
+    opts::{
+        ContainerListOpts, EventsOpts, ExecCreateOpts, ExecStartOpts, LogsOpts, NodeListOpts,
+        ServiceListOpts,
+    },
+    Docker, Error, Exec, Result,
+};
+use futures_util::{stream::BoxStream, StreamExt, TryStreamExt};
+
+/// Output of an exec instance, split into chunks of stdout and stderr
+pub type ExecOutput = BoxStream<'static, Result<TtyChunk>>;
+
+/// Container runtime from which the monitored containers are loaded
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    /// Check whether the runtime is responding
+    async fn ping(&self) -> Result<PingInfo>;
+
+    /// List the summaries of all containers matching the given options
+    async fn list_containers(&self, opts: &ContainerListOpts) -> Result<Vec<ContainerSummary>>;
+
+    /// Subscribe to the events matching the given options
+    fn events(&self, opts: &EventsOpts) -> BoxStream<'_, Result<EventMessage>>;
+
+    /// Inspect the container with the given id
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspect200Response>;
+
+    /// Inspect the swarm service with the given id
+    async fn inspect_service(&self, id: &str) -> Result<Service>;
+
+    /// Create an exec instance that runs the command in the container with the
+    /// given id and return the id of the exec instance
+    async fn create_exec(&self, container_id: &str, command: &[String]) -> Result<String>;
+
+    /// Start the exec instance with the given id and return its output
+    async fn start_exec(&self, id: &str) -> Result<ExecOutput>;
+
+    /// Inspect the exec instance with the given id
+    async fn inspect_exec(&self, id: &str) -> Result<ExecInspect200Response>;
+
+    /// Follow the stdout and stderr of the container with the given id from
+    /// the given unix timestamp on and pass each chunk of output to the
+    /// callback until the container stops
+    async fn follow_logs(
+        &self,
+        id: &str,
+        since: u64,
+        on_chunk: &mut (dyn FnMut(Vec<u8>) + Send),
+    ) -> Result<()>;
+
+    /// Return the current resource usage of the container with the given id
+    async fn container_stats(&self, id: &str) -> Result<serde_json::Value>;
+
+    /// Return system-wide information about the docker daemon
+    async fn info(&self) -> Result<SystemInfo>;
+
+    /// List the nodes of the swarm
+    async fn list_nodes(&self) -> Result<Vec<Node>>;
+
+    /// List the services of the swarm
+    async fn list_services(&self) -> Result<Vec<Service>>;
+
+    /// List the tasks of the swarm service with the given id
+    async fn list_tasks(&self, service_id: &str) -> Result<Vec<Task>>;
+}
+
+#[async_trait]
+impl ContainerRuntime for Docker {
+    async fn ping(&self) -> Result<PingInfo> {
+        Docker::ping(self).await
+    }
+
+    async fn list_containers(&self, opts: &ContainerListOpts) -> Result<Vec<ContainerSummary>> {
+        self.containers().list(opts).await
+    }
+
+    fn events(&self, opts: &EventsOpts) -> BoxStream<'_, Result<EventMessage>> {
+        Box::pin(Docker::events(self, opts))
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspect200Response> {
+        self.containers().get(id).inspect().await
+    }
+
+    async fn inspect_service(&self, id: &str) -> Result<Service> {
+        self.services().get(id).inspect().await
+    }
+
+    async fn create_exec(&self, container_id: &str, command: &[String]) -> Result<String> {
+        let opts = ExecCreateOpts::builder()
+            .command(command)
+            .attach_stdout(true)
+            .attach_stderr(true)
+            .build();
+        // the id of the exec instance is only exposed by its inspect response
+        Exec::create(self.clone(), container_id, &opts)
+            .await?
+            .inspect()
+            .await?
+            .id
+            .ok_or_else(|| Error::InvalidResponse("exec instance has no id".to_owned()))
+    }
+
+    async fn start_exec(&self, id: &str) -> Result<ExecOutput> {
+        let output = Exec::get(self.clone(), id)
+            .start(&ExecStartOpts::default())
+            .await?;
+        Ok(Box::pin(output.map_err(Error::from)))
+    }
+
+    async fn inspect_exec(&self, id: &str) -> Result<ExecInspect200Response> {
+        Exec::get(self.clone(), id).inspect().await
+    }
+
+    async fn follow_logs(
+        &self,
+        id: &str,
+        since: u64,
+        on_chunk: &mut (dyn FnMut(Vec<u8>) + Send),
+    ) -> Result<()> {
+        let opts = LogsOpts::builder()
+            .follow(true)
+            .stdout(true)
+            .stderr(true)
+            .since(i64::try_from(since).unwrap_or(i64::MAX))
+            .build();
+        let container = self.containers().get(id);
+        let mut logs = container.logs(&opts);
+        while let Some(chunk) = logs.next().await {
+            on_chunk(chunk?.into());
+        }
+        Ok(())
+    }
+
+    async fn container_stats(&self, id: &str) -> Result<serde_json::Value> {
+        let container = self.containers().get(id);
+        let mut stats = container.stats();
+        stats
+            .next()
+            .await
+            .unwrap_or_else(|| Err(Error::InvalidResponse("stats stream is empty".to_owned())))
+    }
+
+    async fn info(&self) -> Result<SystemInfo> {
+        Docker::info(self).await
+    }
+
+    async fn list_nodes(&self) -> Result<Vec<Node>> {
+        self.nodes().list(&NodeListOpts::default()).await
+    }
+
+    async fn list_services(&self) -> Result<Vec<Service>> {
+        self.services().list(&ServiceListOpts::default()).await
+    }
+
+    async fn list_tasks(&self, service_id: &str) -> Result<Vec<Task>> {
+        let opts = TaskListOpts::builder()
+            .filter([TaskFilter::Service(service_id.to_owned())])
+            .build();
+        self.tasks().list(&opts).await
+    }
+}
@@ -0,0 +1,123 @@
+//! Tunnel to the docker daemon of a remote host via ssh
+
+use std::{
+    io::ErrorKind,
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{Context, Result};
+use tokio::{
+    io::{self, AsyncBufReadExt, BufReader},
+    net::{UnixListener, UnixStream},
+    process::{ChildStderr, Command},
+    spawn,
+};
+use tracing::{debug, error, warn};
+
+/// Number of seconds between keep-alive messages of the ssh connection. The
+/// connection is closed after three unanswered messages.
+const SERVER_ALIVE_INTERVAL: u32 = 15;
+
+/// Number of tunnels that have been created, used to name their sockets
+static TUNNELS: AtomicUsize = AtomicUsize::new(0);
+
+/// Listen on a local unix socket and forward each connection to the docker
+/// daemon of the given ssh host using `docker system dial-stdio`. A new ssh
+/// process is spawned for every connection, so dropped connections are
+/// re-established as soon as the docker client reconnects.
+pub fn tunnel(url: &str, identity: Option<&str>) -> Result<PathBuf> {
+    let index = TUNNELS.fetch_add(1, Ordering::Relaxed);
+    let path = super::private_dir()?.join(format!("docker-{index}.sock"));
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            return Err(err).with_context(|| format!("failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+
+    let mut args = vec![
+        "-o".to_owned(),
+        "BatchMode=yes".to_owned(),
+        "-o".to_owned(),
+        format!("ServerAliveInterval={SERVER_ALIVE_INTERVAL}"),
+        "-o".to_owned(),
+        "ServerAliveCountMax=3".to_owned(),
+    ];
+    if let Some(identity) = identity {
+        args.extend(["-i".to_owned(), identity.to_owned()]);
+    }
+    args.extend(
+        [url, "--", "docker", "system", "dial-stdio"]
+            .into_iter()
+            .map(str::to_owned),
+    );
+    let args: Arc<[String]> = args.into();
+
+    spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    spawn(forward(stream, args.clone()));
+                }
+                Err(err) => error!("failed to accept docker connection: {err}"),
+            }
+        }
+    });
+    Ok(path)
+}
+
+/// Forward a connection to the docker daemon through a new ssh process
+async fn forward(stream: UnixStream, args: Arc<[String]>) {
+    if let Err(err) = try_forward(stream, &args).await {
+        warn!(
+            "{:#}",
+            err.context("ssh tunnel to the docker daemon failed")
+        );
+    }
+}
+
+/// Forward a connection to the docker daemon through a new ssh process until
+/// either side closes the connection
+async fn try_forward(mut stream: UnixStream, args: &[String]) -> Result<()> {
+    let mut child = Command::new("ssh")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to spawn ssh")?;
+    let (Some(mut stdin), Some(mut stdout), Some(stderr)) =
+        (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+        anyhow::bail!("failed to open the pipes of the ssh process");
+    };
+    spawn(log_stderr(stderr));
+
+    let (mut reader, mut writer) = stream.split();
+    tokio::select! {
+        result = io::copy(&mut reader, &mut stdin) => {
+            result.context("failed to forward request")?;
+        }
+        result = io::copy(&mut stdout, &mut writer) => {
+            result.context("failed to forward response")?;
+        }
+    }
+    debug!("ssh tunnel connection closed");
+    Ok(())
+}
+
+/// Log the error output of an ssh process
+async fn log_stderr(stderr: ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        warn!("ssh: {line}");
+    }
+}
@@ -0,0 +1,67 @@
+//! Client certificates for tls connections to the docker daemon
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::config::TlsFiles;
+
+/// Load the given certificates and return the path of a directory which
+/// contains them as `ca.pem`, `cert.pem` and `key.pem`, as expected by the
+/// docker client. If the files are not already laid out this way, they are
+/// copied to the private temporary directory.
+pub fn cert_dir(files: &TlsFiles) -> Result<PathBuf> {
+    let ca = files.ca.as_deref().map(load_pem).transpose()?;
+    let cert = load_pem(&files.cert)?;
+    let key = load_pem(&files.key)?;
+
+    let cert_dir = files.cert.parent().unwrap_or(Path::new("."));
+    let layout = [
+        (files.ca.as_deref(), "ca.pem"),
+        (Some(files.cert.as_path()), "cert.pem"),
+        (Some(files.key.as_path()), "key.pem"),
+    ];
+    if layout
+        .iter()
+        .all(|(path, name)| path.map_or(true, |path| path == cert_dir.join(name)))
+    {
+        return Ok(cert_dir.to_owned());
+    }
+
+    let dir = super::private_dir()?;
+    for (name, content) in [
+        ("ca.pem", ca),
+        ("cert.pem", Some(cert)),
+        ("key.pem", Some(key)),
+    ] {
+        let Some(content) = content else {
+            continue;
+        };
+        let path = dir.join(name);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&content))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(dir)
+}
+
+/// Read a PEM file and check that it contains at least one PEM block
+fn load_pem(path: &Path) -> Result<Vec<u8>> {
+    let content = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    ensure!(
+        content.windows(11).any(|window| window == b"-----BEGIN "),
+        "{} is not a PEM file",
+        path.display()
+    );
+    Ok(content)
+}
@@ -0,0 +1,120 @@
+//! Dump of the internal state for debugging purposes
+
+use std::{collections::HashMap, hash::BuildHasher};
+
+use serde::Serialize;
+
+use crate::{
+    container_manager::{Health, Transition},
+    state::State,
+};
+
+/// Snapshot of the internal state
+#[derive(Debug, Serialize)]
+pub struct Dump {
+    /// Name of the docker host, if multiple hosts are monitored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+
+    /// Monitored containers
+    pub containers: Vec<ContainerDump>,
+
+    /// Ids of containers without healthchecks label
+    pub ignored_containers: Vec<String>,
+
+    /// Unix timestamp of the last successful container fetch
+    pub last_fetch: Option<u64>,
+
+    /// Pings that have been sent to healthchecks
+    pub healthchecks: State,
+
+    /// Outcome of the last ping attempt of each url
+    pub ping_attempts: HashMap<String, PingAttempt>,
+}
+
+/// Snapshot of a monitored container
+#[derive(Debug, Serialize)]
+pub struct ContainerDump {
+    /// Id of the container
+    pub id: String,
+
+    /// Name of the container
+    pub name: Option<String>,
+
+    /// Image reference of the container
+    pub image: Option<String>,
+
+    /// Time at which the container was created
+    pub created: Option<String>,
+
+    /// Time at which the container was last started
+    pub started_at: Option<String>,
+
+    /// Most recent health transitions of the container
+    pub history: Vec<Transition>,
+
+    /// Ping url of the container
+    pub url: String,
+
+    /// Stored health status of the container
+    pub health: Option<Health>,
+
+    /// Health status that is reported for the container
+    pub effective_health: Health,
+}
+
+/// Snapshot of the state of the docker event stream
+#[derive(Debug, Serialize)]
+pub struct EventStreamDump {
+    /// Unix timestamp of the last event received from the docker daemon
+    pub last_event: Option<u64>,
+
+    /// Unix timestamp since which the event stream is down, if it is
+    pub down_since: Option<u64>,
+
+    /// Number of times the event stream had to be re-established
+    pub reconnects: u64,
+}
+
+/// Last ping attempt to a healthchecks url
+#[derive(Debug, Serialize)]
+pub struct PingAttempt {
+    /// Unix timestamp of the ping attempt
+    pub time: u64,
+
+    /// Error of the ping attempt, if it failed after all retries
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Dump {
+    /// Redact the secret parts of all ping urls
+    pub fn redact(&mut self) {
+        for container in &mut self.containers {
+            container.url = redact_url(&container.url);
+        }
+        self.healthchecks.checks = redact_keys(std::mem::take(&mut self.healthchecks.checks));
+        self.ping_attempts = redact_keys(std::mem::take(&mut self.ping_attempts));
+    }
+}
+
+/// Redact the secret parts of the ping urls in the keys of the given map
+#[must_use]
+pub fn redact_keys<T, S: BuildHasher>(map: HashMap<String, T, S>) -> HashMap<String, T> {
+    map.into_iter()
+        .map(|(url, value)| (redact_url(&url), value))
+        .collect()
+}
+
+/// Redact the path of a ping url, which contains the secret check id
+#[must_use]
+pub fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => format!(
+            "{}://{}/<redacted>",
+            url.scheme(),
+            url.host_str().unwrap_or_default()
+        ),
+        Err(_) => "<redacted>".to_owned(),
+    }
+}
@@ -0,0 +1,242 @@
+//! Container events parsed from the docker event stream
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Error, Result};
+use docker_api::models::EventMessage;
+
+use crate::container_manager::Health;
+
+/// Docker event concerning a container
+pub enum ContainerEvent {
+    /// The container has been started
+    Started(EventContainer),
+
+    /// The container has stopped
+    Died {
+        /// Container that has stopped
+        container: EventContainer,
+
+        /// Exit code of the main process, if included in the event
+        exit_code: Option<i64>,
+    },
+
+    /// The resource limits or restart policy of the container have been
+    /// updated
+    Updated(EventContainer),
+
+    /// The health status of the container has changed
+    HealthChanged {
+        /// Container whose health status has changed
+        container: EventContainer,
+
+        /// New health status of the container
+        health: Health,
+    },
+
+    /// The container has been removed, which needs no handling as it has
+    /// died before
+    Destroyed,
+
+    /// Any other event, which is ignored
+    Other,
+}
+
+/// Container referenced by a docker event
+pub struct EventContainer {
+    /// Id of the container
+    pub id: Arc<str>,
+
+    /// Name of the container, if included in the event
+    pub name: Option<String>,
+}
+
+impl Display for EventContainer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{name} ({})", self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+impl ContainerEvent {
+    /// Return the kind of the event by which it is counted
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Started(_) => "start",
+            Self::Died { .. } => "die",
+            Self::HealthChanged { .. } => "health_status",
+            Self::Updated(_) | Self::Destroyed | Self::Other => "other",
+        }
+    }
+}
+
+impl TryFrom<EventMessage> for ContainerEvent {
+    type Error = Error;
+
+    fn try_from(event: EventMessage) -> Result<Self> {
+        let (Some("container"), Some(action)) = (event.type_.as_deref(), event.action.as_deref())
+        else {
+            return Ok(Self::Other);
+        };
+        Ok(match action {
+            "start" => Self::Started(get_container(&event)?),
+            "die" => Self::Died {
+                container: get_container(&event)?,
+                exit_code: get_attribute(&event, "exitCode").and_then(|code| code.parse().ok()),
+            },
+            "update" => Self::Updated(get_container(&event)?),
+            "destroy" => Self::Destroyed,
+            action => {
+                let Some(status) = action.strip_prefix("health_status: ") else {
+                    return Ok(Self::Other);
+                };
+                let container = get_container(&event)?;
+                let health = match status {
+                    "healthy" => Health::Healthy,
+                    "unhealthy" => Health::Unhealthy,
+                    "starting" => Health::Starting,
+                    status => {
+                        bail!("container {container} has invalid health status: {status}");
+                    }
+                };
+                Self::HealthChanged { container, health }
+            }
+        })
+    }
+}
+
+/// Return an attribute of the actor of a docker event
+fn get_attribute<'a>(event: &'a EventMessage, name: &str) -> Option<&'a String> {
+    event
+        .actor
+        .as_ref()
+        .and_then(|actor| actor.attributes.as_ref())
+        .and_then(|attributes| attributes.get(name))
+}
+
+/// Extract the container id and name from a docker event
+fn get_container(event: &EventMessage) -> Result<EventContainer> {
+    let actor = event
+        .actor
+        .as_ref()
+        .ok_or_else(|| anyhow!("event has no actor"))?;
+    Ok(EventContainer {
+        id: actor
+            .id
+            .as_deref()
+            .map(Arc::from)
+            .ok_or_else(|| anyhow!("event actor is empty"))?,
+        name: get_attribute(event, "name").cloned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Parse a docker event as it is sent by the daemon
+    fn parse(event: &Value) -> Result<ContainerEvent> {
+        serde_json::from_value::<EventMessage>(event.clone())?.try_into()
+    }
+
+    /// Create a container event with the given action and attributes
+    fn event(action: &str, attributes: &Value) -> Value {
+        json!({
+            "status": action,
+            "id": "0123456789ab",
+            "from": "alpine:latest",
+            "Type": "container",
+            "Action": action,
+            "Actor": {
+                "ID": "0123456789ab",
+                "Attributes": attributes,
+            },
+            "scope": "local",
+            "time": 1_700_000_000,
+            "timeNano": 1_700_000_000_123_456_789_i64,
+        })
+    }
+
+    #[test]
+    fn start_events() {
+        let event = event("start", &json!({ "image": "alpine:latest", "name": "web" }));
+        let Ok(ContainerEvent::Started(container)) = parse(&event) else {
+            panic!("not a start event");
+        };
+        assert_eq!(&*container.id, "0123456789ab");
+        assert_eq!(container.name.as_deref(), Some("web"));
+    }
+
+    #[test]
+    fn die_events() {
+        let event = event("die", &json!({ "exitCode": "137", "name": "web" }));
+        let Ok(ContainerEvent::Died {
+            container,
+            exit_code,
+        }) = parse(&event)
+        else {
+            panic!("not a die event");
+        };
+        assert_eq!(container.to_string(), "web (0123456789ab)");
+        assert_eq!(exit_code, Some(137));
+    }
+
+    #[test]
+    fn health_status_events() {
+        for (status, expected) in [
+            ("healthy", Health::Healthy),
+            ("unhealthy", Health::Unhealthy),
+            ("starting", Health::Starting),
+        ] {
+            let event = event(&format!("health_status: {status}"), &json!({}));
+            let Ok(ContainerEvent::HealthChanged { container, health }) = parse(&event) else {
+                panic!("not a health status event");
+            };
+            assert_eq!(container.name, None);
+            assert_eq!(health, expected);
+        }
+    }
+
+    #[test]
+    fn invalid_health_status() {
+        let event = event("health_status: broken", &json!({ "name": "web" }));
+        let err = parse(&event).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "container web (0123456789ab) has invalid health status: broken"
+        );
+    }
+
+    #[test]
+    fn destroy_events() {
+        let event = event("destroy", &json!({ "name": "web" }));
+        assert!(matches!(parse(&event), Ok(ContainerEvent::Destroyed)));
+    }
+
+    #[test]
+    fn other_events_are_ignored() {
+        let exec = event("exec_start: sh -c true", &json!({ "execID": "abc" }));
+        assert!(matches!(parse(&exec), Ok(ContainerEvent::Other)));
+        let network = json!({
+            "Type": "network",
+            "Action": "connect",
+            "Actor": { "ID": "fedcba987654" },
+        });
+        assert!(matches!(parse(&network), Ok(ContainerEvent::Other)));
+    }
+
+    #[test]
+    fn missing_actor() {
+        let mut event = event("start", &json!({}));
+        event.as_object_mut().unwrap().remove("Actor");
+        let err = parse(&event).err().unwrap();
+        assert_eq!(err.to_string(), "event has no actor");
+    }
+}
@@ -1,134 +1,831 @@
 //! Handle docker daemon events
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::{anyhow, bail, Context, Result};
-use docker_api::{models::EventMessage, opts::EventsOpts, Docker};
+use ::metrics::counter;
+use anyhow::{Context, Result};
+use docker_api::{
+    models::EventMessage,
+    opts::{EventFilter, EventFilterType, EventsOpts},
+};
 use futures_util::StreamExt;
-use tokio::{spawn, time::timeout};
-use tracing::{error, info};
+use rand::Rng;
+use tokio::{
+    spawn,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex, Notify, Semaphore,
+    },
+    time::{sleep, timeout, Instant},
+};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
-use crate::container_manager::{ContainerManager, Health};
+use crate::{
+    config::{BufferOverflow, Config},
+    container_manager::{short_id, ContainerManager},
+    docker::runtime::ContainerRuntime,
+    dump::EventStreamDump,
+    event::ContainerEvent,
+    log_pattern::LogMatch,
+    metrics, state,
+    stats::ResourceBreach,
+};
+
+/// Number of seconds after which the event queue of an idle container is
+/// cleaned up
+const QUEUE_IDLE_TIMEOUT: u64 = 60;
+
+/// Container actions to subscribe to, if the docker daemon supports event
+/// filters
+const EVENT_ACTIONS: &[&str] = &[
+    "start",
+    "die",
+    "restart",
+    "destroy",
+    "oom",
+    "update",
+    "health_status",
+];
+
+/// Number of seconds before the last received event from which events are
+/// replayed after resubscribing to the docker event stream. This accounts for
+/// events with the same timestamp and for small clock adjustments.
+const REPLAY_OVERLAP: i64 = 5;
+
+/// Minimum delay before resubscribing to the docker event stream
+const MIN_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay before resubscribing to the docker event stream
+const MAX_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(60);
+
+/// Duration after which an event stream is considered stable, i.e. the
+/// resubscribe delay is reset to its minimum when it ends
+const STABLE_STREAM_DURATION: Duration = Duration::from_secs(10);
+
+/// Counters describing the health of the docker event stream
+#[derive(Default)]
+pub struct EventMetrics {
+    /// Unix timestamp of the last event received from the docker daemon (`0`
+    /// if no event has been received yet)
+    pub last_event: AtomicU64,
+
+    /// Number of times the event stream had to be re-established
+    pub reconnects: AtomicU64,
+
+    /// Unix timestamp since which the event stream is down (`0` if it is up)
+    pub down_since: AtomicU64,
+
+    /// Number of handled container start events
+    pub start_events: AtomicU64,
+
+    /// Number of handled container die events
+    pub die_events: AtomicU64,
+
+    /// Number of handled container health status events
+    pub health_events: AtomicU64,
+
+    /// Number of handled events of any other type
+    pub other_events: AtomicU64,
+
+    /// Number of events whose handling failed
+    pub errors: AtomicU64,
+
+    /// Number of events that could not be handled in time
+    pub timeouts: AtomicU64,
+}
+
+impl EventMetrics {
+    /// Record the receipt of an event
+    fn event_received(&self) {
+        self.last_event.store(state::unix_time(), Ordering::Relaxed);
+    }
+
+    /// Count a handled event by its kind
+    fn count_event(&self, event: &ContainerEvent) {
+        let counter = match event {
+            ContainerEvent::Started(_) => &self.start_events,
+            ContainerEvent::Died { .. } => &self.die_events,
+            ContainerEvent::HealthChanged { .. } => &self.health_events,
+            ContainerEvent::Updated(_) | ContainerEvent::Destroyed | ContainerEvent::Other => {
+                &self.other_events
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        counter!(metrics::EVENTS, "type" => event.kind()).increment(1);
+    }
+
+    /// Log the current state of all counters
+    fn log(&self) {
+        let last_event = match self.last_event.load(Ordering::Relaxed) {
+            0 => "never".to_owned(),
+            last_event => format!("{}s ago", state::unix_time().saturating_sub(last_event)),
+        };
+        info!(
+            last_event,
+            reconnects = self.reconnects.load(Ordering::Relaxed),
+            events.start = self.start_events.load(Ordering::Relaxed),
+            events.die = self.die_events.load(Ordering::Relaxed),
+            events.health_status = self.health_events.load(Ordering::Relaxed),
+            events.other = self.other_events.load(Ordering::Relaxed),
+            errors = self.errors.load(Ordering::Relaxed),
+            timeouts = self.timeouts.load(Ordering::Relaxed),
+            "event stream stats"
+        );
+    }
+}
 
 /// Handler for docker daemon events
 pub struct EventHandler {
     /// Reference to the container manager to which container updates are to be
     /// reported
     container_manager: Arc<ContainerManager>,
+
+    /// Mapping from container id to the event queue of this container. Events
+    /// for the same container are handled sequentially by a dedicated worker
+    /// task, while events for different containers are handled concurrently.
+    queues: Mutex<HashMap<Arc<str>, UnboundedSender<EventMessage>>>,
+
+    /// Limits the number of events that are handled concurrently
+    semaphore: Semaphore,
+
+    /// Number of events that have been received but not yet started to be
+    /// handled
+    pending: AtomicUsize,
+
+    /// Events received before the initial container fetch has completed. Set
+    /// to `None` once the buffered events have been replayed.
+    buffer: Mutex<Option<VecDeque<EventMessage>>>,
+
+    /// Maximum number of events to buffer before the initial container fetch
+    /// has completed
+    buffer_size: usize,
+
+    /// Behavior if the event buffer is full
+    buffer_overflow: BufferOverflow,
+
+    /// Timeout for handling a single docker event
+    timeout: Duration,
+
+    /// Notified whenever the event stream has been re-established
+    reconnected: Notify,
+
+    /// Counters describing the health of the docker event stream
+    metrics: Arc<EventMetrics>,
 }
 
 impl EventHandler {
     /// Create a new docker event handler
-    pub fn new(container_manager: Arc<ContainerManager>) -> Self {
-        Self { container_manager }
+    pub fn new(container_manager: Arc<ContainerManager>, config: &Config) -> Self {
+        Self {
+            container_manager,
+            queues: Mutex::new(HashMap::new()),
+            semaphore: Semaphore::new(config.event_concurrency),
+            pending: AtomicUsize::new(0),
+            buffer: Mutex::new(Some(VecDeque::new())),
+            buffer_size: config.event_buffer_size,
+            buffer_overflow: config.event_buffer_overflow,
+            timeout: config.event_timeout,
+            reconnected: Notify::new(),
+            metrics: Arc::default(),
+        }
     }
 
-    /// Subscribe to the docker event stream and handle all events
-    pub async fn handle_events(self, docker: Docker, timeout_duration: Duration) -> ! {
-        let handler = Arc::new(self);
+    /// Subscribe to the event stream of the container runtime and handle all
+    /// events
+    pub async fn handle_events(self: Arc<Self>, runtime: impl ContainerRuntime) -> ! {
+        let mut delay = MIN_RESUBSCRIBE_DELAY;
+        let mut attempts = 0_u64;
+        let mut down_since: Option<Instant> = None;
+        let mut use_filters = true;
+        let mut last_event_time = None;
         loop {
-            info!("subscribing to docker event stream");
-            let mut stream = docker.events(&EventsOpts::default());
-            while let Some(event) = stream.next().await {
-                spawn(Self::handle_raw_event(
-                    handler.clone(),
-                    event,
-                    timeout_duration,
-                ));
+            if attempts == 0 {
+                info!("subscribing to docker event stream");
+            } else {
+                debug!(attempts, "resubscribing to docker event stream");
+                self.metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+                counter!(metrics::EVENT_STREAM_RECONNECTS).increment(1);
+                self.metrics.log();
             }
+            let subscribed = Instant::now();
+            // replay events that might have been missed while the stream was down
+            let replay_since = last_event_time.map(|time: i64| time - REPLAY_OVERLAP);
+            let mut stream = runtime.events(&events_opts(use_filters, replay_since));
+            // an idle stream yields no events, so a successful ping after
+            // resubscribing marks it as re-established as well
+            let mut pinged = pin!(async {
+                while runtime.ping().await.is_err() {
+                    sleep(MIN_RESUBSCRIBE_DELAY).await;
+                }
+            });
+            loop {
+                let event = tokio::select! {
+                    biased;
+                    event = stream.next() => event,
+                    () = &mut pinged, if down_since.is_some() => {
+                        self.stream_recovered(&mut down_since, &mut attempts);
+                        continue;
+                    }
+                };
+                let Some(event) = event else {
+                    break;
+                };
+                match event {
+                    Ok(event) => {
+                        self.metrics.event_received();
+                        self.container_manager.reachability().succeeded();
+                        self.stream_recovered(&mut down_since, &mut attempts);
+                        if let Some(time) = event.time {
+                            last_event_time = Some(time);
+                        }
+                        self.buffer_event(event).await;
+                    }
+                    Err(docker_api::Error::Fault { code, message })
+                        if use_filters && code.is_client_error() =>
+                    {
+                        warn!(
+                            error = %message,
+                            "docker daemon rejected event filters, falling back to an unfiltered \
+                             event stream"
+                        );
+                        use_filters = false;
+                    }
+                    Err(err) => error!(error = %err, "could not get event data"),
+                }
+            }
+
+            // reset the delay if the stream has been stable for some time
+            if subscribed.elapsed() >= STABLE_STREAM_DURATION {
+                delay = MIN_RESUBSCRIBE_DELAY;
+            }
+            if down_since.is_none() {
+                down_since = Some(Instant::now());
+                self.metrics
+                    .down_since
+                    .store(state::unix_time(), Ordering::Relaxed);
+            }
+            attempts += 1;
+
+            // wait for a random duration between half of and the full delay
+            let jittered = rand::thread_rng().gen_range(delay / 2..=delay);
+            warn!(delay = ?jittered, "docker event stream ended, resubscribing");
+            sleep(jittered).await;
+            delay = (delay * 2).min(MAX_RESUBSCRIBE_DELAY);
         }
     }
 
-    /// Handle a raw event from the docker event stream
-    async fn handle_raw_event(
-        handler: Arc<Self>,
-        event: docker_api::Result<EventMessage>,
-        timeout_duration: Duration,
-    ) {
-        if let Err(err) = timeout(timeout_duration, async {
-            handler
-                .handle_event(event.context("could not get event data")?)
+    /// Record that the event stream is up again if it has been down, and
+    /// notify the waiters for reconnects
+    fn stream_recovered(&self, down_since: &mut Option<Instant>, attempts: &mut u64) {
+        let Some(since) = down_since.take() else {
+            return;
+        };
+        self.metrics.down_since.store(0, Ordering::Relaxed);
+        info!(
+            downtime = ?since.elapsed(),
+            attempts = *attempts,
+            "docker event stream recovered"
+        );
+        *attempts = 0;
+        self.reconnected.notify_one();
+    }
+
+    /// Wait until the event stream has been re-established. Multiple
+    /// reconnects that happen while nobody is waiting are coalesced into a
+    /// single notification.
+    pub async fn wait_for_reconnect(&self) {
+        self.reconnected.notified().await;
+    }
+
+    /// Return the unix timestamp of the last event received from the docker
+    /// daemon
+    pub fn last_event(&self) -> Option<u64> {
+        match self.metrics.last_event.load(Ordering::Relaxed) {
+            0 => None,
+            last_event => Some(last_event),
+        }
+    }
+
+    /// Log the counters describing the health of the docker event stream
+    pub fn log_stats(&self) {
+        self.metrics.log();
+    }
+
+    /// Take a snapshot of the state of the event stream
+    pub fn stream_status(&self) -> EventStreamDump {
+        EventStreamDump {
+            last_event: self.last_event(),
+            down_since: match self.metrics.down_since.load(Ordering::Relaxed) {
+                0 => None,
+                down_since => Some(down_since),
+            },
+            reconnects: self.metrics.reconnects.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Handle the results of the probes of the containers like health status
+    /// events from the docker daemon
+    pub async fn handle_probe_results(self: Arc<Self>) {
+        let Some(mut results) = self.container_manager.take_probe_results() else {
+            return;
+        };
+        while let Some(mut result) = results.recv().await {
+            if let Some(output) = result.output.take() {
+                self.container_manager
+                    .set_probe_output(&result.id, Some(output))
+                    .await;
+            }
+            self.buffer_event(result.into_event()).await;
+        }
+    }
+
+    /// Mark containers as unhealthy whose logs matched their log pattern
+    pub async fn handle_log_matches(self: Arc<Self>) {
+        let Some(mut matches) = self.container_manager.take_log_matches() else {
+            return;
+        };
+        while let Some(LogMatch { id, line }) = matches.recv().await {
+            if let Err(err) = self.container_manager.log_pattern_matched(&id, line).await {
+                error!(
+                    container.id = short_id(&id),
+                    error = %format_args!("{err:#}"),
+                    "failed to handle log pattern match"
+                );
+            }
+        }
+    }
+
+    /// Mark containers as unhealthy while they exceed their resource
+    /// thresholds
+    pub async fn handle_resource_breaches(self: Arc<Self>) {
+        let Some(mut breaches) = self.container_manager.take_resource_breaches() else {
+            return;
+        };
+        while let Some(ResourceBreach { id, reason }) = breaches.recv().await {
+            if let Err(err) = self
+                .container_manager
+                .resource_breach_changed(&id, reason)
                 .await
-                .context("could not handle event")
-        })
-        .await
-        .context("failed to handle event in time")
-        .and_then(|res| res)
-        {
-            error!("{err:#}");
+            {
+                error!(
+                    container.id = short_id(&id),
+                    error = %format_args!("{err:#}"),
+                    "failed to handle resource threshold breach"
+                );
+            }
         }
     }
 
-    /// Handle an event from the docker daemon
-    async fn handle_event(&self, event: EventMessage) -> Result<()> {
-        match (event.type_.as_deref(), event.action.as_deref()) {
-            // container start
-            (Some("container"), Some("start")) => self.handle_container_start(event).await,
-
-            // container die
-            (Some("container"), Some("die")) => self.handle_container_die(event).await,
-
-            // container health update
-            (Some("container"), Some(action)) => {
-                if let Some(status) = action.to_owned().strip_prefix("health_status: ") {
-                    self.handle_container_health_status(event, status).await
-                } else {
-                    Ok(())
+    /// Stop buffering events and replay all events that have been received
+    /// before the initial container fetch completed
+    pub async fn set_ready(self: &Arc<Self>) {
+        // keep the lock while replaying, so that new events are not handled before the
+        // buffered ones
+        let mut buffer = self.buffer.lock().await;
+        let events = buffer.take().unwrap_or_default();
+        info!(events = events.len(), "replaying buffered events");
+        for event in events {
+            self.dispatch_event(event).await;
+        }
+    }
+
+    /// Buffer an event if the initial container fetch has not completed yet,
+    /// otherwise dispatch it right away
+    async fn buffer_event(self: &Arc<Self>, event: EventMessage) {
+        let mut buffer = self.buffer.lock().await;
+        let Some(buffer) = buffer.as_mut() else {
+            drop(buffer);
+            self.dispatch_event(event).await;
+            return;
+        };
+
+        if buffer.len() < self.buffer_size {
+            buffer.push_back(event);
+            return;
+        }
+        match self.buffer_overflow {
+            BufferOverflow::DropOldest => {
+                warn!("event buffer is full, dropping oldest event");
+                buffer.pop_front();
+                buffer.push_back(event);
+            }
+            BufferOverflow::DropNewest => {
+                warn!("event buffer is full, dropping newest event");
+            }
+        }
+    }
+
+    /// Append an event to the queue of the container it belongs to, and start a
+    /// new worker task for this queue if necessary
+    async fn dispatch_event(self: &Arc<Self>, event: EventMessage) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+
+        // events without a container id cannot be ordered, so handle them right away
+        let Some(id) = event
+            .actor
+            .as_ref()
+            .and_then(|actor| actor.id.as_deref())
+            .map(Arc::<str>::from)
+        else {
+            let handler = self.clone();
+            spawn(async move { handler.handle_raw_event(event).await }.in_current_span());
+            return;
+        };
+
+        let mut queues = self.queues.lock().await;
+        let event = match queues.get(&id) {
+            Some(sender) => match sender.send(event) {
+                Ok(()) => return,
+                Err(err) => err.0,
+            },
+            None => event,
+        };
+
+        let (sender, receiver) = unbounded_channel();
+        // the receiver cannot have been dropped yet, as it is still owned by this scope
+        let _ = sender.send(event);
+        queues.insert(id.clone(), sender);
+        spawn(self.clone().process_queue(id, receiver).in_current_span());
+    }
+
+    /// Handle the events of a single container in order until its queue has
+    /// been idle for some time
+    async fn process_queue(
+        self: Arc<Self>,
+        id: Arc<str>,
+        mut receiver: UnboundedReceiver<EventMessage>,
+    ) {
+        loop {
+            let event =
+                match timeout(Duration::from_secs(QUEUE_IDLE_TIMEOUT), receiver.recv()).await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => return,
+                    Err(_) => {
+                        // new events are only sent while holding this lock, so it is safe to remove
+                        // the queue if it is still empty
+                        let mut queues = self.queues.lock().await;
+                        if let Ok(event) = receiver.try_recv() {
+                            event
+                        } else {
+                            queues.remove(&id);
+                            return;
+                        }
+                    }
+                };
+            self.handle_raw_event(event).await;
+        }
+    }
+
+    /// Handle a raw event from the docker event stream as soon as the
+    /// concurrency limit permits it. All logs emitted while handling the event
+    /// are attached to a span that identifies the event and its container.
+    async fn handle_raw_event(&self, event: EventMessage) {
+        let span = event_span(&event);
+        async {
+            let _permit = if let Ok(permit) = self.semaphore.try_acquire() {
+                permit
+            } else {
+                warn!(
+                    queued = self.pending.load(Ordering::Relaxed),
+                    "event concurrency limit reached"
+                );
+                match self.semaphore.acquire().await {
+                    Ok(permit) => permit,
+                    Err(err) => {
+                        error!(error = %err, "could not acquire event permit");
+                        return;
+                    }
+                }
+            };
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+
+            let result = match ContainerEvent::try_from(event) {
+                Ok(event) => {
+                    self.metrics.count_event(&event);
+                    timeout(self.timeout, self.handle_event(event)).await
+                }
+                Err(err) => Ok(Err(err)),
+            };
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+                    counter!(metrics::EVENT_FAILURES, "reason" => "error").increment(1);
+                    error!(error = %format_args!("{err:#}"), "could not handle event");
+                }
+                Err(_) => {
+                    self.metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+                    counter!(metrics::EVENT_FAILURES, "reason" => "timeout").increment(1);
+                    error!("failed to handle event in time");
                 }
             }
+        }
+        .instrument(span)
+        .await;
+    }
+
+    /// Handle an event from the docker daemon
+    async fn handle_event(&self, event: ContainerEvent) -> Result<()> {
+        match event {
+            ContainerEvent::Started(container) => {
+                info!("container started");
+                self.container_manager
+                    .container_started(container.id)
+                    .await
+                    .context("failed to handle container start")
+            }
+            ContainerEvent::Died {
+                container,
+                exit_code,
+            } => {
+                info!(exit_code, "container died");
+                self.container_manager
+                    .container_died(container.id)
+                    .await
+                    .context("failed to handle container death")
+            }
+            ContainerEvent::Updated(container) => {
+                info!("container updated");
+                self.container_manager
+                    .container_updated(container.id)
+                    .await
+                    .context("failed to handle container update")
+            }
+            ContainerEvent::HealthChanged { container, health } => {
+                debug!(health = ?health, "health status update");
+                self.container_manager
+                    .container_health_update(container.id, health)
+                    .await
+                    .context("failed to handle container health update")
+            }
 
             // ignore all other events
-            _ => Ok(()),
+            ContainerEvent::Destroyed | ContainerEvent::Other => Ok(()),
         }
     }
+}
 
-    /// Handle a container start event
-    async fn handle_container_start(&self, event: EventMessage) -> Result<()> {
-        let id = get_container_id(&event)?.clone();
-        info!("container started: {:?}", id);
-        self.container_manager.container_started(id).await?;
-        Ok(())
+/// Create the span in which an event is handled, carrying the type and action
+/// of the event and the short id and name of its container
+fn event_span(event: &EventMessage) -> Span {
+    let actor = event.actor.as_ref();
+    let id = actor.and_then(|actor| actor.id.as_deref()).map(short_id);
+    let name = actor
+        .and_then(|actor| actor.attributes.as_ref())
+        .and_then(|attributes| attributes.get("name"))
+        .map(String::as_str);
+    info_span!(
+        "event",
+        r#type = event.type_.as_deref(),
+        action = event.action.as_deref(),
+        container.id = id,
+        container.name = name,
+    )
+}
+
+/// Build the options for subscribing to the docker event stream, optionally
+/// replaying all events since the given unix timestamp
+fn events_opts(use_filters: bool, since: Option<i64>) -> EventsOpts {
+    let mut builder = EventsOpts::builder();
+    if let Some(since) = since {
+        builder = builder.since(since);
     }
+    if use_filters {
+        let mut filters = vec![EventFilter::Type(EventFilterType::Container)];
+        filters.extend(
+            EVENT_ACTIONS
+                .iter()
+                .map(|&action| EventFilter::Event(action.to_owned())),
+        );
+        builder = builder.filter(filters);
+    }
+    builder.build()
+}
 
-    /// Handle a container die event
-    async fn handle_container_die(&self, event: EventMessage) -> Result<()> {
-        let id = get_container_id(&event)?;
-        info!("container died: {:?}", id);
-        self.container_manager.container_died(id).await?;
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use reqwest::Client;
+    use serde_json::json;
+    use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+    use super::*;
+    use crate::{
+        docker::fake::{self, FakeRuntime},
+        healthchecks::Healthchecks,
+    };
+
+    /// Start a healthchecks server that accepts all pings
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        server
     }
 
-    /// Handle a container health update event
-    async fn handle_container_health_status(
-        &self,
-        event: EventMessage,
-        status: &str,
-    ) -> Result<()> {
-        let id = get_container_id(&event)?.clone();
-        let status = match status {
-            "healthy" => Health::Healthy,
-            "unhealthy" => Health::Unhealthy,
-            "starting" => Health::Starting,
-            status => {
-                bail!("container {} has invalid health status: {}", id, status);
-            }
+    /// Return the paths of the pings received by the server in order
+    async fn pings(server: &MockServer) -> Vec<String> {
+        server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|request| request.url.path().to_owned())
+            .collect()
+    }
+
+    /// Add a healthy container to the runtime which is pinged at the given
+    /// path of the server
+    fn insert_container(runtime: &FakeRuntime, server: &MockServer, id: &str) {
+        let url = format!("{}/{id}", server.uri());
+        runtime.insert(fake::container(
+            id,
+            id,
+            &[("healthchecks.url", &url)],
+            Some("healthy"),
+        ));
+    }
+
+    /// Create a docker event with the given action for a container
+    fn event(action: &str, id: &str) -> EventMessage {
+        serde_json::from_value(json!({
+            "Type": "container",
+            "Action": action,
+            "Actor": { "ID": id, "Attributes": { "name": id } },
+            "time": 1_700_000_000,
+        }))
+        .unwrap()
+    }
+
+    /// Create an event handler for the containers of the given runtime
+    fn handler(runtime: &FakeRuntime, config: &Config) -> Arc<EventHandler> {
+        let healthchecks = Arc::new(Healthchecks::new(Client::new(), config));
+        let manager = ContainerManager::new(runtime.clone(), healthchecks, config, None);
+        Arc::new(EventHandler::new(Arc::new(manager), config))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_streams_are_up_once_the_daemon_responds() {
+        let runtime = FakeRuntime::default();
+        runtime.push_event_stream(Vec::new(), Some(Duration::ZERO));
+        runtime.set_unreachable(true);
+        let handler = handler(&runtime, &Config::default());
+        spawn(handler.clone().handle_events(runtime.clone()));
+
+        // the resubscribed stream stays idle, and the daemon does not respond
+        sleep(Duration::from_secs(5)).await;
+        assert_eq!(runtime.subscriptions().len(), 2);
+        assert!(handler.stream_status().down_since.is_some());
+
+        runtime.set_unreachable(false);
+        sleep(Duration::from_secs(5)).await;
+        let status = handler.stream_status();
+        assert_eq!(status.down_since, None);
+        assert_eq!(status.reconnects, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnects_are_notified_once_the_stream_is_up() {
+        let runtime = FakeRuntime::default();
+        runtime.push_event_stream(Vec::new(), Some(Duration::ZERO));
+        runtime.set_unreachable(true);
+        let handler = handler(&runtime, &Config::default());
+        spawn(handler.clone().handle_events(runtime.clone()));
+
+        let reconnect = timeout(Duration::from_secs(5), handler.wait_for_reconnect()).await;
+        assert!(reconnect.is_err());
+
+        runtime.set_unreachable(false);
+        timeout(Duration::from_secs(5), handler.wait_for_reconnect())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resubscribe_delays_back_off_and_reset_after_stable_streams() {
+        let runtime = FakeRuntime::default();
+        for _ in 0..4 {
+            runtime.push_event_stream(Vec::new(), Some(Duration::ZERO));
+        }
+        runtime.push_event_stream(Vec::new(), Some(STABLE_STREAM_DURATION));
+        let handler = handler(&runtime, &Config::default());
+        spawn(handler.clone().handle_events(runtime.clone()));
+        sleep(Duration::from_secs(120)).await;
+
+        let times: Vec<_> = runtime
+            .subscriptions()
+            .into_iter()
+            .map(|(time, _)| time)
+            .collect();
+        assert_eq!(times.len(), 6);
+        // the delay doubles with each stream that ends right away, and is
+        // jittered between half of and the full delay
+        let mut delay = MIN_RESUBSCRIBE_DELAY;
+        for pair in times[..5].windows(2) {
+            let gap = pair[1] - pair[0];
+            assert!(
+                gap >= delay / 2 && gap <= delay,
+                "{gap:?} not within {delay:?}"
+            );
+            delay *= 2;
+        }
+        // the delay is reset after a stream that has been stable
+        let gap = times[5] - (times[4] + STABLE_STREAM_DURATION);
+        assert!(
+            gap >= MIN_RESUBSCRIBE_DELAY / 2 && gap <= MIN_RESUBSCRIBE_DELAY,
+            "{gap:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn events_are_ordered_per_container_and_concurrent_across_containers() {
+        let server = server().await;
+        let runtime = FakeRuntime::default();
+        insert_container(&runtime, &server, "a");
+        insert_container(&runtime, &server, "b");
+        // handling the start of the first container takes a while
+        runtime.delay_inspects("a", Duration::from_millis(300));
+        let config = Config {
+            ping_retries: 0,
+            ..Config::default()
         };
+        let handler = handler(&runtime, &config);
+
+        for (action, id) in [("start", "a"), ("die", "a"), ("start", "b")] {
+            handler.dispatch_event(event(action, id)).await;
+        }
+        sleep(Duration::from_secs(1)).await;
 
-        info!("health status update: {} {:?}", id, status);
-        self.container_manager
-            .container_health_update(id, status)
-            .await?;
-        Ok(())
+        // the die event waits for the start event of the same container, while the
+        // other container is handled right away
+        assert_eq!(pings(&server).await, ["/b", "/a", "/a/fail"]);
     }
-}
 
-/// Extract the container id from a docker event
-fn get_container_id(event: &EventMessage) -> Result<&String> {
-    event
-        .actor
-        .as_ref()
-        .ok_or_else(|| anyhow!("event has no actor"))?
-        .id
-        .as_ref()
-        .ok_or_else(|| anyhow!("event actor is empty"))
+    #[tokio::test(start_paused = true)]
+    async fn event_stream_is_filtered_on_the_server() {
+        let runtime = FakeRuntime::default();
+        let handler = handler(&runtime, &Config::default());
+        spawn(handler.handle_events(runtime.clone()));
+        sleep(Duration::from_secs(1)).await;
+
+        let subscriptions = runtime.subscriptions();
+        let url = format!("http://docker/events?{}", subscriptions[0].1);
+        let url = reqwest::Url::parse(&url).unwrap();
+        let (_, filters) = url.query_pairs().find(|(key, _)| key == "filters").unwrap();
+        let filters: HashMap<String, Vec<String>> = serde_json::from_str(&filters).unwrap();
+        assert_eq!(filters.len(), 2);
+        assert_eq!(filters["type"], ["container"]);
+        let mut actions = filters["event"].clone();
+        actions.sort_unstable();
+        let mut expected = EVENT_ACTIONS.to_vec();
+        expected.sort_unstable();
+        assert_eq!(actions, expected);
+
+        // the fallback subscribes without any filters
+        assert_eq!(events_opts(false, None).serialize(), None);
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn events_are_logged_with_structured_fields() {
+        let server = server().await;
+        let runtime = FakeRuntime::default();
+        insert_container(&runtime, &server, "0123456789abcdef");
+        let handler = handler(&runtime, &Config::default());
+
+        handler
+            .dispatch_event(event("start", "0123456789abcdef"))
+            .await;
+        sleep(Duration::from_millis(500)).await;
+
+        // the container and the ping url are fields of the spans, not part of the
+        // messages, and the secret part of the url is redacted
+        let span = r#"event{type="container" action="start" container.id="0123456789ab" "#;
+        logs_assert(|lines| {
+            let started = lines
+                .iter()
+                .find(|line| line.ends_with(": container started"))
+                .ok_or("no start logged")?;
+            let ping = lines
+                .iter()
+                .find(|line| line.ends_with(": sending ping"))
+                .ok_or("no ping logged")?;
+            if !started.contains(span) || !ping.contains(span) {
+                return Err(format!("container fields missing: {started}\n{ping}"));
+            }
+            if !ping.contains("url=http://127.0.0.1/<redacted> health=Healthy") {
+                return Err(format!("ping fields missing: {ping}"));
+            }
+            Ok(())
+        });
+    }
 }
@@ -1,78 +1,911 @@
 //! Healthchecks.io interface
 
-use std::{collections::HashSet, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::Result;
-use reqwest::{Client, IntoUrl};
-use tokio::{sync::RwLock, time::sleep};
-use tracing::{debug, warn};
+use ::metrics::{counter, histogram};
+use anyhow::{Context, Result};
+use reqwest::{header::CONTENT_TYPE, Client, IntoUrl, Response};
+use tokio::{
+    sync::{Mutex, Notify, RwLock},
+    time::sleep,
+};
+use tracing::{debug, field, info, info_span, warn, Instrument, Span};
 
-use crate::container_manager::Health;
+use crate::{
+    config::{Config, IpVersion, PingMode},
+    container_manager::Health,
+    dns::PingResolver,
+    dump::{redact_url, PingAttempt},
+    metrics,
+    state::{self, CheckState, State},
+};
+
+/// Delay between retries of failed healthcheck pings, unless the ping
+/// timeout is too short for it
+pub const PING_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Minimum size of ping bodies, which leaves room for the truncation marker
+pub const MIN_PING_BODY_LIMIT: usize = 64;
+
+/// Time of a ping attempt and its error, if it failed
+type Attempt = (SystemTime, Option<String>);
 
 /// Healthchecks.io interface
 pub struct Healthchecks {
+    /// Http client used for all pings, which keeps connections to the
+    /// healthchecks server alive
+    client: Client,
+
     /// Number of retries for failed healthcheck pings
-    ping_retries: u8,
+    ping_retries: AtomicU8,
+
+    /// Delay between retries of failed healthcheck pings
+    retry_delay: std::sync::RwLock<Duration>,
+
+    /// Maximum size of ping bodies in bytes
+    ping_body_limit: AtomicUsize,
+
+    /// Suffixes of the ping urls for each health status
+    ping_suffixes: std::sync::RwLock<PingSuffixes>,
 
     /// Set of ping urls that last received a starting ping
-    starting: RwLock<HashSet<String>>,
+    starting: RwLock<HashSet<Arc<str>>>,
+
+    /// Whether pings are only sent if the health status changed
+    transitions_only: AtomicBool,
+
+    /// Whether pings are only logged instead of being sent
+    dry_run: bool,
+
+    /// Mapping from ping urls to the last successfully reported health status
+    /// and the time of this ping
+    reported: RwLock<HashMap<Arc<str>, (Health, SystemTime)>>,
+
+    /// Mapping from ping urls to the time of the last ping attempt and its
+    /// error, if it failed
+    attempts: RwLock<HashMap<Arc<str>, Attempt>>,
+
+    /// Notified whenever a ping has been sent successfully
+    pinged: Notify,
+
+    /// Number of pings sent successfully since the last summary
+    sent: AtomicU64,
+
+    /// Number of failed pings since the last summary
+    failed: AtomicU64,
+
+    /// Mapping from ping urls to locks which ensure that only one ping
+    /// sequence per url is in flight
+    url_locks: Mutex<HashMap<Arc<str>, Arc<UrlLock>>>,
+
+    /// Last generation handed out by `next_generation`
+    generation: AtomicU64,
+}
+
+/// Suffixes appended to the ping urls for each health status
+struct PingSuffixes {
+    /// Suffix of healthy pings
+    healthy: String,
+
+    /// Suffix of unhealthy pings
+    unhealthy: String,
+
+    /// Suffix of starting pings
+    starting: String,
+}
+
+impl From<&Config> for PingSuffixes {
+    fn from(config: &Config) -> Self {
+        Self {
+            healthy: config.ping_suffix_healthy.clone(),
+            unhealthy: config.ping_suffix_unhealthy.clone(),
+            starting: config.ping_suffix_starting.clone(),
+        }
+    }
+}
+
+/// Lock for the pings to a single url
+#[derive(Default)]
+struct UrlLock {
+    /// Held while a ping sequence (including retries) is in flight
+    in_flight: Mutex<()>,
+
+    /// Number of ping requests for this url so far
+    requests: AtomicU64,
+
+    /// Generation and number of the request that is sent next. A request
+    /// replaces it if its generation is not older.
+    newest: std::sync::Mutex<(u64, u64)>,
 }
 
 impl Healthchecks {
     /// Create a new Healthchecks.io interface
-    pub fn new(ping_retries: u8) -> Self {
+    #[must_use]
+    pub fn new(client: Client, config: &Config) -> Self {
         Self {
-            ping_retries,
+            client,
+            ping_retries: AtomicU8::new(config.ping_retries),
+            retry_delay: std::sync::RwLock::new(retry_delay(config)),
+            ping_body_limit: AtomicUsize::new(config.ping_body_limit),
+            ping_suffixes: std::sync::RwLock::new(config.into()),
             starting: RwLock::new(HashSet::new()),
+            transitions_only: AtomicBool::new(config.ping_mode != PingMode::Interval),
+            dry_run: config.dry_run,
+            reported: RwLock::new(HashMap::new()),
+            attempts: RwLock::new(HashMap::new()),
+            pinged: Notify::new(),
+            sent: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            url_locks: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Return a new generation, which is greater than all generations
+    /// returned before. Generations order the observations of health statuses
+    /// and the pings derived from them without relying on the clocks of the
+    /// docker daemons.
+    pub fn next_generation(&self) -> u64 {
+        self.generation.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Change the number of retries, the ping mode, the body limit and the
+    /// url suffixes of future pings
+    pub fn reconfigure(&self, config: &Config) {
+        self.ping_retries
+            .store(config.ping_retries, Ordering::Relaxed);
+        *self
+            .retry_delay
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = retry_delay(config);
+        self.ping_body_limit
+            .store(config.ping_body_limit, Ordering::Relaxed);
+        *self
+            .ping_suffixes
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = config.into();
+        self.transitions_only
+            .store(config.ping_mode != PingMode::Interval, Ordering::Relaxed);
+    }
+
+    /// Return the number of sent and failed pings since the last call and
+    /// reset the counters
+    pub fn take_ping_counts(&self) -> (u64, u64) {
+        (
+            self.sent.swap(0, Ordering::Relaxed),
+            self.failed.swap(0, Ordering::Relaxed),
+        )
+    }
+
+    /// Wait until a ping has been sent successfully
+    pub async fn wait_for_ping(&self) {
+        self.pinged.notified().await;
+    }
+
+    /// Take a snapshot of the pings that have been sent
+    pub async fn snapshot(&self) -> State {
+        let starting = self.starting.read().await;
+        let checks = self
+            .reported
+            .read()
+            .await
+            .iter()
+            .map(|(url, &(health, time))| {
+                let check = CheckState {
+                    health,
+                    starting: starting.contains(url),
+                    last_ping: state::to_unix(time),
+                };
+                (url.to_string(), check)
+            })
+            .collect();
+        State {
+            saved: state::unix_time(),
+            checks,
+        }
+    }
+
+    /// Return the outcome of the last ping attempt of each url
+    pub async fn attempts(&self) -> HashMap<String, PingAttempt> {
+        self.attempts
+            .read()
+            .await
+            .iter()
+            .map(|(url, (time, error))| {
+                let attempt = PingAttempt {
+                    time: state::to_unix(*time),
+                    error: error.clone(),
+                };
+                (url.to_string(), attempt)
+            })
+            .collect()
+    }
+
+    /// Forget the ping attempts and locks of all urls that are no longer
+    /// monitored. Locks are kept while pings to their url are in flight or
+    /// waiting, so that these pings stay serialized.
+    pub async fn retain(&self, urls: &HashSet<Arc<str>>) {
+        self.attempts
+            .write()
+            .await
+            .retain(|url, _| urls.contains(url));
+        self.url_locks
+            .lock()
+            .await
+            .retain(|url, lock| urls.contains(url) || Arc::strong_count(lock) > 1);
+    }
+
+    /// Restore the pings that have been sent from a snapshot
+    pub async fn restore(&self, snapshot: State) {
+        let mut starting = self.starting.write().await;
+        let mut reported = self.reported.write().await;
+        for (url, check) in snapshot.checks {
+            let url = Arc::<str>::from(url);
+            if check.starting {
+                starting.insert(url.clone());
+            }
+            let time = UNIX_EPOCH + Duration::from_secs(check.last_ping);
+            reported.insert(url, (check.health, time));
         }
     }
 
-    /// Ping a given healthchecks url
-    pub async fn ping(&self, url: &str, health: &Health) -> Result<()> {
+    /// Ping a given healthchecks url with a health status of the given
+    /// generation. Depending on the ping mode, the ping is skipped if the
+    /// health status has not changed since the last ping.
+    pub async fn ping(
+        &self,
+        url: &Arc<str>,
+        health: &Health,
+        body: &str,
+        generation: u64,
+    ) -> Result<()> {
+        self.send(url, health, body, generation, false).await
+    }
+
+    /// Ping a given healthchecks url with a health status of the given
+    /// generation, even if the health status has not changed
+    pub async fn keepalive(
+        &self,
+        url: &Arc<str>,
+        health: &Health,
+        body: &str,
+        generation: u64,
+    ) -> Result<()> {
+        self.send(url, health, body, generation, true).await
+    }
+
+    /// Ping a given healthchecks url. Only one ping sequence per url is in
+    /// flight at any time, and pings that are still waiting for the previous
+    /// sequence to finish are superseded by later ones of the same or a newer
+    /// generation. Pings of an older generation than a ping that has already
+    /// been requested for the url are dropped.
+    async fn send(
+        &self,
+        url: &Arc<str>,
+        health: &Health,
+        body: &str,
+        generation: u64,
+        force: bool,
+    ) -> Result<()> {
+        let lock = self
+            .url_locks
+            .lock()
+            .await
+            .entry(url.clone())
+            .or_default()
+            .clone();
+        let request = lock.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        {
+            let mut newest = lock.newest.lock().unwrap_or_else(PoisonError::into_inner);
+            if generation >= newest.0 {
+                *newest = (generation, request);
+            }
+        }
+        let _in_flight = lock.in_flight.lock().await;
+        let (newest_generation, newest_request) =
+            *lock.newest.lock().unwrap_or_else(PoisonError::into_inner);
+        if newest_request != request {
+            if newest_generation > generation {
+                debug!(url = %redact_url(url), health = ?health, "dropping outdated ping");
+            } else {
+                debug!(url = %redact_url(url), health = ?health, "dropping superseded ping");
+            }
+            return Ok(());
+        }
+
+        if !force
+            && self.transitions_only.load(Ordering::Relaxed)
+            && self.reported.read().await.get(url).map(|(last, _)| last) == Some(health)
+        {
+            debug!(url = %redact_url(url), health = ?health, "health status unchanged, not pinging");
+            return Ok(());
+        }
+
         // avoid sending multiple consecutive starting pings to the same url
         let mut starting = self.starting.write().await;
-        if starting.contains(url) {
+        if starting.contains(&**url) {
             if health == &Health::Starting {
-                debug!("not sending another starting ping to healthchecks for {url}");
+                debug!(url = %redact_url(url), "starting ping already sent, not pinging");
                 return Ok(());
             }
-            starting.remove(url);
+            starting.remove(&**url);
         } else if health == &Health::Starting {
-            starting.insert(url.to_owned());
+            starting.insert(url.clone());
         }
         drop(starting);
 
-        debug!("sending {health:?} ping to healthchecks for {url}");
+        let span = info_span!(
+            "ping",
+            url = %redact_url(url),
+            health = ?health,
+            http.status_code = field::Empty,
+            retries = field::Empty,
+        );
+        self.send_with_retries(url, health, body)
+            .instrument(span)
+            .await
+    }
+
+    /// Send a ping to a given healthchecks url and retry if it fails
+    async fn send_with_retries(&self, url: &Arc<str>, health: &Health, body: &str) -> Result<()> {
+        debug!("sending ping");
 
         // create url from given health status
-        let url = match health {
-            Health::Healthy => url.to_owned(),
-            Health::Unhealthy => format!("{url}/fail"),
-            Health::Starting => format!("{url}/start"),
+        let ping_url = {
+            let suffixes = self
+                .ping_suffixes
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            let suffix = match health {
+                Health::Healthy => &suffixes.healthy,
+                Health::Unhealthy => &suffixes.unhealthy,
+                Health::Starting => &suffixes.starting,
+            };
+            append_suffix(url, suffix)
         };
 
+        // healthchecks.io rejects oversized bodies, which would lose the ping
+        let body = truncate_body(body, self.ping_body_limit.load(Ordering::Relaxed));
+
         // send the ping and retry if it fails
-        let mut retries = self.ping_retries;
-        while let Err(err) = try_ping(&url).await {
+        let max_retries = self.ping_retries.load(Ordering::Relaxed);
+        let mut retries = max_retries;
+        while let Err(err) = try_ping(&self.client, &ping_url, &body, false, self.dry_run).await {
             if retries == 0 {
+                Span::current().record("retries", max_retries);
                 // return the last error if all retries are exhausted
-                return Err(err.context(format!("healthchecks ping to {url} failed")));
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                counter!(metrics::PINGS, "result" => "failure").increment(1);
+                self.attempts
+                    .write()
+                    .await
+                    .insert(url.clone(), (SystemTime::now(), Some(format!("{err:#}"))));
+                return Err(err.context(format!("ping to {} failed", redact_url(url))));
             }
             retries -= 1;
-            warn!("healthchecks ping to {url} failed, retrying...");
-            sleep(Duration::from_secs(2)).await;
+            counter!(metrics::PING_RETRIES).increment(1);
+            warn!(
+                error = %format_args!("{err:#}"),
+                retries,
+                "ping failed, retrying"
+            );
+            sleep(self.retry_delay()).await;
         }
+        Span::current().record("retries", max_retries - retries);
+
+        self.reported
+            .write()
+            .await
+            .insert(url.clone(), (*health, SystemTime::now()));
+        self.attempts
+            .write()
+            .await
+            .insert(url.clone(), (SystemTime::now(), None));
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        counter!(metrics::PINGS, "result" => "success").increment(1);
+        self.pinged.notify_one();
+        Ok(())
+    }
 
+    /// Return the delay between retries of failed pings
+    fn retry_delay(&self) -> Duration {
+        *self
+            .retry_delay
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Post a json summary to the given url and retry if it fails
+    pub async fn post_summary(&self, url: &str, body: &str) -> Result<()> {
+        let max_retries = self.ping_retries.load(Ordering::Relaxed);
+        let mut retries = max_retries;
+        while let Err(err) = try_ping(&self.client, &url, body, true, self.dry_run).await {
+            if retries == 0 {
+                return Err(err.context("summary request failed"));
+            }
+            retries -= 1;
+            warn!(
+                error = %format_args!("{err:#}"),
+                retries,
+                "summary request failed, retrying"
+            );
+            sleep(self.retry_delay()).await;
+        }
+        debug!("summary sent");
         Ok(())
     }
 }
 
-/// Send a post request to the given url
-async fn try_ping(url: &impl IntoUrl) -> Result<()> {
-    Client::new()
-        .post(url.as_str())
-        .send()
-        .await?
-        .error_for_status()?;
+/// Create the http client used for all pings. The configured hosts are
+/// resolved to static addresses, other hosts are resolved by the system
+/// resolver, whose results are restricted to the configured ip version and
+/// which optionally falls back to the last successful resolution.
+pub fn ping_client(config: &Config) -> Result<Client> {
+    let mut builder = Client::builder()
+        .pool_idle_timeout(config.ping_pool_idle_timeout)
+        .pool_max_idle_per_host(config.ping_pool_max_idle_per_host)
+        .tcp_keepalive(Some(config.ping_tcp_keepalive).filter(|keepalive| !keepalive.is_zero()));
+    if config.ping_dns_cache || config.ping_ip_version != IpVersion::Auto {
+        builder = builder.dns_resolver(Arc::new(PingResolver::new(
+            config.ping_ip_version,
+            config.ping_dns_cache,
+        )));
+    }
+    let mut overrides = HashMap::<_, Vec<_>>::new();
+    for (host, addr) in config.ping_resolutions()? {
+        info!(host, %addr, "resolving ping host statically");
+        overrides.entry(host).or_default().push(addr);
+    }
+    for (host, addrs) in overrides {
+        builder = builder.resolve_to_addrs(&host, &addrs);
+    }
+    builder
+        .build()
+        .context("failed to create healthchecks http client")
+}
+
+/// Append a suffix to a ping url. The path part of the suffix is appended to
+/// the path of the url and its query part (starting with `?` or `&`) is added
+/// to the query of the url, e.g. `/fail?rid=1` turns `https://host/uuid?a=b`
+/// into `https://host/uuid/fail?a=b&rid=1`.
+fn append_suffix(url: &str, suffix: &str) -> String {
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let (path_suffix, query_suffix) = match suffix.find(['?', '&']) {
+        Some(index) => (&suffix[..index], &suffix[index + 1..]),
+        None => (suffix, ""),
+    };
+    let mut result = format!("{path}{path_suffix}");
+    let params: Vec<_> = [query, query_suffix]
+        .into_iter()
+        .filter(|params| !params.is_empty())
+        .collect();
+    if !params.is_empty() {
+        result.push('?');
+        result.push_str(&params.join("&"));
+    }
+    if let Some(fragment) = fragment {
+        result.push('#');
+        result.push_str(fragment);
+    }
+    result
+}
+
+/// Return the delay between retries of failed pings. For short ping timeouts,
+/// the delay is shortened so that the delays of all retries take at most half
+/// of the ping timeout, leaving the other half for the requests.
+fn retry_delay(config: &Config) -> Duration {
+    let retries = u32::from(config.ping_retries) + 1;
+    PING_RETRY_DELAY.min(config.ping_timeout / (2 * retries))
+}
+
+/// Truncate a ping body to at most `limit` bytes, replacing the end of the
+/// body with a marker stating the number of removed bytes. The body is only
+/// cut at a character boundary, so it remains valid utf-8.
+fn truncate_body(body: &str, limit: usize) -> Cow<'_, str> {
+    if body.len() <= limit {
+        return Cow::Borrowed(body);
+    }
+    // the marker may become shorter as more bytes are removed, so its length
+    // for the largest possible number of removed bytes is reserved
+    let marker_len = format!("...[truncated {} bytes]", body.len()).len();
+    let mut end = limit.saturating_sub(marker_len);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!(
+        "{}...[truncated {} bytes]",
+        &body[..end],
+        body.len() - end
+    ))
+}
+
+/// Send a post request with the given (json) body to the given url using the
+/// given client. In dry run mode, the request is only logged. Errors are
+/// prefixed with their reason and do not contain the url.
+async fn try_ping(
+    client: &Client,
+    url: &impl IntoUrl,
+    body: &str,
+    json: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let mut request = client.post(url.as_str()).body(body.to_owned());
+    if json {
+        request = request.header(CONTENT_TYPE, "application/json");
+    }
+    let request = request.build().map_err(reqwest::Error::without_url)?;
+    if dry_run {
+        let headers: Vec<_> = request
+            .headers()
+            .keys()
+            .map(|name| format!("{name}: <redacted>"))
+            .collect();
+        info!(
+            method = %request.method(),
+            url = %redact_url(request.url().as_str()),
+            headers = %headers.join(", "),
+            body,
+            "dry run, not sending ping"
+        );
+        return Ok(());
+    }
+    let start = Instant::now();
+    let result = client.execute(request).await;
+    if let Ok(response) = &result {
+        Span::current().record("http.status_code", response.status().as_u16());
+    }
+    let result = result.and_then(Response::error_for_status);
+    histogram!(metrics::PING_DURATION).record(start.elapsed());
+    if let Err(err) = result {
+        let reason = if err.is_timeout() {
+            "timeout"
+        } else if err.is_connect() {
+            "connect"
+        } else if err.is_status() {
+            "status"
+        } else {
+            "other"
+        };
+        counter!(metrics::PING_FAILURES, "reason" => reason).increment(1);
+        return Err(anyhow::Error::new(err.without_url()).context(reason));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    /// Start a healthchecks server that accepts all pings
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Return the paths of the pings received by the server in order
+    async fn pings(server: &MockServer) -> Vec<String> {
+        server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(|request| request.url.path().to_owned())
+            .collect()
+    }
+
+    /// Create a healthchecks interface with the given number of retries
+    fn healthchecks(ping_retries: u8) -> Healthchecks {
+        let config = Config {
+            ping_retries,
+            ..Config::default()
+        };
+        Healthchecks::new(Client::new(), &config)
+    }
+
+    #[tokio::test]
+    async fn suffixes_are_appended_per_health_status() {
+        let server = server().await;
+        let url = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = healthchecks(0);
+
+        for health in [Health::Healthy, Health::Unhealthy, Health::Starting] {
+            let generation = healthchecks.next_generation();
+            healthchecks
+                .ping(&url, &health, "", generation)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            pings(&server).await,
+            ["/check", "/check/fail", "/check/start"]
+        );
+    }
+
+    #[tokio::test]
+    async fn starting_pings_are_only_sent_once() {
+        let server = server().await;
+        let url = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = healthchecks(0);
+
+        for health in [Health::Starting, Health::Starting, Health::Healthy] {
+            let generation = healthchecks.next_generation();
+            healthchecks
+                .keepalive(&url, &health, "", generation)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(pings(&server).await, ["/check/start", "/check"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_pings_are_retried() {
+        let server = MockServer::start().await;
+        Mock::given(path("/check/fail"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(4)
+            .mount(&server)
+            .await;
+        let url: Arc<str> = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = healthchecks(3);
+
+        let start = tokio::time::Instant::now();
+        let err = healthchecks
+            .ping(&url, &Health::Unhealthy, "", healthchecks.next_generation())
+            .await
+            .unwrap_err();
+
+        // paused time also advances while waiting for the mock server, so the
+        // retry delays are only a lower bound of the elapsed time
+        assert!(start.elapsed() >= PING_RETRY_DELAY * 3);
+        assert!(format!("{err:#}").contains(&redact_url(&url)));
+        server.verify().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    #[tracing_test::traced_test]
+    async fn failed_pings_are_logged_with_structured_fields() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        let url: Arc<str> = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = healthchecks(1);
+
+        healthchecks
+            .ping(&url, &Health::Unhealthy, "", healthchecks.next_generation())
+            .await
+            .unwrap_err();
+
+        // the remaining retries and the error are fields of the constant message
+        logs_assert(|lines| {
+            let retry = lines
+                .iter()
+                .find(|line| line.contains(": ping failed, retrying "))
+                .ok_or("no retry logged")?;
+            let fields = [
+                "ping{url=http://127.0.0.1/<redacted> health=Unhealthy http.status_code=500}",
+                "error=status: HTTP status server error",
+                "retries=0",
+            ];
+            if fields.iter().any(|field| !retry.contains(field)) || retry.contains("/check") {
+                return Err(format!("unexpected fields: {retry}"));
+            }
+            Ok(())
+        });
+    }
+
+    #[tokio::test]
+    async fn ping_clients_use_the_configured_ip_version() {
+        let server = server().await;
+        // the mock server only listens on the ipv4 loopback address
+        let url: Arc<str> = format!("http://localhost:{}/check", server.address().port()).into();
+        for (ping_ip_version, reachable) in [(IpVersion::V4, true), (IpVersion::V6, false)] {
+            let config = Config {
+                ping_ip_version,
+                ping_retries: 0,
+                ..Config::default()
+            };
+            let healthchecks = Healthchecks::new(ping_client(&config).unwrap(), &config);
+            let result = healthchecks
+                .ping(&url, &Health::Healthy, "", healthchecks.next_generation())
+                .await;
+            assert_eq!(result.is_ok(), reachable, "{ping_ip_version:?}");
+        }
+    }
+
+    #[test]
+    fn retry_delays_fit_into_the_ping_timeout() {
+        assert_eq!(retry_delay(&Config::default()), PING_RETRY_DELAY);
+        for ping_retries in [0, 1, 5, u8::MAX] {
+            for ping_timeout in [100, 500, 999, 5000, 60_000] {
+                let config = Config {
+                    ping_retries,
+                    ping_timeout: Duration::from_millis(ping_timeout),
+                    ..Config::default()
+                };
+                let delay = retry_delay(&config);
+                assert!(delay <= PING_RETRY_DELAY);
+                assert!(delay * ping_retries.into() <= config.ping_timeout / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_bodies_fit_the_limit() {
+        for ch in ['é', '€', '😀'] {
+            for padding in 0..ch.len_utf8() {
+                let body = "a".repeat(padding) + &ch.to_string().repeat(100);
+                for limit in MIN_PING_BODY_LIMIT..MIN_PING_BODY_LIMIT + 8 {
+                    let truncated = truncate_body(&body, limit);
+                    assert!(truncated.len() <= limit, "{truncated:?} exceeds {limit}");
+                    let (kept, marker) = truncated.split_once("...").unwrap();
+                    assert!(body.starts_with(kept));
+                    assert_eq!(
+                        marker,
+                        format!("[truncated {} bytes]", body.len() - kept.len())
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn short_bodies_are_not_truncated() {
+        let body = "😀".repeat(16);
+        assert_eq!(truncate_body(&body, 64), body);
+    }
+
+    #[tokio::test]
+    async fn outdated_pings_are_dropped() {
+        let server = server().await;
+        let url = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = healthchecks(0);
+        let old = healthchecks.next_generation();
+        let new = healthchecks.next_generation();
+
+        healthchecks
+            .ping(&url, &Health::Unhealthy, "", new)
+            .await
+            .unwrap();
+        healthchecks
+            .ping(&url, &Health::Healthy, "", old)
+            .await
+            .unwrap();
+
+        assert_eq!(pings(&server).await, ["/check/fail"]);
+    }
+
+    /// Wait until the given number of pings has been requested for the url
+    async fn wait_for_requests(healthchecks: &Healthchecks, url: &Arc<str>, count: u64) {
+        loop {
+            let lock = healthchecks.url_locks.lock().await.get(url).cloned();
+            if lock.is_some_and(|lock| lock.requests.load(Ordering::SeqCst) >= count) {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_pings_are_superseded_by_newer_ones() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+            .mount(&server)
+            .await;
+        let url: Arc<str> = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = Arc::new(healthchecks(0));
+
+        // a starting and then an unhealthy ping wait for the slow healthy ping
+        let mut tasks = Vec::new();
+        for (count, health) in (1..).zip([Health::Healthy, Health::Starting, Health::Unhealthy]) {
+            let generation = healthchecks.next_generation();
+            tasks.push(tokio::spawn({
+                let (healthchecks, url) = (healthchecks.clone(), url.clone());
+                async move { healthchecks.ping(&url, &health, "", generation).await }
+            }));
+            wait_for_requests(&healthchecks, &url, count).await;
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(pings(&server).await, ["/check", "/check/fail"]);
+        let reported = healthchecks.reported.read().await;
+        assert_eq!(
+            reported.get(&url).map(|(health, _)| health),
+            Some(&Health::Unhealthy)
+        );
+    }
+
+    #[tokio::test]
+    async fn outdated_pings_do_not_supersede_newer_ones() {
+        let server = MockServer::start().await;
+        Mock::given(path("/check/start"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let url: Arc<str> = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = Arc::new(healthchecks(0));
+        let first = healthchecks.next_generation();
+        let old = healthchecks.next_generation();
+        let new = healthchecks.next_generation();
+
+        // a newer unhealthy ping and then an older healthy one wait for the
+        // slow starting ping
+        let in_flight = tokio::spawn({
+            let (healthchecks, url) = (healthchecks.clone(), url.clone());
+            async move { healthchecks.ping(&url, &Health::Starting, "", first).await }
+        });
+        wait_for_requests(&healthchecks, &url, 1).await;
+        let newer = tokio::spawn({
+            let (healthchecks, url) = (healthchecks.clone(), url.clone());
+            async move { healthchecks.ping(&url, &Health::Unhealthy, "", new).await }
+        });
+        wait_for_requests(&healthchecks, &url, 2).await;
+        healthchecks
+            .ping(&url, &Health::Healthy, "", old)
+            .await
+            .unwrap();
+        in_flight.await.unwrap().unwrap();
+        newer.await.unwrap().unwrap();
+
+        assert_eq!(pings(&server).await, ["/check/start", "/check/fail"]);
+    }
+
+    #[tokio::test]
+    async fn locks_of_pings_in_flight_are_retained() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(200)))
+            .mount(&server)
+            .await;
+        let url: Arc<str> = Arc::from(format!("{}/check", server.uri()));
+        let healthchecks = Arc::new(healthchecks(0));
+        let generation = healthchecks.next_generation();
+        let in_flight = tokio::spawn({
+            let (healthchecks, url) = (healthchecks.clone(), url.clone());
+            async move {
+                healthchecks
+                    .ping(&url, &Health::Healthy, "", generation)
+                    .await
+            }
+        });
+        wait_for_requests(&healthchecks, &url, 1).await;
+
+        healthchecks.retain(&HashSet::new()).await;
+        assert!(healthchecks.url_locks.lock().await.contains_key(&url));
+
+        in_flight.await.unwrap().unwrap();
+        healthchecks.retain(&HashSet::new()).await;
+        assert!(healthchecks.url_locks.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+//! Heartbeats of the main loops used to detect stalled loops
+
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Additional time a loop may take after its announced next tick before it is
+/// considered stalled
+const GRACE: Duration = Duration::from_secs(30);
+
+/// Heartbeat of a loop that announces when it will tick next
+#[derive(Default)]
+pub struct Heartbeat {
+    /// Time until which the next tick is expected (`None` if the loop is
+    /// idle and not expected to tick)
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl Heartbeat {
+    /// Record a tick and expect the next one within the given duration
+    pub fn beat(&self, next: Duration) {
+        *self.lock() = Some(Instant::now() + next + GRACE);
+    }
+
+    /// Record that the loop is idle, e.g. because it has been disabled
+    pub fn pause(&self) {
+        *self.lock() = None;
+    }
+
+    /// Return for how long the expected tick is overdue, if at all
+    pub fn overdue(&self) -> Option<Duration> {
+        let deadline = (*self.lock())?;
+        Instant::now().checked_duration_since(deadline)
+    }
+
+    /// Lock the deadline, ignoring poisoning as it cannot be left in an
+    /// inconsistent state
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        self.deadline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
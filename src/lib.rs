@@ -0,0 +1,1225 @@
+//! Healthchecks.io Integration for Docker Healthchecks
+//!
+//! The [`run`] function monitors the docker hosts of a [`Config`] until the
+//! process is asked to shut down. The building blocks are exported for
+//! programs which drive the monitoring themselves: a [`ContainerManager`]
+//! tracks the health of the containers on one docker host, an
+//! [`EventHandler`] keeps it up to date using the docker event stream and
+//! [`Healthchecks`] sends the pings.
+//!
+//! Logs use short, constant messages and attach variable data as structured
+//! fields with the following names:
+//! - `error`: an error including its causes (`error = %format_args!("{err:#}")`)
+//! - `container.id` and `container.name`: the short id and the name of a
+//!   container
+//! - `url`: a redacted ping url
+//! - `health`: a health status
+//!
+//! Logs emitted while handling a docker event inherit the container fields
+//! from the span of the event.
+
+#![forbid(unsafe_code)]
+#![warn(clippy::pedantic)]
+// errors are described in the doc comments of the functions returning them
+#![allow(clippy::missing_errors_doc)]
+#![warn(clippy::dbg_macro, clippy::use_debug)]
+#![warn(
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::unimplemented,
+    clippy::todo,
+    clippy::unreachable
+)]
+#![warn(
+    clippy::missing_docs_in_private_items,
+    clippy::self_named_module_files,
+    clippy::shadow_unrelated,
+    clippy::str_to_string,
+    clippy::wildcard_enum_match_arm
+)]
+
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{ensure, Context, Result};
+use docker_api::Docker;
+use futures_util::future::join_all;
+use metrics_exporter_prometheus::PrometheusHandle;
+use rand::Rng;
+use tokio::{
+    signal::{
+        ctrl_c,
+        unix::{signal, SignalKind},
+    },
+    spawn,
+    sync::watch,
+    time::{self, sleep, timeout},
+};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+pub use self::{
+    config::{Config, Overrides},
+    container_manager::{ContainerManager, Health},
+    docker::runtime::ContainerRuntime,
+    event_handler::EventHandler,
+    healthchecks::Healthchecks,
+};
+use self::{
+    config::{DockerHost, PingMode, RunMode},
+    heartbeat::Heartbeat,
+    summary::Summary,
+};
+
+pub mod config;
+pub mod container_manager;
+mod dns;
+pub mod docker;
+pub mod dump;
+mod event;
+pub mod event_handler;
+pub mod healthchecks;
+mod heartbeat;
+mod list;
+pub mod log_pattern;
+mod metrics;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod probe;
+pub mod reachability;
+mod startup;
+pub mod state;
+pub mod stats;
+mod status;
+mod summary;
+mod swarm;
+mod systemd;
+
+/// Delay for debouncing writes of the state file
+const STATE_SAVE_DELAY: Duration = Duration::from_secs(5);
+
+/// Interval in which the docker daemons are pinged to check whether they are
+/// reachable
+const DOCKER_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Duration after which a ping to a docker daemon is considered failed
+const DOCKER_PING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Container manager and event handler of a monitored docker host
+#[derive(Clone)]
+struct Endpoint {
+    /// Name of the docker host, if multiple hosts are monitored
+    name: Option<Arc<str>>,
+
+    /// Span of the logs that concern the docker host
+    span: Span,
+
+    /// Docker daemon interface
+    docker: Docker,
+
+    /// Manager of the containers on the docker host
+    containers: Arc<ContainerManager>,
+
+    /// Handler of the docker events of the docker host
+    events: Arc<EventHandler>,
+
+    /// Heartbeat of the periodic container fetch
+    fetch_loop: Arc<Heartbeat>,
+}
+
+impl Endpoint {
+    /// Describe a component of the docker host, naming the host if multiple
+    /// hosts are monitored
+    fn describe(&self, component: &str) -> String {
+        match &self.name {
+            Some(name) => format!("{component} of {name}"),
+            None => component.to_owned(),
+        }
+    }
+}
+
+/// Monitor the docker hosts of the given configuration until a SIGINT or
+/// SIGTERM is received, or fetch the containers and ping all healthcheck urls
+/// once in oneshot mode. On SIGHUP, the configuration is reloaded with the
+/// given overrides.
+pub async fn run(config: Config, overrides: Option<Overrides>) -> Result<()> {
+    let hosts = config.docker_hosts()?;
+
+    // restore the persisted state of the healthchecks checks
+    let healthchecks = create_healthchecks(&config)?;
+    if let Some(path) = &config.state_file {
+        if let Some(snapshot) = state::load(path, config.state_max_age).await {
+            info!("restoring state from {path}");
+            healthchecks.restore(snapshot).await;
+        }
+    }
+
+    // delay the startup by a random duration, so that instances which are
+    // started at the same time do not fetch and ping in lockstep
+    if !config.startup_jitter.is_zero() {
+        let delay = rand::thread_rng().gen_range(Duration::ZERO..=config.startup_jitter);
+        debug!("delaying startup by {}", humantime::format_duration(delay));
+        sleep(delay).await;
+    }
+
+    // connect to the docker daemons, waiting for them until the startup timeout
+    // expires, and create a container manager for each of them
+    let deadline = startup::Deadline::new(config.startup_timeout);
+    let endpoints = connect_endpoints(hosts, &config, deadline, &healthchecks).await?;
+
+    // skip event handling and the periodic tasks in oneshot mode
+    if config.mode == RunMode::Oneshot {
+        let result = oneshot(&endpoints, &healthchecks, &config).await;
+        if let Some(path) = &config.state_file {
+            save_state(&healthchecks, path).await;
+        }
+        return result;
+    }
+
+    // record metrics from the initial container fetch on, if they are served
+    let metrics = config
+        .status_addr
+        .is_some()
+        .then(metrics::install)
+        .transpose()?;
+
+    for endpoint in &endpoints {
+        start_endpoint(endpoint, &config, deadline, endpoints.len() > 1)
+            .instrument(endpoint.span.clone())
+            .await?;
+    }
+    systemd::notify("READY=1");
+    report_status(&endpoints).await;
+
+    // share the configuration with the background tasks, so that it can be
+    // reloaded on SIGHUP
+    let state_file = config.state_file.clone();
+    let (config_tx, config) = watch::channel(Arc::new(config));
+    let endpoints: Arc<[Endpoint]> = endpoints.into();
+    let heartbeat = Arc::new(Heartbeat::default());
+    spawn_background_tasks(&config, &endpoints, &healthchecks)?;
+    spawn_watchdog(&endpoints, &heartbeat);
+    spawn_reload_handler(overrides, config_tx, &endpoints, &healthchecks)?;
+    let current = config.borrow().clone();
+    let status =
+        start_status_server(&current, &endpoints, &healthchecks, metrics, &heartbeat).await?;
+
+    // stop pinging on shutdown and persist the final state
+    tokio::select! {
+        () = ping_loop(&endpoints, &healthchecks, config, &heartbeat) => {}
+        result = shutdown_signal() => {
+            result?;
+            info!("shutting down");
+        }
+    }
+    systemd::notify("STOPPING=1");
+    for endpoint in endpoints.iter() {
+        endpoint.containers.shutdown().await;
+    }
+    if let Some(status) = status {
+        status.shutdown().await;
+    }
+    if let Some(path) = &state_file {
+        save_state(&healthchecks, path).await;
+    }
+    Ok(())
+}
+
+/// Connect to the given docker hosts and create a container manager and event
+/// handler for each of them. If multiple hosts are configured, hosts that
+/// cannot be reached until the startup deadline are skipped.
+async fn connect_endpoints(
+    hosts: Vec<(Option<String>, DockerHost)>,
+    config: &Config,
+    deadline: startup::Deadline,
+    healthchecks: &Arc<Healthchecks>,
+) -> Result<Vec<Endpoint>> {
+    let multiple = hosts.len() > 1;
+    let mut endpoints = Vec::new();
+    for (name, host) in hosts {
+        let span = name
+            .as_deref()
+            .map_or_else(Span::none, |name| info_span!("host", name));
+        let result = docker::connect(config, &host, deadline)
+            .instrument(span.clone())
+            .await;
+        let docker = match result {
+            Ok(docker) => docker,
+            Err(err) if multiple => {
+                span.in_scope(|| error!("{:#}", err.context("skipping docker host")));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let containers = Arc::new(ContainerManager::new(
+            docker.clone(),
+            healthchecks.clone(),
+            config,
+            name.as_deref(),
+        ));
+        let events = Arc::new(EventHandler::new(containers.clone(), config));
+        endpoints.push(Endpoint {
+            name: name.map(Arc::from),
+            span,
+            docker,
+            containers,
+            events,
+            fetch_loop: Arc::default(),
+        });
+    }
+    ensure!(
+        !endpoints.is_empty(),
+        "none of the docker hosts could be reached"
+    );
+    Ok(endpoints)
+}
+
+/// Handle the docker events of the given endpoint and load its initial
+/// container list, which is retried until the startup deadline. If multiple
+/// hosts are monitored, a failed fetch is only logged and retried by the
+/// periodic fetch.
+async fn start_endpoint(
+    endpoint: &Endpoint,
+    config: &Config,
+    deadline: startup::Deadline,
+    multiple: bool,
+) -> Result<()> {
+    // handle docker events and the results of the http probes in new tasks.
+    // events are buffered until the initial container fetch has completed.
+    spawn(
+        endpoint
+            .events
+            .clone()
+            .handle_events(endpoint.docker.clone())
+            .instrument(endpoint.span.clone()),
+    );
+    spawn(
+        endpoint
+            .events
+            .clone()
+            .handle_probe_results()
+            .instrument(endpoint.span.clone()),
+    );
+    spawn(
+        endpoint
+            .events
+            .clone()
+            .handle_log_matches()
+            .instrument(endpoint.span.clone()),
+    );
+    spawn(
+        endpoint
+            .events
+            .clone()
+            .handle_resource_breaches()
+            .instrument(endpoint.span.clone()),
+    );
+
+    // load container list from docker daemon and replay buffered events
+    let fetch = deadline.retry("fetching the containers", || {
+        endpoint.containers.fetch_containers()
+    });
+    match fetch.await {
+        Ok(()) => {}
+        Err(err) if multiple => error!("{:#}", err.context("failed to fetch containers")),
+        Err(err) => return Err(err),
+    }
+    endpoint.events.set_ready().await;
+
+    // report exited containers whose ping url has no running counterpart
+    if config.include_stopped_on_startup {
+        if let Err(err) = endpoint.containers.fail_stopped_containers().await {
+            error!("{err:#}");
+        }
+    }
+    Ok(())
+}
+
+/// Serve the http status endpoint if it is enabled
+async fn start_status_server(
+    config: &Config,
+    endpoints: &Arc<[Endpoint]>,
+    healthchecks: &Arc<Healthchecks>,
+    metrics: Option<PrometheusHandle>,
+    heartbeat: &Arc<Heartbeat>,
+) -> Result<Option<status::Server>> {
+    let Some(addr) = &config.status_addr else {
+        return Ok(None);
+    };
+    let server = status::Server::start(
+        addr,
+        config.status_token.clone(),
+        config.admin_token.clone(),
+        endpoints.clone(),
+        healthchecks.clone(),
+        metrics,
+        heartbeat.clone(),
+    )
+    .await?;
+    Ok(Some(server))
+}
+
+/// Send keep-alive notifications to the systemd watchdog as long as the main
+/// loops are ticking, if the watchdog is enabled
+fn spawn_watchdog(endpoints: &Arc<[Endpoint]>, heartbeat: &Arc<Heartbeat>) {
+    let Some(interval) = systemd::watchdog_interval() else {
+        return;
+    };
+    spawn({
+        let endpoints = endpoints.clone();
+        let heartbeat = heartbeat.clone();
+        async move {
+            loop {
+                match check_liveness(&endpoints, &heartbeat) {
+                    Ok(()) => systemd::notify("WATCHDOG=1"),
+                    Err(reason) => warn!("skipping watchdog notification: {reason}"),
+                }
+                sleep(interval).await;
+            }
+        }
+    });
+}
+
+/// Spawn the tasks that run in the background while pinging
+fn spawn_background_tasks(
+    config: &watch::Receiver<Arc<Config>>,
+    endpoints: &Arc<[Endpoint]>,
+    healthchecks: &Arc<Healthchecks>,
+) -> Result<()> {
+    for endpoint in endpoints.iter() {
+        // periodically refresh docker container list in case we miss some events
+        spawn({
+            let containers = endpoint.containers.clone();
+            let heartbeat = endpoint.fetch_loop.clone();
+            let mut config = config.clone();
+            async move {
+                loop {
+                    let (duration, fetch_timeout) = {
+                        let config = config.borrow_and_update();
+                        (config.fetch_interval, config.fetch_timeout)
+                    };
+                    if duration.is_zero() {
+                        // wait until the periodic fetch is enabled by a reload
+                        heartbeat.pause();
+                        if config.changed().await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    let duration = jitter(duration, config.borrow().interval_jitter);
+                    heartbeat.beat(duration + fetch_timeout);
+                    debug!("next fetch in {}", humantime::format_duration(duration));
+                    sleep(duration).await;
+                    fetch_containers(&containers, fetch_timeout).await;
+                }
+            }
+            .instrument(endpoint.span.clone())
+        });
+
+        // periodically check whether the docker daemon is still reachable
+        spawn({
+            let containers = endpoint.containers.clone();
+            async move {
+                loop {
+                    sleep(DOCKER_PING_INTERVAL).await;
+                    containers.ping_docker(DOCKER_PING_TIMEOUT).await;
+                }
+            }
+            .instrument(endpoint.span.clone())
+        });
+
+        // refresh docker container list after the event stream has been
+        // re-established, as events might have been lost
+        spawn({
+            let containers = endpoint.containers.clone();
+            let events = endpoint.events.clone();
+            let config = config.clone();
+            async move {
+                loop {
+                    events.wait_for_reconnect().await;
+                    let fetch_timeout = config.borrow().fetch_timeout;
+                    fetch_containers(&containers, fetch_timeout).await;
+                }
+            }
+            .instrument(endpoint.span.clone())
+        });
+    }
+
+    // periodically log a summary of the monitored containers
+    spawn({
+        let endpoints = endpoints.clone();
+        let healthchecks = healthchecks.clone();
+        let mut config = config.clone();
+        async move {
+            loop {
+                let interval = config.borrow_and_update().summary_interval;
+                if interval.is_zero() {
+                    // wait until the summary is enabled by a reload
+                    if config.changed().await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                sleep(interval).await;
+                log_summary(&endpoints, &healthchecks).await;
+            }
+        }
+    });
+
+    // dump the internal state on SIGUSR1
+    let mut user_signal =
+        signal(SignalKind::user_defined1()).context("failed to install SIGUSR1 handler")?;
+    spawn({
+        let endpoints = endpoints.clone();
+        let config = config.clone();
+        async move {
+            while user_signal.recv().await.is_some() {
+                let (dump_file, redact) = {
+                    let config = config.borrow();
+                    (config.dump_file.clone(), config.dump_redact_urls)
+                };
+                if let Err(err) = dump_state(&endpoints, dump_file.as_deref(), redact).await {
+                    error!("{err:#}");
+                }
+            }
+        }
+    });
+
+    // persist the state of the healthchecks checks after pings have been sent
+    let state_file = config.borrow().state_file.clone();
+    if let Some(path) = state_file {
+        spawn({
+            let healthchecks = healthchecks.clone();
+            async move {
+                loop {
+                    healthchecks.wait_for_ping().await;
+                    sleep(STATE_SAVE_DELAY).await;
+                    save_state(&healthchecks, &path).await;
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Reload the configuration on SIGHUP and apply the options that can be
+/// changed at runtime. The current configuration is kept if the new one is
+/// invalid.
+fn spawn_reload_handler(
+    overrides: Option<Overrides>,
+    config: watch::Sender<Arc<Config>>,
+    endpoints: &Arc<[Endpoint]>,
+    healthchecks: &Arc<Healthchecks>,
+) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup()).context("failed to install SIGHUP handler")?;
+    let endpoints = endpoints.clone();
+    let healthchecks = healthchecks.clone();
+    spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("reloading configuration");
+            let new = match load_config(overrides.as_ref()) {
+                Ok(new) => new,
+                Err(err) => {
+                    error!("{:#}", err.context("failed to reload configuration"));
+                    continue;
+                }
+            };
+            let restart_required = config.borrow().restart_required(&new);
+            if !restart_required.is_empty() {
+                warn!(
+                    "changes of {} only take effect after a restart",
+                    restart_required.join(", ")
+                );
+            }
+            for endpoint in endpoints.iter() {
+                endpoint.containers.reconfigure(&new);
+            }
+            healthchecks.reconfigure(&new);
+            config.send_replace(Arc::new(new));
+        }
+    });
+    Ok(())
+}
+
+/// Fetch the containers and ping all healthcheck urls once. Fail if the
+/// containers of any docker host could not be fetched or any ping failed.
+async fn oneshot(
+    endpoints: &[Endpoint],
+    healthchecks: &Healthchecks,
+    config: &Config,
+) -> Result<()> {
+    let mut failed = 0;
+    let mut unreachable = 0;
+    for endpoint in endpoints {
+        let result = async {
+            timeout(config.fetch_timeout, endpoint.containers.fetch_containers())
+                .await
+                .context("failed to fetch containers in time")??;
+            timeout(
+                config.ping_timeout,
+                endpoint.containers.ping_healthchecks(false),
+            )
+            .await
+            .context("failed to ping healthchecks in time")
+        }
+        .instrument(endpoint.span.clone())
+        .await;
+        match result {
+            Ok(count) => failed += count,
+            Err(err) if endpoints.len() > 1 => {
+                endpoint.span.in_scope(|| error!("{err:#}"));
+                unreachable += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    if let Some(url) = &config.summary_url {
+        let body = summary_body(endpoints, &mut Summary::default()).await?;
+        send_summary(healthchecks, url, &body, config.ping_timeout).await?;
+    }
+    ensure!(
+        unreachable == 0,
+        "{unreachable} docker hosts could not be checked"
+    );
+    ensure!(failed == 0, "{failed} healthcheck pings failed");
+    Ok(())
+}
+
+/// Create the healthchecks.io interface with the ping client of the given
+/// configuration
+fn create_healthchecks(config: &Config) -> Result<Arc<Healthchecks>> {
+    Ok(Arc::new(Healthchecks::new(
+        healthchecks::ping_client(config)?,
+        config,
+    )))
+}
+
+/// Fetch the containers and print which of them are monitored without
+/// sending any pings. The full ping urls are only shown if requested.
+pub async fn list_containers(config: &Config, full_urls: bool, json: bool) -> Result<()> {
+    let hosts = config.docker_hosts()?;
+    let healthchecks = create_healthchecks(config)?;
+    let deadline = startup::Deadline::new(config.startup_timeout);
+    let mut dumps = Vec::new();
+    for endpoint in connect_endpoints(hosts, config, deadline, &healthchecks).await? {
+        timeout(config.fetch_timeout, endpoint.containers.fetch_containers())
+            .await
+            .context("failed to fetch containers in time")??;
+        let mut dump = endpoint.containers.dump().await;
+        if !full_urls {
+            dump.redact();
+        }
+        dumps.push(dump);
+    }
+    list::print(dumps, json)
+}
+
+/// Load the configuration from the configuration file, environment variables
+/// and the given overrides and validate it
+pub fn load_config(overrides: Option<&Overrides>) -> Result<Config> {
+    let config = config::load(overrides).context("could not load configuration")?;
+    config.validate().context("invalid configuration")?;
+    Ok(config)
+}
+
+/// Periodically ping the healthcheck urls of the monitored containers. The
+/// first round of pings is sent right after the initial container fetch unless
+/// this has been disabled. Each interval is measured from the previous tick
+/// and randomly lengthened or shortened by the configured jitter.
+async fn ping_loop(
+    endpoints: &[Endpoint],
+    healthchecks: &Arc<Healthchecks>,
+    mut config: watch::Receiver<Arc<Config>>,
+    heartbeat: &Heartbeat,
+) {
+    let mut current = config.borrow_and_update().clone();
+    let mut next = time::Instant::now();
+    if !current.ping_on_startup {
+        next += next_ping(&current, false);
+    }
+    let mut last_keepalive = None::<Instant>;
+    let mut summary = Summary::default();
+    loop {
+        // the next round of pings has to be completed within the ping timeout
+        heartbeat.beat(next.saturating_duration_since(time::Instant::now()) + current.ping_timeout);
+        tokio::select! {
+            () = time::sleep_until(next) => {}
+            Ok(()) = config.changed() => {
+                // restart the interval if the ping interval has been reloaded
+                let new = config.borrow_and_update().clone();
+                if new.ping_interval != current.ping_interval
+                    || new.interval_jitter != current.interval_jitter
+                    || new.align_pings != current.align_pings
+                {
+                    next = time::Instant::now() + next_ping(&new, false);
+                }
+                current = new;
+                continue;
+            }
+        }
+        next = time::Instant::now() + next_ping(&current, true);
+        let keepalive_interval = current.keepalive_interval;
+
+        // in `both` ping mode, send keep-alive pings for unchanged health statuses
+        // every keepalive interval
+        let keepalive = current.ping_mode == PingMode::Both
+            && last_keepalive.map_or(true, |last| last.elapsed() >= keepalive_interval);
+        if keepalive {
+            last_keepalive = Some(Instant::now());
+        }
+
+        let completed = join_all(endpoints.iter().map(|endpoint| {
+            async {
+                let result = timeout(
+                    current.ping_timeout,
+                    endpoint.containers.ping_healthchecks(keepalive),
+                )
+                .await
+                .context("failed to ping healthchecks in time");
+                if let Err(err) = &result {
+                    error!("{err:#}");
+                }
+                result.is_ok()
+            }
+            .instrument(endpoint.span.clone())
+        }))
+        .await;
+        report_status(endpoints).await;
+        forget_ping_urls(endpoints, healthchecks, &current).await;
+
+        if let Some(url) = &current.self_ping_url {
+            let (health, body) = self_ping(endpoints, completed.iter().all(|&ok| ok));
+            let url = Arc::from(url.as_str());
+            let healthchecks = healthchecks.clone();
+            let generation = healthchecks.next_generation();
+            spawn(async move {
+                if let Err(err) = healthchecks
+                    .keepalive(&url, &health, &body, generation)
+                    .await
+                {
+                    error!("{:#}", err.context("failed to send self ping"));
+                }
+            });
+        }
+        if let Some(url) = &current.docker_check_url {
+            let (health, body) = docker_check(endpoints, current.docker_down_threshold);
+            let url = Arc::from(url.as_str());
+            let healthchecks = healthchecks.clone();
+            let generation = healthchecks.next_generation();
+            spawn(async move {
+                if let Err(err) = healthchecks
+                    .keepalive(&url, &health, &body, generation)
+                    .await
+                {
+                    error!("{:#}", err.context("failed to send docker check ping"));
+                }
+            });
+        }
+        if let Some(url) = &current.summary_url {
+            match summary_body(endpoints, &mut summary).await {
+                Ok(body) => {
+                    let url = url.clone();
+                    let healthchecks = healthchecks.clone();
+                    let ping_timeout = current.ping_timeout;
+                    spawn(async move {
+                        let result = send_summary(&healthchecks, &url, &body, ping_timeout).await;
+                        if let Err(err) = result {
+                            error!("{err:#}");
+                        }
+                    });
+                }
+                Err(err) => error!("{:#}", err.context("failed to create summary")),
+            }
+        }
+    }
+}
+
+/// Return the json summary of the health statuses of the monitored containers
+/// of all docker hosts
+async fn summary_body(endpoints: &[Endpoint], summary: &mut Summary) -> Result<String> {
+    let mut containers = Vec::new();
+    for endpoint in endpoints {
+        containers.extend(
+            endpoint
+                .containers
+                .health_summary()
+                .await
+                .into_iter()
+                .map(|(name, url, health)| (endpoint.name.clone(), name, url, health)),
+        );
+    }
+    summary.update(containers)
+}
+
+/// Post the summary to the given url within the ping timeout
+async fn send_summary(
+    healthchecks: &Healthchecks,
+    url: &str,
+    body: &str,
+    ping_timeout: Duration,
+) -> Result<()> {
+    timeout(ping_timeout, healthchecks.post_summary(url, body))
+        .await
+        .context("failed to send summary in time")?
+        .context("failed to send summary")
+}
+
+/// Forget the ping attempts of urls that are no longer monitored on any host
+async fn forget_ping_urls(endpoints: &[Endpoint], healthchecks: &Healthchecks, config: &Config) {
+    let mut urls: HashSet<Arc<str>> = [&config.self_ping_url, &config.docker_check_url]
+        .into_iter()
+        .flatten()
+        .map(|url| Arc::from(url.as_str()))
+        .collect();
+    for endpoint in endpoints {
+        urls.extend(endpoint.containers.ping_urls().await);
+    }
+    healthchecks.retain(&urls).await;
+}
+
+/// Return the health status and body of the self ping after a round of pings.
+/// The round fails if it timed out or a docker daemon has been unreachable
+/// for longer than the configured threshold.
+fn self_ping(endpoints: &[Endpoint], completed: bool) -> (Health, String) {
+    if !completed {
+        return (Health::Unhealthy, "round of pings timed out".into());
+    }
+    for endpoint in endpoints {
+        if let Some(down) = endpoint.containers.docker_down() {
+            let body = format!(
+                "{} unreachable for {}",
+                endpoint.describe("docker daemon"),
+                humantime::format_duration(down)
+            );
+            return (Health::Unhealthy, body);
+        }
+    }
+    (Health::Healthy, "round of pings completed".into())
+}
+
+/// Return the health status and body of the docker check. The check fails if a
+/// docker daemon has been unreachable or its event stream has been down for
+/// longer than the given threshold.
+fn docker_check(endpoints: &[Endpoint], threshold: Duration) -> (Health, String) {
+    let mut health = Health::Healthy;
+    let lines: Vec<_> = endpoints
+        .iter()
+        .map(|endpoint| {
+            if let Some(down) = endpoint.containers.docker_down() {
+                health = Health::Unhealthy;
+                return format!(
+                    "{} unreachable for {}",
+                    endpoint.describe("docker daemon"),
+                    humantime::format_duration(down)
+                );
+            }
+            let stream_down = endpoint
+                .events
+                .stream_status()
+                .down_since
+                .map(|since| Duration::from_secs(state::unix_time().saturating_sub(since)))
+                .filter(|&down| down >= threshold);
+            if let Some(down) = stream_down {
+                health = Health::Unhealthy;
+                return format!(
+                    "{} down for {}",
+                    endpoint.describe("docker event stream"),
+                    humantime::format_duration(down)
+                );
+            }
+            format!("{} reachable", endpoint.describe("docker daemon"))
+        })
+        .collect();
+    (health, lines.join("\n"))
+}
+
+/// Return an error if the ping loop or a periodic container fetch missed its
+/// expected tick
+fn check_liveness(endpoints: &[Endpoint], ping_loop: &Heartbeat) -> Result<(), String> {
+    if let Some(overdue) = ping_loop.overdue() {
+        return Err(format!("ping loop is overdue by {}s", overdue.as_secs()));
+    }
+    for endpoint in endpoints {
+        if let Some(overdue) = endpoint.fetch_loop.overdue() {
+            return Err(format!(
+                "{} is overdue by {}s",
+                endpoint.describe("container fetch loop"),
+                overdue.as_secs()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Report the number of monitored containers to systemd
+async fn report_status(endpoints: &[Endpoint]) {
+    if !systemd::enabled() {
+        return;
+    }
+    let (mut monitored, mut ignored) = (0, 0);
+    for endpoint in endpoints {
+        let counts = endpoint.containers.counts().await;
+        monitored += counts.0;
+        ignored += counts.1;
+    }
+    let hosts = if endpoints.len() > 1 {
+        format!(" on {} hosts", endpoints.len())
+    } else {
+        String::new()
+    };
+    systemd::notify(&format!(
+        "STATUS=monitoring {monitored} containers{hosts}, {ignored} ignored"
+    ));
+}
+
+/// Return the duration until the next round of pings. Aligned pings are
+/// scheduled at the next multiple of the ping interval according to the wall
+/// clock, so that clock adjustments are taken into account on every tick.
+fn next_ping(config: &Config, after_tick: bool) -> Duration {
+    let duration = if config.align_pings {
+        until_next_tick(config.ping_interval, after_tick, SystemTime::now())
+    } else {
+        jitter(config.ping_interval, config.interval_jitter)
+    };
+    debug!("next ping in {}", humantime::format_duration(duration));
+    duration
+}
+
+/// Return the duration from the given time until the next aligned round of
+/// pings. Directly after a tick, the boundary of that tick is skipped.
+fn until_next_tick(interval: Duration, after_tick: bool, now: SystemTime) -> Duration {
+    // skip the boundary of the current tick in case the timer fired early
+    let offset = if after_tick {
+        interval / 2
+    } else {
+        Duration::ZERO
+    };
+    offset + until_aligned(interval, now + offset)
+}
+
+/// Return the duration from the given time until the next multiple of the
+/// interval since the unix epoch
+fn until_aligned(interval: Duration, time: SystemTime) -> Duration {
+    let interval = interval.as_nanos();
+    if interval == 0 {
+        return Duration::ZERO;
+    }
+    let elapsed = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    Duration::from_nanos(u64::try_from(interval - elapsed % interval).unwrap_or(u64::MAX))
+}
+
+/// Randomly lengthen or shorten the given interval by up to the given
+/// percentage. The percentage is less than 100, so the interval never becomes
+/// zero.
+fn jitter(interval: Duration, percent: u8) -> Duration {
+    if percent == 0 {
+        return interval;
+    }
+    let percent = f64::from(percent) / 100.0;
+    let factor = rand::thread_rng().gen_range(1.0 - percent..=1.0 + percent);
+    interval.mul_f64(factor).max(Duration::from_millis(1))
+}
+
+/// Log a summary of the monitored containers and the event stream of each
+/// docker host and the numbers of sent and failed pings since the last summary
+async fn log_summary(endpoints: &[Endpoint], healthchecks: &Healthchecks) {
+    let pings = healthchecks.take_ping_counts();
+    if let [endpoint] = endpoints {
+        let last_event = endpoint.events.last_event();
+        endpoint
+            .containers
+            .log_summary(last_event, Some(pings))
+            .await;
+        endpoint.events.log_stats();
+        return;
+    }
+    for endpoint in endpoints {
+        let last_event = endpoint.events.last_event();
+        endpoint
+            .containers
+            .log_summary(last_event, None)
+            .instrument(endpoint.span.clone())
+            .await;
+        endpoint.span.in_scope(|| endpoint.events.log_stats());
+    }
+    let (sent, failed) = pings;
+    info!("summary: {sent} pings sent and {failed} failed since last summary");
+}
+
+/// Wait until the process receives a SIGINT or SIGTERM signal
+async fn shutdown_signal() -> Result<()> {
+    let mut terminate =
+        signal(SignalKind::terminate()).context("failed to install SIGTERM handler")?;
+    tokio::select! {
+        result = ctrl_c() => result.context("failed to wait for SIGINT"),
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+/// Write a dump of the internal state to the given file or the log. If
+/// multiple hosts are monitored, the dump contains a list with one entry per
+/// docker host.
+async fn dump_state(endpoints: &[Endpoint], path: Option<&str>, redact: bool) -> Result<()> {
+    let mut dumps = Vec::new();
+    for endpoint in endpoints {
+        let mut dump = endpoint.containers.dump().await;
+        if redact {
+            dump.redact();
+        }
+        dumps.push(dump);
+    }
+    let json = match &dumps[..] {
+        [dump] => serde_json::to_string_pretty(dump),
+        dumps => serde_json::to_string_pretty(dumps),
+    }
+    .context("failed to serialize state dump")?;
+    if let Some(path) = path {
+        tokio::fs::write(path, json)
+            .await
+            .with_context(|| format!("failed to write state dump to {path}"))
+    } else {
+        info!("state dump:\n{json}");
+        Ok(())
+    }
+}
+
+/// Persist the state of the healthchecks checks and log any errors
+async fn save_state(healthchecks: &Healthchecks, path: &str) {
+    if let Err(err) = state::save(path, &healthchecks.snapshot().await).await {
+        error!("{err:#}");
+    }
+}
+
+/// Reload all docker containers from the daemon and log any errors
+async fn fetch_containers(containers: &ContainerManager, fetch_timeout: Duration) {
+    if let Err(err) = timeout(fetch_timeout, async {
+        containers
+            .fetch_containers()
+            .await
+            .context("failed to fetch containers")
+    })
+    .await
+    .context("failed to fetch containers in time")
+    .and_then(|res| res)
+    {
+        error!("{err:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::docker::fake::{self, FakeRuntime};
+
+    /// Start a healthchecks server that accepts pings to `/ok` and rejects
+    /// pings to `/broken`
+    async fn server() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/ok"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/broken"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// Return the configuration of the oneshot tests, which does not retry
+    /// failed pings
+    fn oneshot_config() -> Config {
+        Config {
+            mode: RunMode::Oneshot,
+            ping_retries: 0,
+            ..Config::default()
+        }
+    }
+
+    /// Create an endpoint backed by the given runtime
+    fn endpoint(
+        runtime: &FakeRuntime,
+        healthchecks: &Arc<Healthchecks>,
+        config: &Config,
+        name: Option<&str>,
+    ) -> Endpoint {
+        let containers = Arc::new(ContainerManager::new(
+            runtime.clone(),
+            healthchecks.clone(),
+            config,
+            name,
+        ));
+        Endpoint {
+            name: name.map(Arc::from),
+            span: Span::none(),
+            docker: Docker::new("tcp://127.0.0.1:1").unwrap(),
+            events: Arc::new(EventHandler::new(containers.clone(), config)),
+            containers,
+            fetch_loop: Arc::default(),
+        }
+    }
+
+    /// Create a runtime with a healthy container pinging the given url
+    fn runtime(id: &str, url: &str) -> FakeRuntime {
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            id,
+            id,
+            &[("healthchecks.url", url)],
+            Some("healthy"),
+        ));
+        runtime
+    }
+
+    #[tokio::test]
+    async fn oneshot_succeeds_if_all_pings_succeed() {
+        let server = server().await;
+        let config = oneshot_config();
+        let healthchecks = create_healthchecks(&config).unwrap();
+        let runtime = runtime("a", &format!("{}/ok", server.uri()));
+        let endpoints = [endpoint(&runtime, &healthchecks, &config, None)];
+
+        oneshot(&endpoints, &healthchecks, &config).await.unwrap();
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn oneshot_fails_if_a_ping_fails() {
+        let server = server().await;
+        let config = oneshot_config();
+        let healthchecks = create_healthchecks(&config).unwrap();
+        let runtime = runtime("a", &format!("{}/ok", server.uri()));
+        runtime.insert(fake::container(
+            "b",
+            "b",
+            &[("healthchecks.url", &format!("{}/broken", server.uri()))],
+            Some("healthy"),
+        ));
+        let endpoints = [endpoint(&runtime, &healthchecks, &config, None)];
+
+        let err = oneshot(&endpoints, &healthchecks, &config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "1 healthcheck pings failed");
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn oneshot_fails_if_the_docker_host_is_unreachable() {
+        let server = server().await;
+        let config = oneshot_config();
+        let healthchecks = create_healthchecks(&config).unwrap();
+        let runtime = runtime("a", &format!("{}/ok", server.uri()));
+        runtime.set_unreachable(true);
+        let endpoints = [endpoint(&runtime, &healthchecks, &config, None)];
+
+        oneshot(&endpoints, &healthchecks, &config)
+            .await
+            .unwrap_err();
+        assert_eq!(server.received_requests().await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn oneshot_checks_the_reachable_hosts_and_fails() {
+        let server = server().await;
+        let config = oneshot_config();
+        let healthchecks = create_healthchecks(&config).unwrap();
+        let unreachable = runtime("a", &format!("{}/ok", server.uri()));
+        unreachable.set_unreachable(true);
+        let reachable = runtime("b", &format!("{}/ok", server.uri()));
+        let endpoints = [
+            endpoint(&unreachable, &healthchecks, &config, Some("first")),
+            endpoint(&reachable, &healthchecks, &config, Some("second")),
+        ];
+
+        let err = oneshot(&endpoints, &healthchecks, &config)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "1 docker hosts could not be checked");
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn docker_check_recovers_with_an_idle_event_stream() {
+        let config = Config::default();
+        let healthchecks = create_healthchecks(&config).unwrap();
+        let runtime = FakeRuntime::default();
+        runtime.push_event_stream(Vec::new(), Some(Duration::ZERO));
+        let endpoints = [endpoint(&runtime, &healthchecks, &config, None)];
+        spawn(endpoints[0].events.clone().handle_events(runtime.clone()));
+
+        // the first stream ends right away, and the check fails until the
+        // stream has been re-established
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            docker_check(&endpoints, Duration::ZERO).0,
+            Health::Unhealthy
+        );
+
+        // the resubscribed stream stays idle, but the daemon responds
+        sleep(Duration::from_secs(5)).await;
+        assert_eq!(docker_check(&endpoints, Duration::ZERO).0, Health::Healthy);
+    }
+
+    /// Return the time at the given offset in milliseconds from an aligned
+    /// tick of a one minute interval
+    fn near_tick(offset: i64) -> SystemTime {
+        let tick = UNIX_EPOCH + Duration::from_secs(60 * 29_000_000);
+        let delta = Duration::from_millis(offset.unsigned_abs());
+        if offset < 0 {
+            tick - delta
+        } else {
+            tick + delta
+        }
+    }
+
+    #[test]
+    fn aligned_pings_wait_for_the_next_tick() {
+        let interval = Duration::from_secs(60);
+        // exactly at a tick, the next one is a full interval away
+        assert_eq!(until_aligned(interval, near_tick(0)), interval);
+        assert_eq!(
+            until_aligned(interval, near_tick(-1)),
+            Duration::from_millis(1)
+        );
+        assert_eq!(
+            until_aligned(interval, near_tick(1)),
+            Duration::from_millis(59_999)
+        );
+        assert_eq!(until_aligned(Duration::ZERO, near_tick(1)), Duration::ZERO);
+    }
+
+    #[test]
+    fn aligned_pings_skip_the_current_tick() {
+        let interval = Duration::from_secs(60);
+        // a timer that fired early must not ping again at the same tick
+        assert_eq!(
+            until_next_tick(interval, true, near_tick(-1)),
+            Duration::from_millis(60_001)
+        );
+        assert_eq!(until_next_tick(interval, true, near_tick(0)), interval);
+        assert_eq!(
+            until_next_tick(interval, true, near_tick(1)),
+            Duration::from_millis(59_999)
+        );
+        // before the first tick, the nearest boundary is used
+        assert_eq!(
+            until_next_tick(interval, false, near_tick(-1)),
+            Duration::from_millis(1)
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Listing of the discovered docker containers
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{container_manager::Health, dump::Dump};
+
+/// Entry of the container list
+#[derive(Serialize)]
+struct Entry {
+    /// Name of the docker host, if multiple hosts are monitored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+
+    /// Id of the container
+    id: String,
+
+    /// Name of the container
+    name: Option<String>,
+
+    /// Whether the container is monitored
+    monitored: bool,
+
+    /// Reason why the container is ignored
+    reason: Option<&'static str>,
+
+    /// Ping url of the container
+    url: Option<String>,
+
+    /// Health status that is reported for the container
+    health: Option<Health>,
+}
+
+/// Print the monitored and ignored containers of the given dumps, either as a
+/// table or as json
+pub fn print(dumps: Vec<Dump>, json: bool) -> Result<()> {
+    let mut entries = Vec::new();
+    for dump in dumps {
+        let host = &dump.host;
+        entries.extend(dump.containers.into_iter().map(|container| Entry {
+            host: host.clone(),
+            id: container.id,
+            name: container.name,
+            monitored: true,
+            reason: None,
+            url: Some(container.url),
+            health: Some(container.effective_health),
+        }));
+        entries.extend(dump.ignored_containers.into_iter().map(|id| Entry {
+            host: host.clone(),
+            id,
+            name: None,
+            monitored: false,
+            reason: Some("no healthchecks.url label"),
+            url: None,
+            health: None,
+        }));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 6]> = entries
+        .into_iter()
+        .map(|entry| {
+            [
+                entry.host.unwrap_or_default(),
+                entry.id.chars().take(12).collect(),
+                entry.name.unwrap_or_default(),
+                if entry.monitored {
+                    "monitored".to_owned()
+                } else {
+                    format!("ignored ({})", entry.reason.unwrap_or_default())
+                },
+                entry
+                    .health
+                    .map(|health| health.to_string())
+                    .unwrap_or_default(),
+                entry.url.unwrap_or_default(),
+            ]
+        })
+        .collect();
+    let header = ["HOST", "ID", "NAME", "STATUS", "HEALTH", "URL"].map(str::to_owned);
+    // only show the host column if multiple hosts are monitored
+    let skip = usize::from(rows.iter().all(|row| row[0].is_empty()));
+    let mut widths = [0; 6];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .skip(skip)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! Following the logs of containers to mark them as unhealthy when a log line
+//! matches a pattern
+
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+
+use regex::Regex;
+use tokio::{spawn, sync::mpsc::Sender, task::JoinHandle, time::sleep};
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::{container_manager::short_id, docker::runtime::ContainerRuntime, state};
+
+/// Default duration for which a container is reported as unhealthy after a
+/// log line matched its pattern
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Maximum number of matches that are buffered until they are handled. Further
+/// matches are dropped, as they would not change the health status anyway.
+pub const MATCH_BUFFER_SIZE: usize = 16;
+
+/// Maximum number of bytes of a log line that are kept, the rest of the line is
+/// discarded
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Delay before reconnecting to the log stream after it failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Pattern of log lines that mark a container as unhealthy
+#[derive(Debug)]
+pub struct LogPattern {
+    /// Regular expression that is matched against each log line
+    pub regex: Regex,
+
+    /// Duration for which the container is reported as unhealthy after a
+    /// match
+    pub cooldown: Duration,
+}
+
+impl PartialEq for LogPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str() && self.cooldown == other.cooldown
+    }
+}
+
+impl Display for LogPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "log: {}", self.regex)
+    }
+}
+
+/// Log line of a container that matched its pattern
+pub struct LogMatch {
+    /// Id of the container
+    pub id: Arc<str>,
+
+    /// Matching log line
+    pub line: String,
+}
+
+/// Follow the logs of a container and send the lines matching the pattern to
+/// the given channel until the container stops or the task is aborted. The
+/// log stream is reopened if it fails.
+pub fn spawn_log_follower(
+    pattern: Arc<LogPattern>,
+    runtime: Arc<dyn ContainerRuntime>,
+    id: Arc<str>,
+    name: Option<&str>,
+    matches: Sender<LogMatch>,
+) -> JoinHandle<()> {
+    let span = info_span!(
+        parent: None,
+        "log_follower",
+        container.id = short_id(&id),
+        container.name = name,
+    );
+    spawn(
+        async move {
+            let mut since = state::unix_time();
+            let mut line = Vec::new();
+            loop {
+                let mut on_chunk = |chunk: Vec<u8>| {
+                    for part in chunk.split_inclusive(|&byte| byte == b'\n') {
+                        let (part, complete) = match part.strip_suffix(b"\n") {
+                            Some(part) => (part, true),
+                            None => (part, false),
+                        };
+                        let len = part.len().min(MAX_LINE_LENGTH - line.len());
+                        line.extend_from_slice(&part[..len]);
+                        if !complete {
+                            continue;
+                        }
+                        let text = String::from_utf8_lossy(&line);
+                        if pattern.regex.is_match(&text) {
+                            debug!(line = %text, "log line matched pattern");
+                            let _ = matches.try_send(LogMatch {
+                                id: id.clone(),
+                                line: text.into_owned(),
+                            });
+                        }
+                        line.clear();
+                    }
+                };
+                match runtime.follow_logs(&id, since, &mut on_chunk).await {
+                    Ok(()) => {
+                        debug!("log stream ended");
+                        return;
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "failed to follow container logs");
+                        since = state::unix_time();
+                        line.clear();
+                        sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        }
+        .instrument(span),
+    )
+}
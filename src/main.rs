@@ -1,4 +1,4 @@
-//! Healthchecks.io Integration for Docker Healthchecks
+//! Command line interface of docker-healthchecks
 
 #![forbid(unsafe_code)]
 #![warn(clippy::pedantic)]
@@ -19,99 +19,123 @@
     clippy::wildcard_enum_match_arm
 )]
 
-use std::{sync::Arc, time::Duration};
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+#[cfg(feature = "otlp")]
+use docker_healthchecks::otlp;
+use docker_healthchecks::{config::RunMode, load_config, Config, Overrides};
+#[cfg(feature = "otlp")]
+use tracing::error;
+use tracing::info;
+use tracing_subscriber::{prelude::*, reload, EnvFilter, Registry};
 
-use anyhow::{ensure, Context, Result};
-use docker_api::Docker;
-use tokio::{
-    spawn,
-    time::{interval, sleep, timeout},
-};
-use tracing::{debug, error};
+/// Command line arguments
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Command to run instead of monitoring the docker containers
+    #[command(subcommand)]
+    command: Option<Command>,
 
-use self::{
-    container_manager::ContainerManager, event_handler::EventHandler, healthchecks::Healthchecks,
-};
+    /// Fetch the containers, ping all healthcheck urls once and exit (same as
+    /// `--mode oneshot`)
+    #[arg(long)]
+    oneshot: bool,
 
-mod config;
-mod container_manager;
-mod event_handler;
-mod healthchecks;
+    /// Print the monitored and ignored containers and exit without sending
+    /// any pings
+    #[arg(long)]
+    list: bool,
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // initialize tracing subscriber
-    tracing_subscriber::fmt::init();
+    /// Show the full ping urls in the container list instead of redacting
+    /// their secret parts
+    #[arg(long, requires = "list")]
+    full_urls: bool,
 
-    // load config from environment variables
-    let config = config::load().context("could not load environment variables")?;
-    ensure!(
-        config.ping_interval >= 1,
-        "ping_interval must be at least one second"
-    );
-    ensure!(
-        config.fetch_interval >= 1,
-        "fetch_interval must be at least one second"
-    );
+    /// Print the container list as json
+    #[arg(long, requires = "list")]
+    json: bool,
 
-    // connect to docker daemon
-    let docker = Docker::unix(&config.docker_path);
-    debug!(
-        "connected to docker: {:?}",
-        docker
-            .ping()
-            .await
-            .context("could not ping docker daemon")?
-    );
+    /// Configuration values that take precedence over environment variables
+    #[command(flatten)]
+    overrides: Overrides,
+}
 
-    // create container manager and load container list from docker daemon
-    let containers = ContainerManager::new(docker.clone(), Healthchecks::new(config.ping_retries));
-    containers.fetch_containers().await?;
+/// Commands other than monitoring the docker containers
+#[derive(Subcommand)]
+enum Command {
+    /// Validate the configuration and print the effective values without
+    /// connecting to docker
+    CheckConfig,
+}
 
-    // create event handler
-    let containers = Arc::new(containers);
-    let events = EventHandler::new(containers.clone());
+#[tokio::main]
+async fn main() -> Result<()> {
+    // initialize tracing subscriber. the log filter is replaced once the
+    // configuration has been loaded.
+    let log_filter = init_tracing();
+    let result = run(&log_filter).await;
+    #[cfg(feature = "otlp")]
+    otlp::shutdown();
+    result
+}
 
-    // handle docker events in a new task
-    spawn(async move {
-        events
-            .handle_events(docker, Duration::from_secs(config.event_timeout))
-            .await;
-    });
+/// Run the command given on the command line
+async fn run(log_filter: &reload::Handle<EnvFilter, Registry>) -> Result<()> {
+    // load config from command line flags, environment variables and the
+    // configuration file
+    let mut cli = Cli::parse();
+    if cli.oneshot {
+        cli.overrides.mode = Some(RunMode::Oneshot);
+    }
+    let config = load_config(Some(&cli.overrides))?;
+    apply_log_filter(log_filter, &config)?;
+    if let Some(Command::CheckConfig) = cli.command {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
 
-    // periodically refresh docker container list in case we miss some events
-    let cont = containers.clone();
-    spawn(async move {
-        let duration = Duration::from_secs(config.fetch_interval);
-        loop {
-            sleep(duration).await;
-            if let Err(err) = timeout(Duration::from_secs(config.fetch_timeout), async {
-                cont.fetch_containers()
-                    .await
-                    .context("failed to fetch containers")
-            })
-            .await
-            .context("failed to fetch containers in time")
-            .and_then(|res| res)
-            {
-                error!("{err:#}");
-            }
-        }
-    });
+    if cli.list {
+        return docker_healthchecks::list_containers(&config, cli.full_urls, cli.json).await;
+    }
+    docker_healthchecks::run(config, Some(cli.overrides)).await
+}
 
-    // periodically ping the healthcheck urls of the monitored containers
-    let mut interval = interval(Duration::from_secs(config.ping_interval));
-    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
-    loop {
-        interval.tick().await;
-        if let Err(err) = timeout(
-            Duration::from_secs(config.ping_timeout),
-            containers.ping_healthchecks(),
-        )
-        .await
-        .context("failed to ping healthchecks in time")
-        {
-            error!("{err:#}");
+/// Initialize the tracing subscriber with a reloadable log filter, which is
+/// taken from `RUST_LOG` or shows warnings by default
+fn init_tracing() -> reload::Handle<EnvFilter, Registry> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let (filter, handle) = reload::Layer::new(filter);
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer());
+    #[cfg(feature = "otlp")]
+    {
+        let (layer, err) = match otlp::layer() {
+            Ok(layer) => (layer, None),
+            Err(err) => (None, Some(err)),
+        };
+        registry.with(layer).init();
+        if let Some(err) = err {
+            error!("failed to initialize otlp exporter: {err}");
         }
     }
+    #[cfg(not(feature = "otlp"))]
+    registry.init();
+    handle
+}
+
+/// Replace the log filter with the configured log levels, unless `RUST_LOG`
+/// is set
+fn apply_log_filter(handle: &reload::Handle<EnvFilter, Registry>, config: &Config) -> Result<()> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(filter) => filter,
+        Err(_) => config.log_filter(),
+    };
+    handle
+        .reload(EnvFilter::try_new(&filter).context("invalid log filter")?)
+        .context("failed to apply log filter")?;
+    info!("using log filter {filter}");
+    Ok(())
 }
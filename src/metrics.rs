@@ -0,0 +1,201 @@
+//! Prometheus metrics describing the behavior of the daemon itself. The
+//! names and labels of the metrics are part of the public interface and must
+//! not be changed.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use metrics::{describe_counter, describe_histogram, Unit};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use tokio::{spawn, time::interval};
+
+/// Counter of ping sequences (including retries) by `result` (`success` or
+/// `failure`)
+pub const PINGS: &str = "dhc_pings_total";
+
+/// Counter of failed ping attempts by `reason` (`timeout`, `connect`,
+/// `status` or `other`)
+pub const PING_FAILURES: &str = "dhc_ping_failures_total";
+
+/// Counter of retried ping attempts
+pub const PING_RETRIES: &str = "dhc_ping_retries_total";
+
+/// Histogram of the duration of single ping attempts
+pub const PING_DURATION: &str = "dhc_ping_duration_seconds";
+
+/// Counter of reconnects of the docker event stream
+pub const EVENT_STREAM_RECONNECTS: &str = "dhc_event_stream_reconnects_total";
+
+/// Counter of handled docker events by `type` (`start`, `die`,
+/// `health_status` or `other`)
+pub const EVENTS: &str = "dhc_events_total";
+
+/// Counter of docker events whose handling failed by `reason` (`error` or
+/// `timeout`)
+pub const EVENT_FAILURES: &str = "dhc_event_failures_total";
+
+/// Histogram of the duration of full container fetches
+pub const FETCH_DURATION: &str = "dhc_fetch_duration_seconds";
+
+/// Counter of failed full container fetches
+pub const FETCH_FAILURES: &str = "dhc_fetch_failures_total";
+
+/// Histogram of the time spent waiting for a `lock` (`containers`)
+pub const LOCK_WAIT: &str = "dhc_lock_wait_seconds";
+
+/// Buckets of the duration histograms in seconds
+const BUCKETS: &[f64] = &[
+    0.0001, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Interval in which the recorded histograms are compacted
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Install the global metrics recorder and return a handle to render the
+/// metrics. Metrics that are recorded before are discarded.
+pub fn install() -> Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets(BUCKETS)?
+        .install_recorder()?;
+
+    describe_counter!(PINGS, "Ping sequences (including retries) by result");
+    describe_counter!(PING_FAILURES, "Failed ping attempts by reason");
+    describe_counter!(PING_RETRIES, "Retried ping attempts");
+    describe_histogram!(
+        PING_DURATION,
+        Unit::Seconds,
+        "Duration of single ping attempts"
+    );
+    describe_counter!(
+        EVENT_STREAM_RECONNECTS,
+        "Reconnects of the docker event stream"
+    );
+    describe_counter!(EVENTS, "Handled docker events by type");
+    describe_counter!(
+        EVENT_FAILURES,
+        "Docker events whose handling failed by reason"
+    );
+    describe_histogram!(
+        FETCH_DURATION,
+        Unit::Seconds,
+        "Duration of full container fetches"
+    );
+    describe_counter!(FETCH_FAILURES, "Failed full container fetches");
+    describe_histogram!(LOCK_WAIT, Unit::Seconds, "Time spent waiting for a lock");
+
+    spawn({
+        let handle = handle.clone();
+        async move {
+            let mut upkeep = interval(UPKEEP_INTERVAL);
+            loop {
+                upkeep.tick().await;
+                handle.run_upkeep();
+            }
+        }
+    });
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ::metrics::counter;
+    use reqwest::Client;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::{
+        config::Config,
+        container_manager::{ContainerManager, Health},
+        docker::fake::{self, FakeRuntime},
+        healthchecks::Healthchecks,
+    };
+
+    #[tokio::test]
+    async fn scrape_exposes_the_documented_metrics() {
+        let handle = install().unwrap();
+
+        // a successful ping and a failed one which is retried once
+        let server = MockServer::start().await;
+        Mock::given(path("/fail/fail"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let config = Config {
+            ping_retries: 1,
+            ping_timeout: Duration::from_secs(1),
+            ..Config::default()
+        };
+        let healthchecks = Arc::new(Healthchecks::new(Client::new(), &config));
+        let ok = Arc::from(format!("{}/ok", server.uri()));
+        let fail = Arc::from(format!("{}/fail", server.uri()));
+        healthchecks
+            .ping(&ok, &Health::Healthy, "", healthchecks.next_generation())
+            .await
+            .unwrap();
+        healthchecks
+            .ping(
+                &fail,
+                &Health::Unhealthy,
+                "",
+                healthchecks.next_generation(),
+            )
+            .await
+            .unwrap_err();
+
+        // a container fetch
+        let runtime = FakeRuntime::default();
+        runtime.insert(fake::container(
+            "a",
+            "a",
+            &[("healthchecks.url", &ok)],
+            Some("healthy"),
+        ));
+        let manager = ContainerManager::new(runtime, healthchecks, &config, None);
+        manager.fetch_containers().await.unwrap();
+
+        // the event stream needs a docker daemon, so its metrics are recorded
+        // directly
+        counter!(EVENTS, "type" => "start").increment(1);
+        counter!(EVENT_FAILURES, "reason" => "error").increment(1);
+        counter!(EVENT_STREAM_RECONNECTS).increment(1);
+        counter!(FETCH_FAILURES).increment(1);
+
+        let scrape = handle.render();
+        for (name, kind) in [
+            (PINGS, "counter"),
+            (PING_FAILURES, "counter"),
+            (PING_RETRIES, "counter"),
+            (PING_DURATION, "histogram"),
+            (EVENT_STREAM_RECONNECTS, "counter"),
+            (EVENTS, "counter"),
+            (EVENT_FAILURES, "counter"),
+            (FETCH_DURATION, "histogram"),
+            (FETCH_FAILURES, "counter"),
+            (LOCK_WAIT, "histogram"),
+        ] {
+            assert!(
+                scrape.contains(&format!("# TYPE {name} {kind}\n")),
+                "{name} missing in scrape:\n{scrape}"
+            );
+        }
+        // other tests may record metrics concurrently, so only the series are
+        // checked
+        for series in [
+            r#"dhc_pings_total{result="success"} "#,
+            r#"dhc_pings_total{result="failure"} "#,
+            r#"dhc_ping_failures_total{reason="status"} "#,
+            r#"dhc_lock_wait_seconds_bucket{lock="containers","#,
+        ] {
+            assert!(scrape.contains(series), "{series} missing in scrape");
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Export of traces via OTLP, configured using the standard `OTEL_*`
+//! environment variables (requires the `otlp` feature)
+
+use std::env;
+
+use opentelemetry::{
+    global,
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_sdk::{
+    runtime,
+    trace::{Config, Tracer},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Name of the service reported if it is not set via `OTEL_SERVICE_NAME` or
+/// `OTEL_RESOURCE_ATTRIBUTES`
+const SERVICE_NAME: &str = "docker-healthchecks";
+
+/// Create a tracing layer that exports all spans via OTLP/HTTP, if an OTLP
+/// endpoint is configured
+pub fn layer<S>() -> Result<Option<OpenTelemetryLayer<S, Tracer>>, TraceError>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|name| env::var_os(name).is_some());
+    if !configured || env::var("OTEL_SDK_DISABLED").is_ok_and(|value| value == "true") {
+        return Ok(None);
+    }
+
+    let mut resource = Resource::default();
+    let service_name_set = env::var_os("OTEL_SERVICE_NAME").is_some()
+        || env::var("OTEL_RESOURCE_ATTRIBUTES").is_ok_and(|attrs| attrs.contains("service.name"));
+    if !service_name_set {
+        resource = resource.merge(&Resource::new([KeyValue::new(
+            "service.name",
+            SERVICE_NAME,
+        )]));
+    }
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().http())
+        .with_trace_config(Config::default().with_resource(resource))
+        .install_batch(runtime::Tokio)?;
+    let tracer = provider.tracer(SERVICE_NAME);
+    global::set_tracer_provider(provider);
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Export all pending spans and stop the exporter
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
@@ -0,0 +1,493 @@
+//! HTTP, TCP and exec probes of containers whose health is not reported by a docker
+//! healthcheck
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use docker_api::{
+    conn::TtyChunk,
+    models::{EventActor, EventMessage, NetworkSettings},
+};
+use futures_util::StreamExt;
+use reqwest::{Client, Url};
+use tokio::{
+    net::TcpStream,
+    spawn,
+    sync::{mpsc::UnboundedSender, Semaphore},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::{
+    container_manager::{short_id, Health},
+    docker::runtime::ContainerRuntime,
+};
+
+/// Default interval between two probes of a container
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default duration after which a probe is considered failed
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum number of bytes of the output of an exec probe that are kept
+const MAX_EXEC_OUTPUT: usize = 4096;
+
+/// Address at which the probed endpoint of a container is reached if the
+/// probe url points to `localhost`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProbeTarget {
+    /// The ip address of the container
+    #[default]
+    Container,
+
+    /// The port on the docker host to which the port of the probe url is
+    /// published
+    Published,
+}
+
+impl FromStr for ProbeTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "container" => Ok(Self::Container),
+            "published" => Ok(Self::Published),
+            _ => bail!("expected `container` or `published`"),
+        }
+    }
+}
+
+/// Inclusive range of http status codes that are considered healthy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusRange {
+    /// Lowest healthy status code
+    min: u16,
+
+    /// Highest healthy status code
+    max: u16,
+}
+
+impl Default for StatusRange {
+    fn default() -> Self {
+        Self { min: 200, max: 399 }
+    }
+}
+
+impl FromStr for StatusRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = s.split_once('-').unwrap_or((s, s));
+        let range = Self {
+            min: min.trim().parse()?,
+            max: max.trim().parse()?,
+        };
+        if range.min > range.max {
+            bail!("empty status range");
+        }
+        Ok(range)
+    }
+}
+
+/// Check that is performed by a probe
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProbeKind {
+    /// Request the url with `GET` and check the status code of the response
+    Http {
+        /// Url that is requested, with `localhost` replaced by the address of
+        /// the container
+        url: Url,
+
+        /// Status codes that are considered healthy
+        status: StatusRange,
+    },
+
+    /// Open a tcp connection to the address
+    Tcp {
+        /// Host to which the connection is opened
+        host: String,
+
+        /// Port to which the connection is opened
+        port: u16,
+    },
+
+    /// Run the shell command in the container and check its exit code
+    Exec(String),
+}
+
+impl Display for ProbeKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http { url, .. } => write!(f, "{url}"),
+            Self::Tcp { host, port } => write!(f, "tcp://{host}:{port}"),
+            Self::Exec(command) => write!(f, "exec: {command}"),
+        }
+    }
+}
+
+/// Probe of a container
+#[derive(Debug, PartialEq, Eq)]
+pub struct Probe {
+    /// Check that is performed
+    pub kind: ProbeKind,
+
+    /// Interval between two probes
+    pub interval: Duration,
+
+    /// Duration after which a probe is considered failed
+    pub timeout: Duration,
+}
+
+impl Display for Probe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+/// Network of a container over which it is probed
+pub struct ProbeNetwork<'a> {
+    /// Network settings of the container
+    pub settings: Option<&'a NetworkSettings>,
+
+    /// Network mode of the container
+    pub mode: Option<&'a str>,
+
+    /// Name of the network whose ip address is used if the container is
+    /// connected to multiple networks
+    pub name: Option<&'a str>,
+
+    /// How the address of the container is resolved
+    pub target: ProbeTarget,
+}
+
+/// Health status of a container determined by a probe
+pub struct ProbeResult {
+    /// Id of the probed container
+    pub id: Arc<str>,
+
+    /// Name of the probed container
+    pub name: Option<String>,
+
+    /// Health status of the container
+    pub health: Health,
+
+    /// Time at which the probe completed
+    pub time: SystemTime,
+
+    /// Output of an exec probe
+    pub output: Option<String>,
+}
+
+/// Resources shared by the probes of a docker host
+#[derive(Clone)]
+pub struct ProbeContext {
+    /// Http client used for http probes
+    pub client: Client,
+
+    /// Container runtime in which exec probes are run
+    pub runtime: Arc<dyn ContainerRuntime>,
+
+    /// Semaphore limiting the number of exec probes that run concurrently
+    pub exec_permits: Arc<Semaphore>,
+
+    /// Sender of the results of the probes
+    pub results: UnboundedSender<ProbeResult>,
+}
+
+impl ProbeResult {
+    /// Turn the result into a health status event, as if it had been emitted
+    /// by the docker daemon
+    #[must_use]
+    pub fn into_event(self) -> EventMessage {
+        let status = match self.health {
+            Health::Healthy => "healthy",
+            Health::Unhealthy => "unhealthy",
+            Health::Starting => "starting",
+        };
+        let attributes = self
+            .name
+            .map(|name| HashMap::from([("name".to_owned(), name)]));
+        EventMessage {
+            action: Some(format!("health_status: {status}")),
+            actor: Some(EventActor {
+                attributes,
+                id: Some(self.id.to_string()),
+            }),
+            type_: Some("container".to_owned()),
+            scope: Some("local".to_owned()),
+            time: None,
+            time_nano: self
+                .time
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .and_then(|time| i64::try_from(time.as_nanos()).ok()),
+        }
+    }
+}
+
+impl ProbeNetwork<'_> {
+    /// Return the host and port at which the given port of the container is
+    /// reached from the docker host
+    pub fn resolve(&self, port: u16) -> Result<(String, u16)> {
+        // containers using host networking share the ports of the docker host
+        if self.mode == Some("host") {
+            return Ok(("127.0.0.1".to_owned(), port));
+        }
+        match self.target {
+            ProbeTarget::Container => Ok((self.container_ip()?, port)),
+            ProbeTarget::Published => {
+                let host_port = self
+                    .settings
+                    .and_then(|settings| settings.ports.as_ref())
+                    .and_then(|ports| ports.get(&format!("{port}/tcp")))
+                    .and_then(Option::as_ref)
+                    .and_then(|bindings| {
+                        bindings
+                            .iter()
+                            .find_map(|binding| binding.host_port.as_deref())
+                    })
+                    .ok_or_else(|| anyhow!("port {port} is not published"))?
+                    .parse()
+                    .context("invalid published port")?;
+                Ok(("127.0.0.1".to_owned(), host_port))
+            }
+        }
+    }
+
+    /// Return the ip address of the container in the configured network, or
+    /// in the first network with an ip address ordered by name
+    fn container_ip(&self) -> Result<String> {
+        let settings = self
+            .settings
+            .ok_or_else(|| anyhow!("container has no network settings"))?;
+        let networks = settings.networks.as_ref();
+        let ip = match self.name {
+            Some(name) => networks
+                .and_then(|networks| networks.get(name))
+                .ok_or_else(|| anyhow!("container is not connected to network {name}"))?
+                .ip_address
+                .clone(),
+            None => settings
+                .ip_address
+                .clone()
+                .filter(|ip| !ip.is_empty())
+                .or_else(|| {
+                    networks?
+                        .iter()
+                        .filter(|(_, endpoint)| {
+                            endpoint
+                                .ip_address
+                                .as_ref()
+                                .is_some_and(|ip| !ip.is_empty())
+                        })
+                        .min_by_key(|(name, _)| *name)
+                        .and_then(|(_, endpoint)| endpoint.ip_address.clone())
+                }),
+        };
+        ip.filter(|ip| !ip.is_empty())
+            .ok_or_else(|| anyhow!("container has no ip address"))
+    }
+
+    /// Return the url of an http probe. If the url points to `localhost`, its
+    /// host and port are replaced by the address of the container.
+    pub fn resolve_url(&self, url: &str) -> Result<Url> {
+        let mut url = Url::parse(url)?;
+        if !matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")) {
+            return Ok(url);
+        }
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow!("url has no port"))?;
+        let (host, port) = self.resolve(port)?;
+        url.set_host(Some(&host))?;
+        url.set_port(Some(port))
+            .map_err(|()| anyhow!("cannot set port of url"))?;
+        Ok(url)
+    }
+}
+
+impl Probe {
+    /// Perform the check and return whether the container is healthy,
+    /// together with the output of an exec probe
+    async fn check(&self, context: &ProbeContext, id: &str) -> (Health, Option<String>) {
+        let health = match &self.kind {
+            ProbeKind::Http { url, status } => {
+                let result = context
+                    .client
+                    .get(url.clone())
+                    .timeout(self.timeout)
+                    .send()
+                    .await;
+                match result {
+                    Ok(response) => {
+                        let code = response.status().as_u16();
+                        if (status.min..=status.max).contains(&code) {
+                            Health::Healthy
+                        } else {
+                            debug!(http.status_code = code, "probe failed");
+                            Health::Unhealthy
+                        }
+                    }
+                    Err(err) => {
+                        debug!(error = %err.without_url(), "probe failed");
+                        Health::Unhealthy
+                    }
+                }
+            }
+            ProbeKind::Tcp { host, port } => {
+                match timeout(self.timeout, TcpStream::connect((host.as_str(), *port))).await {
+                    Ok(Ok(_)) => Health::Healthy,
+                    Ok(Err(err)) => {
+                        debug!(error = %err, "probe failed");
+                        Health::Unhealthy
+                    }
+                    Err(_) => {
+                        debug!("probe timed out");
+                        Health::Unhealthy
+                    }
+                }
+            }
+            ProbeKind::Exec(command) => {
+                let (health, output) = self.exec(context, id, command).await;
+                return (health, Some(output));
+            }
+        };
+        (health, None)
+    }
+
+    /// Run the command of an exec probe in the container and return the
+    /// health status determined by its exit code and its output
+    async fn exec(&self, context: &ProbeContext, id: &str, command: &str) -> (Health, String) {
+        // limit the load on the docker daemon. the semaphore is never closed.
+        let Ok(_permit) = context.exec_permits.acquire().await else {
+            return (Health::Unhealthy, String::new());
+        };
+
+        // the shell prints its pid before it is replaced by the command, so
+        // that the command can be killed if it times out, as docker provides
+        // no way to stop an exec instance
+        let command = [
+            "/bin/sh".to_owned(),
+            "-c".to_owned(),
+            r#"echo $$; exec /bin/sh -c "$1""#.to_owned(),
+            "sh".to_owned(),
+            command.to_owned(),
+        ];
+        let mut pid = None;
+        let mut output = Vec::new();
+        let runtime = &*context.runtime;
+        let run = async {
+            let exec = runtime.create_exec(id, &command).await?;
+            let mut stream = runtime.start_exec(&exec).await?;
+            while let Some(chunk) = stream.next().await {
+                let (TtyChunk::StdOut(chunk) | TtyChunk::StdErr(chunk)) = chunk? else {
+                    continue;
+                };
+                let mut bytes = chunk.as_slice();
+                if pid.is_none() {
+                    if let Some((line, rest)) = split_line(bytes) {
+                        pid = std::str::from_utf8(line)
+                            .ok()
+                            .and_then(|line| line.parse().ok());
+                        bytes = rest;
+                    }
+                }
+                let len = bytes
+                    .len()
+                    .min(MAX_EXEC_OUTPUT.saturating_sub(output.len()));
+                output.extend_from_slice(&bytes[..len]);
+            }
+            runtime.inspect_exec(&exec).await
+        };
+        let health = match timeout(self.timeout, run).await {
+            Ok(Ok(exec)) => match exec.exit_code {
+                Some(0) => Health::Healthy,
+                code => {
+                    debug!(exit_code = code, "probe failed");
+                    Health::Unhealthy
+                }
+            },
+            Ok(Err(err)) => {
+                debug!(error = %err, "probe failed");
+                Health::Unhealthy
+            }
+            Err(_) => {
+                debug!("probe timed out");
+                if let Some(pid) = pid {
+                    kill(runtime, id, pid).await;
+                }
+                Health::Unhealthy
+            }
+        };
+        (health, String::from_utf8_lossy(&output).into_owned())
+    }
+}
+
+/// Split the first line off the given bytes
+fn split_line(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = bytes.iter().position(|&byte| byte == b'\n')?;
+    Some((&bytes[..pos], &bytes[pos + 1..]))
+}
+
+/// Kill the process of a timed out exec probe in the container
+async fn kill(runtime: &dyn ContainerRuntime, id: &str, pid: u32) {
+    let command = [
+        "/bin/sh".to_owned(),
+        "-c".to_owned(),
+        format!("kill -KILL {pid}"),
+    ];
+    let result = async {
+        let exec = runtime.create_exec(id, &command).await?;
+        runtime
+            .start_exec(&exec)
+            .await?
+            .for_each(|_| async {})
+            .await;
+        Ok::<_, docker_api::Error>(())
+    };
+    if let Err(err) = result.await {
+        warn!(error = %err, "failed to kill timed out probe");
+    }
+}
+
+/// Probe a container in the configured interval and send the results to the
+/// channel of the context until the task is aborted
+pub fn spawn_probe(
+    probe: Arc<Probe>,
+    context: ProbeContext,
+    id: Arc<str>,
+    name: Option<String>,
+) -> JoinHandle<()> {
+    let span = info_span!(
+        parent: None,
+        "probe",
+        container.id = short_id(&id),
+        container.name = name.as_deref(),
+    );
+    spawn(
+        async move {
+            loop {
+                sleep(probe.interval).await;
+                let (health, output) = probe.check(&context, &id).await;
+                let result = ProbeResult {
+                    id: id.clone(),
+                    name: name.clone(),
+                    health,
+                    time: SystemTime::now(),
+                    output,
+                };
+                if context.results.send(result).is_err() {
+                    return;
+                }
+            }
+        }
+        .instrument(span),
+    )
+}
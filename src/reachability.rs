@@ -0,0 +1,73 @@
+//! Track whether a docker daemon is reachable
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tracing::{info, warn};
+
+use crate::state;
+
+/// Reachability of a docker daemon, updated by every interaction with it
+pub struct Reachability {
+    /// Whether the last interaction with the docker daemon succeeded
+    reachable: AtomicBool,
+
+    /// Unix timestamp of the last successful interaction with the docker
+    /// daemon
+    last_success: AtomicU64,
+}
+
+impl Default for Reachability {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reachability {
+    /// Create a new tracker for a docker daemon that has just been reached
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            reachable: AtomicBool::new(true),
+            last_success: AtomicU64::new(state::unix_time()),
+        }
+    }
+
+    /// Record a successful interaction with the docker daemon
+    pub fn succeeded(&self) {
+        self.last_success
+            .store(state::unix_time(), Ordering::Relaxed);
+        if !self.reachable.swap(true, Ordering::Relaxed) {
+            info!("docker daemon is reachable again");
+        }
+    }
+
+    /// Record a failed interaction with the docker daemon
+    pub fn failed(&self) {
+        if self.reachable.swap(false, Ordering::Relaxed) {
+            warn!("docker daemon is unreachable");
+        }
+    }
+
+    /// Return whether the last interaction with the docker daemon succeeded
+    pub fn reachable(&self) -> bool {
+        self.reachable.load(Ordering::Relaxed)
+    }
+
+    /// Return the unix timestamp of the last successful interaction with the
+    /// docker daemon
+    pub fn last_success(&self) -> u64 {
+        self.last_success.load(Ordering::Relaxed)
+    }
+
+    /// Return for how long the docker daemon has been unreachable, if it is
+    pub fn down_for(&self) -> Option<Duration> {
+        if self.reachable() {
+            return None;
+        }
+        let elapsed = state::unix_time().saturating_sub(self.last_success());
+        Some(Duration::from_secs(elapsed))
+    }
+}
@@ -0,0 +1,71 @@
+//! Retrying of the startup steps until the docker daemon is ready
+
+use std::{future::Future, time::Duration};
+
+use anyhow::Result;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+
+/// Delay before the first retry of a failed startup step
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Maximum delay between retries of a failed startup step
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Deadline until which failed startup steps are retried
+#[derive(Debug, Clone, Copy)]
+pub enum Deadline {
+    /// Fail on the first error
+    FailFast,
+
+    /// Retry until the given instant
+    At(Instant),
+
+    /// Retry forever
+    Never,
+}
+
+impl Deadline {
+    /// Create the deadline for the given startup timeout, starting now. A
+    /// timeout of zero never expires.
+    pub fn new(startup_timeout: Option<Duration>) -> Self {
+        match startup_timeout {
+            None => Self::FailFast,
+            Some(timeout) if timeout.is_zero() => Self::Never,
+            Some(timeout) => Self::At(Instant::now() + timeout),
+        }
+    }
+
+    /// Run the given startup step and retry it with exponential backoff until
+    /// it succeeds or the deadline is exceeded
+    pub async fn retry<T, F, Fut>(self, step: &str, mut run: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut delay = MIN_RETRY_DELAY;
+        loop {
+            let err = match run().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            let wait = match self {
+                Self::FailFast => return Err(err),
+                Self::Never => delay,
+                Self::At(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(err.context(format!("{step} timed out during startup")));
+                    }
+                    delay.min(remaining)
+                }
+            };
+            warn!(
+                "{step} failed, retrying in {:.1}s: {err:#}",
+                wait.as_secs_f64()
+            );
+            sleep(wait).await;
+            delay = (delay * 2).min(MAX_RETRY_DELAY);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Persistence of the healthchecks state across restarts
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{debug, warn};
+
+use crate::container_manager::Health;
+
+/// Snapshot of the pings that have been sent to healthchecks
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Unix timestamp of the time the snapshot was taken
+    pub saved: u64,
+
+    /// Mapping from ping urls to the state of their checks
+    pub checks: HashMap<String, CheckState>,
+}
+
+/// State of a single healthchecks check
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckState {
+    /// Last successfully reported health status
+    pub health: Health,
+
+    /// Whether the last ping was a starting ping
+    pub starting: bool,
+
+    /// Unix timestamp of the last successful ping
+    pub last_ping: u64,
+}
+
+/// Load a snapshot from the given path. Missing, corrupted or stale
+/// snapshots are ignored.
+pub async fn load(path: &str, max_age: Duration) -> Option<State> {
+    let data = match fs::read(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            debug!("state file {path} does not exist");
+            return None;
+        }
+        Err(err) => {
+            warn!("failed to read state file {path}: {err}");
+            return None;
+        }
+    };
+    let state: State = match serde_json::from_slice(&data) {
+        Ok(state) => state,
+        Err(err) => {
+            warn!("ignoring corrupted state file {path}: {err}");
+            return None;
+        }
+    };
+    if unix_time().saturating_sub(state.saved) > max_age.as_secs() {
+        warn!("ignoring stale state file {path}");
+        return None;
+    }
+    Some(state)
+}
+
+/// Atomically write a snapshot to the given path
+pub async fn save(path: &str, state: &State) -> Result<()> {
+    let data = serde_json::to_vec(state).context("failed to serialize state")?;
+    let tmp = format!("{path}.tmp");
+    fs::write(&tmp, data)
+        .await
+        .with_context(|| format!("failed to write state file {tmp}"))?;
+    fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("failed to replace state file {path}"))?;
+    debug!("saved state to {path}");
+    Ok(())
+}
+
+/// Convert a system time to a unix timestamp in seconds
+#[must_use]
+pub fn to_unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Return the current unix timestamp in seconds
+#[must_use]
+pub fn unix_time() -> u64 {
+    to_unix(SystemTime::now())
+}
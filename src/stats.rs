@@ -0,0 +1,191 @@
+//! Sampling the docker stats of containers to mark them as unhealthy while
+//! they exceed their resource thresholds
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde_json::Value;
+use tokio::{spawn, sync::mpsc::UnboundedSender, task::JoinHandle, time::interval};
+use tracing::{debug, info_span, warn, Instrument};
+
+use crate::{container_manager::short_id, docker::runtime::ContainerRuntime};
+
+/// Default duration over which the cpu usage of a container has to exceed
+/// its threshold
+pub const DEFAULT_CPU_WINDOW: Duration = Duration::from_secs(600);
+
+/// Resource thresholds of a container
+#[derive(Debug, PartialEq)]
+pub struct ResourceLimits {
+    /// Maximum cpu usage in percent as shown by `docker stats`
+    pub max_cpu: Option<f64>,
+
+    /// Maximum memory usage in percent of the memory limit
+    pub max_mem: Option<f64>,
+
+    /// Duration over which the average cpu usage has to exceed `max_cpu`
+    pub cpu_window: Duration,
+}
+
+impl Display for ResourceLimits {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "stats:")?;
+        if let Some(max_cpu) = self.max_cpu {
+            write!(f, " cpu <= {max_cpu}%")?;
+        }
+        if let Some(max_mem) = self.max_mem {
+            write!(f, " mem <= {max_mem}%")?;
+        }
+        Ok(())
+    }
+}
+
+/// Change of whether a container exceeds its resource thresholds
+pub struct ResourceBreach {
+    /// Id of the container
+    pub id: Arc<str>,
+
+    /// Description of the exceeded threshold, or `None` if the container has
+    /// recovered
+    pub reason: Option<String>,
+}
+
+/// Resource usage of a container at one point in time
+struct Sample {
+    /// Total cpu time used by the container
+    container_cpu: u64,
+
+    /// Total cpu time of the docker host
+    system_cpu: u64,
+
+    /// Number of cpus of the docker host
+    cpus: u64,
+
+    /// Memory usage in percent of the memory limit
+    mem: Option<f64>,
+}
+
+impl Sample {
+    /// Extract the resource usage from a docker stats response
+    fn parse(stats: &Value) -> Option<Self> {
+        let cpu = &stats["cpu_stats"];
+        let memory = &stats["memory_stats"];
+        // page cache is excluded from the memory usage like in `docker stats`
+        let cache = memory["stats"]["inactive_file"]
+            .as_u64()
+            .or_else(|| memory["stats"]["total_inactive_file"].as_u64())
+            .unwrap_or(0);
+        let mem = memory["usage"]
+            .as_u64()
+            .zip(memory["limit"].as_u64().filter(|&limit| limit > 0))
+            .map(|(usage, limit)| percent(usage.saturating_sub(cache), limit));
+        Some(Self {
+            container_cpu: cpu["cpu_usage"]["total_usage"].as_u64()?,
+            system_cpu: cpu["system_cpu_usage"].as_u64()?,
+            cpus: cpu["online_cpus"].as_u64().unwrap_or(1),
+            mem,
+        })
+    }
+
+    /// Return the cpu usage since the previous sample in percent as shown by
+    /// `docker stats`
+    fn cpu_since(&self, previous: &Self) -> Option<f64> {
+        let container = self.container_cpu.checked_sub(previous.container_cpu)?;
+        let system = self
+            .system_cpu
+            .checked_sub(previous.system_cpu)
+            .filter(|&system| system > 0)?;
+        #[allow(clippy::cast_precision_loss)]
+        Some(percent(container, system) * self.cpus as f64)
+    }
+}
+
+/// Return the given part of the total in percent
+#[allow(clippy::cast_precision_loss)]
+fn percent(part: u64, total: u64) -> f64 {
+    part as f64 / total as f64 * 100.0
+}
+
+/// Sample the docker stats of a container in the given interval and report
+/// to the given channel whenever it starts or stops exceeding its resource
+/// thresholds, until the container stops or the task is aborted
+pub fn spawn_stats_sampler(
+    limits: Arc<ResourceLimits>,
+    runtime: Arc<dyn ContainerRuntime>,
+    sample_interval: Duration,
+    id: Arc<str>,
+    name: Option<&str>,
+    breaches: UnboundedSender<ResourceBreach>,
+) -> JoinHandle<()> {
+    let span = info_span!(
+        parent: None,
+        "stats_sampler",
+        container.id = short_id(&id),
+        container.name = name,
+    );
+    // the average cpu usage is computed from the cpu usage between consecutive
+    // samples within the window
+    let window_size = usize::try_from(limits.cpu_window.as_secs() / sample_interval.as_secs())
+        .unwrap_or(usize::MAX)
+        .max(1);
+    spawn(
+        async move {
+            let mut ticks = interval(sample_interval);
+            let mut previous: Option<Sample> = None;
+            let mut cpu_window = VecDeque::new();
+            let mut exceeded = false;
+            loop {
+                ticks.tick().await;
+                let sample = match runtime.container_stats(&id).await {
+                    Ok(stats) => Sample::parse(&stats),
+                    Err(err) => {
+                        warn!(error = %err, "failed to sample container stats");
+                        continue;
+                    }
+                };
+                let Some(sample) = sample else {
+                    debug!("container stats are incomplete");
+                    continue;
+                };
+                if let Some(cpu) = previous.as_ref().and_then(|prev| sample.cpu_since(prev)) {
+                    if cpu_window.len() >= window_size {
+                        cpu_window.pop_front();
+                    }
+                    cpu_window.push_back(cpu);
+                }
+
+                #[allow(clippy::cast_precision_loss)]
+                let cpu = (cpu_window.len() >= window_size)
+                    .then(|| cpu_window.iter().sum::<f64>() / cpu_window.len() as f64);
+                let reason = match (limits.max_cpu, cpu, limits.max_mem, sample.mem) {
+                    (_, _, Some(max), Some(mem)) if mem > max => {
+                        Some(format!("memory usage {mem:.1}% exceeds {max}%"))
+                    }
+                    (Some(max), Some(cpu), _, _) if cpu > max => Some(format!(
+                        "cpu usage {cpu:.1}% over {} exceeds {max}%",
+                        humantime::format_duration(limits.cpu_window)
+                    )),
+                    _ => None,
+                };
+                previous = Some(sample);
+                if reason.is_some() == exceeded {
+                    continue;
+                }
+                exceeded = reason.is_some();
+                debug!(reason, "resource threshold breach changed");
+                let breach = ResourceBreach {
+                    id: id.clone(),
+                    reason,
+                };
+                if breaches.send(breach).is_err() {
+                    return;
+                }
+            }
+        }
+        .instrument(span),
+    )
+}
@@ -0,0 +1,439 @@
+//! HTTP endpoint reporting the internal state
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::future::join_all;
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, spawn, sync::watch, task::JoinHandle, time::timeout};
+use tracing::{error, info, warn, Instrument};
+
+use crate::{
+    check_liveness,
+    container_manager::Health,
+    dump::{self, redact_url, ContainerDump, EventStreamDump, PingAttempt},
+    healthchecks::Healthchecks,
+    heartbeat::Heartbeat,
+    state::CheckState,
+    Endpoint,
+};
+
+/// Maximum duration to wait for open connections on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum duration to wait for the docker daemon to respond to a ping in the
+/// readiness check
+const READY_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Running status server
+pub struct Server {
+    /// Dropped to initiate the graceful shutdown of the server
+    shutdown: watch::Sender<()>,
+
+    /// Task running the server
+    task: JoinHandle<()>,
+}
+
+/// State shared by the request handlers
+struct Shared {
+    /// Monitored docker hosts
+    endpoints: Arc<[Endpoint]>,
+
+    /// Healthchecks.io interface
+    healthchecks: Arc<Healthchecks>,
+
+    /// Bearer token required to access the status endpoint
+    token: Option<String>,
+
+    /// Bearer token required to access the admin endpoints
+    admin_token: Option<String>,
+
+    /// Handle to render the prometheus metrics
+    metrics: Option<PrometheusHandle>,
+
+    /// Heartbeat of the ping loop
+    ping_loop: Arc<Heartbeat>,
+}
+
+/// Result of a liveness or readiness check
+#[derive(Serialize)]
+struct Check {
+    /// `ok` or `failing`
+    status: &'static str,
+
+    /// Reason why the check is failing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl Check {
+    /// Turn the result of a check into a response
+    fn respond(result: Result<(), String>) -> Response {
+        match result {
+            Ok(()) => Json(Self {
+                status: "ok",
+                reason: None,
+            })
+            .into_response(),
+            Err(reason) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(Self {
+                    status: "failing",
+                    reason: Some(reason),
+                }),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Query parameters of the status endpoint
+#[derive(Deserialize)]
+struct StatusQuery {
+    /// Whether the full ping urls are included (`1` or `true`)
+    full: Option<String>,
+}
+
+/// Query parameters of the admin ping endpoint
+#[derive(Deserialize)]
+struct PingQuery {
+    /// Ping url of a monitored container
+    url: Option<String>,
+
+    /// Name or (abbreviated) id of a monitored container
+    container: Option<String>,
+}
+
+/// Result of a manual container fetch on a docker host
+#[derive(Serialize)]
+struct FetchResult {
+    /// Name of the docker host, if multiple hosts are monitored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+
+    /// `fetched`, `coalesced` (with a fetch that was already running) or
+    /// `failed`
+    result: &'static str,
+
+    /// Error of the failed fetch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Result of a manual ping
+#[derive(Serialize)]
+struct PingResult {
+    /// Redacted ping url
+    url: String,
+
+    /// Health status that has been reported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    health: Option<Health>,
+
+    /// Error of the failed ping
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Status document returned by `GET /status`
+#[derive(Serialize)]
+struct Status {
+    /// Monitored docker hosts
+    hosts: Vec<HostStatus>,
+
+    /// Last successful ping of each url
+    checks: HashMap<String, CheckState>,
+
+    /// Outcome of the last ping attempt of each url
+    ping_attempts: HashMap<String, PingAttempt>,
+}
+
+/// Status of a monitored docker host
+#[derive(Serialize)]
+struct HostStatus {
+    /// Name of the docker host, if multiple hosts are monitored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+
+    /// Monitored containers
+    containers: Vec<ContainerDump>,
+
+    /// Ids of containers without healthchecks label
+    ignored_containers: Vec<String>,
+
+    /// Unix timestamp of the last successful container fetch
+    last_fetch: Option<u64>,
+
+    /// State of the docker event stream
+    event_stream: EventStreamDump,
+
+    /// Whether the last interaction with the docker daemon succeeded
+    docker_reachable: bool,
+
+    /// Unix timestamp of the last successful interaction with the docker
+    /// daemon
+    docker_last_success: u64,
+}
+
+impl Server {
+    /// Listen on the given address and serve the status, metrics, liveness
+    /// and readiness endpoints in a new task. The admin endpoints are only
+    /// served if an admin token is set.
+    pub async fn start(
+        addr: &str,
+        token: Option<String>,
+        admin_token: Option<String>,
+        endpoints: Arc<[Endpoint]>,
+        healthchecks: Arc<Healthchecks>,
+        metrics: Option<PrometheusHandle>,
+        ping_loop: Arc<Heartbeat>,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to listen on {addr}"))?;
+        info!(
+            "serving status endpoint on http://{}/status",
+            listener.local_addr()?
+        );
+        let mut app = Router::new()
+            .route("/status", get(status))
+            .route("/metrics", get(render_metrics))
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz));
+        if admin_token.is_some() {
+            app = app
+                .route("/admin/fetch", post(admin_fetch))
+                .route("/admin/ping", post(admin_ping));
+        }
+        let app = app.with_state(Arc::new(Shared {
+            endpoints,
+            healthchecks,
+            token,
+            admin_token,
+            metrics,
+            ping_loop,
+        }));
+
+        let (shutdown, mut shutdown_rx) = watch::channel(());
+        let task = spawn(async move {
+            let signal = async move {
+                // resolves as soon as the sender has been dropped
+                while shutdown_rx.changed().await.is_ok() {}
+            };
+            if let Err(err) = axum::serve(listener, app)
+                .with_graceful_shutdown(signal)
+                .await
+            {
+                error!(
+                    "{:#}",
+                    anyhow::Error::from(err).context("status server failed")
+                );
+            }
+        });
+        Ok(Self { shutdown, task })
+    }
+
+    /// Stop accepting connections and wait for open connections to be closed
+    pub async fn shutdown(self) {
+        drop(self.shutdown);
+        if timeout(SHUTDOWN_TIMEOUT, self.task).await.is_err() {
+            warn!("status server did not shut down in time");
+        }
+    }
+}
+
+/// Return whether the request carries the given bearer token, if any
+fn authorized(token: Option<&str>, headers: &HeaderMap) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    bearer == Some(token)
+}
+
+/// Check whether the main loops are still ticking
+async fn healthz(State(shared): State<Arc<Shared>>) -> Response {
+    Check::respond(check_liveness(&shared.endpoints, &shared.ping_loop))
+}
+
+/// Check whether all docker hosts have been fetched and are reachable
+async fn readyz(State(shared): State<Arc<Shared>>) -> Response {
+    Check::respond(check_readiness(&shared).await)
+}
+
+/// Return an error if the initial container fetch has not completed, the
+/// docker daemon cannot be pinged or the event stream is down on any host
+async fn check_readiness(shared: &Shared) -> Result<(), String> {
+    for endpoint in shared.endpoints.iter() {
+        if endpoint.containers.last_fetch().await.is_none() {
+            return Err(format!(
+                "{} has not completed",
+                endpoint.describe("initial container fetch")
+            ));
+        }
+        match timeout(READY_PING_TIMEOUT, endpoint.docker.ping()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                return Err(format!(
+                    "{} failed: {err}",
+                    endpoint.describe("docker ping")
+                ));
+            }
+            Err(_) => return Err(format!("{} timed out", endpoint.describe("docker ping"))),
+        }
+        if endpoint.events.stream_status().down_since.is_some() {
+            return Err(format!(
+                "{} is down",
+                endpoint.describe("docker event stream")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Return the prometheus metrics, if the request is authorized
+async fn render_metrics(State(shared): State<Arc<Shared>>, headers: HeaderMap) -> Response {
+    if !authorized(shared.token.as_deref(), &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let Some(metrics) = &shared.metrics else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+        .into_response()
+}
+
+/// Return the status document, if the request is authorized
+async fn status(
+    State(shared): State<Arc<Shared>>,
+    Query(query): Query<StatusQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(shared.token.as_deref(), &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let full = matches!(query.full.as_deref(), Some("1" | "true"));
+
+    let mut hosts = Vec::new();
+    for endpoint in shared.endpoints.iter() {
+        let mut dump = endpoint.containers.dump().await;
+        if !full {
+            dump.redact();
+        }
+        hosts.push(HostStatus {
+            host: dump.host,
+            containers: dump.containers,
+            ignored_containers: dump.ignored_containers,
+            last_fetch: dump.last_fetch,
+            event_stream: endpoint.events.stream_status(),
+            docker_reachable: endpoint.containers.reachability().reachable(),
+            docker_last_success: endpoint.containers.reachability().last_success(),
+        });
+    }
+    let mut checks = shared.healthchecks.snapshot().await.checks;
+    let mut ping_attempts = shared.healthchecks.attempts().await;
+    if !full {
+        checks = dump::redact_keys(checks);
+        ping_attempts = dump::redact_keys(ping_attempts);
+    }
+    Json(Status {
+        hosts,
+        checks,
+        ping_attempts,
+    })
+    .into_response()
+}
+
+/// Fetch the containers of all docker hosts, if the request is authorized. A
+/// fetch that is already running is waited for instead of starting a new one.
+async fn admin_fetch(State(shared): State<Arc<Shared>>, headers: HeaderMap) -> Response {
+    if !authorized(shared.admin_token.as_deref(), &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    info!("manual container fetch requested");
+    let results: Vec<_> = join_all(shared.endpoints.iter().map(|endpoint| {
+        async {
+            let (result, error) = match endpoint.containers.fetch_coalesced().await {
+                Ok(true) => ("fetched", None),
+                Ok(false) => ("coalesced", None),
+                Err(err) => {
+                    error!(error = %format_args!("{err:#}"), "manual container fetch failed");
+                    ("failed", Some(format!("{err:#}")))
+                }
+            };
+            FetchResult {
+                host: endpoint.name.as_deref().map(str::to_owned),
+                result,
+                error,
+            }
+        }
+        .instrument(endpoint.span.clone())
+    }))
+    .await;
+    let status = if results.iter().any(|result| result.error.is_some()) {
+        StatusCode::BAD_GATEWAY
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(results)).into_response()
+}
+
+/// Ping the given url or the url of the given container, even if its health
+/// status has not changed, if the request is authorized
+async fn admin_ping(
+    State(shared): State<Arc<Shared>>,
+    Query(query): Query<PingQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if !authorized(shared.admin_token.as_deref(), &headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let mut target = None;
+    for endpoint in shared.endpoints.iter() {
+        let url = match (&query.url, &query.container) {
+            (Some(url), None) => endpoint.containers.monitored_url(url).await,
+            (None, Some(container)) => endpoint.containers.container_url(container).await,
+            _ => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    "exactly one of `url` and `container` is required",
+                )
+                    .into_response()
+            }
+        };
+        if let Some(url) = url {
+            target = Some((endpoint, url));
+            break;
+        }
+    }
+    let Some((endpoint, url)) = target else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    info!(url = %redact_url(&url), "manual ping requested");
+    let result = endpoint
+        .containers
+        .ping_one(&url, true)
+        .instrument(endpoint.span.clone())
+        .await;
+    let (status, health, error) = match result {
+        Ok(health) => (StatusCode::OK, Some(health), None),
+        Err(err) => (StatusCode::BAD_GATEWAY, None, Some(format!("{err:#}"))),
+    };
+    let url = redact_url(&url);
+    (status, Json(PingResult { url, health, error })).into_response()
+}
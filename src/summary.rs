@@ -0,0 +1,83 @@
+//! Summary of the health statuses of all monitored containers, which is posted
+//! to a single url after every round of pings
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{container_manager::Health, dump::redact_url, state};
+
+/// Name of the docker host (if multiple hosts are monitored), name, ping url
+/// and reported health status of a monitored container
+pub type ContainerHealth = (Option<Arc<str>>, String, Arc<str>, Health);
+
+/// Health status of a monitored container as included in the summary
+#[derive(Serialize)]
+struct Target {
+    /// Name of the docker host, if multiple hosts are monitored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+
+    /// Name of the container
+    name: String,
+
+    /// Ping url of the container without its secret parts
+    url: String,
+
+    /// Reported health status of the container
+    health: Health,
+
+    /// Unix timestamp since which the container has had this health status
+    since: u64,
+}
+
+/// Tracks since when the monitored containers have had their health status
+#[derive(Default)]
+pub struct Summary {
+    /// Mapping from docker host and container name to the health status of
+    /// the container in the last summary and the time since which it has had
+    /// this health status
+    since: HashMap<(Option<String>, String), (Health, u64)>,
+}
+
+impl Summary {
+    /// Record the health statuses of the given containers and return the
+    /// json summary of them. Containers that are no longer monitored are
+    /// forgotten.
+    pub fn update(&mut self, containers: Vec<ContainerHealth>) -> Result<String> {
+        let now = state::unix_time();
+        let mut seen = HashSet::new();
+        let mut targets: Vec<_> = containers
+            .into_iter()
+            .map(|(host, name, url, health)| {
+                let host = host.as_deref().map(str::to_owned);
+                let key = (host.clone(), name.clone());
+                let (_, since) = *self
+                    .since
+                    .entry(key.clone())
+                    .and_modify(|(last, since)| {
+                        if *last != health {
+                            *last = health;
+                            *since = now;
+                        }
+                    })
+                    .or_insert((health, now));
+                seen.insert(key);
+                Target {
+                    host,
+                    name,
+                    url: redact_url(&url),
+                    health,
+                    since,
+                }
+            })
+            .collect();
+        self.since.retain(|key, _| seen.contains(key));
+        targets.sort_unstable_by(|a, b| (&a.host, &a.name).cmp(&(&b.host, &b.name)));
+        Ok(serde_json::to_string(&targets)?)
+    }
+}
@@ -0,0 +1,121 @@
+//! Health of swarm services computed from the tasks across the cluster
+
+use anyhow::{Context, Result};
+use docker_api::models::Task;
+
+use crate::{container_manager::Health, docker::runtime::ContainerRuntime};
+
+/// Task states in which a task has not been started yet
+const PENDING_STATES: [&str; 7] = [
+    "new",
+    "allocated",
+    "pending",
+    "assigned",
+    "accepted",
+    "preparing",
+    "starting",
+];
+
+/// Health of a swarm service with a ping url
+pub struct ServiceHealth {
+    /// Ping url of the service
+    pub url: String,
+
+    /// Name of the service
+    pub name: String,
+
+    /// Health status of the service
+    pub health: Health,
+
+    /// Number of running tasks
+    pub running: usize,
+
+    /// Number of tasks that should be running
+    pub desired: usize,
+}
+
+/// Return whether the services are pinged by this instance, which is the
+/// case on the reachable manager with the lowest node id, so that each
+/// service is pinged exactly once per cluster
+pub async fn is_leader(runtime: &dyn ContainerRuntime) -> Result<bool> {
+    let info = runtime
+        .info()
+        .await
+        .context("failed to get docker system info")?;
+    let Some(swarm) = info
+        .swarm
+        .filter(|swarm| swarm.control_available == Some(true))
+    else {
+        return Ok(false);
+    };
+    let nodes = runtime
+        .list_nodes()
+        .await
+        .context("failed to list swarm nodes")?;
+    let leader = nodes
+        .iter()
+        .filter(|node| {
+            node.manager_status
+                .as_ref()
+                .is_some_and(|status| status.reachability.as_deref() == Some("reachable"))
+        })
+        .filter_map(|node| node.id.as_deref())
+        .min();
+    Ok(leader.is_some() && leader == swarm.node_id.as_deref())
+}
+
+/// Compute the health of all swarm services with a `healthchecks.url` label. A
+/// service is healthy as long as at least one of its tasks is running.
+pub async fn service_health(runtime: &dyn ContainerRuntime) -> Result<Vec<ServiceHealth>> {
+    let services = runtime
+        .list_services()
+        .await
+        .context("failed to list swarm services")?;
+    let mut result = Vec::new();
+    for service in services {
+        let (Some(id), Some(spec)) = (service.id, service.spec) else {
+            continue;
+        };
+        let Some(url) = spec
+            .labels
+            .and_then(|mut labels| labels.remove("healthchecks.url"))
+        else {
+            continue;
+        };
+        let tasks = runtime
+            .list_tasks(&id)
+            .await
+            .with_context(|| format!("failed to list tasks of service {id}"))?;
+        let desired: Vec<_> = tasks
+            .iter()
+            .filter(|task| task.desired_state.as_deref() == Some("running"))
+            .collect();
+        let running = desired
+            .iter()
+            .filter(|task| task_state(task) == Some("running"))
+            .count();
+        let health = if running > 0 || desired.is_empty() {
+            Health::Healthy
+        } else if desired
+            .iter()
+            .any(|task| task_state(task).is_some_and(|state| PENDING_STATES.contains(&state)))
+        {
+            Health::Starting
+        } else {
+            Health::Unhealthy
+        };
+        result.push(ServiceHealth {
+            url,
+            name: spec.name.unwrap_or(id),
+            health,
+            running,
+            desired: desired.len(),
+        });
+    }
+    Ok(result)
+}
+
+/// Return the current state of a task
+fn task_state(task: &Task) -> Option<&str> {
+    task.status.as_ref()?.state.as_deref()
+}
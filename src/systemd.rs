@@ -0,0 +1,60 @@
+//! Minimal implementation of the systemd notification protocol (`sd_notify`).
+//! All functions are no-ops unless the process has been started by systemd
+//! with `NotifyAccess` enabled.
+
+use std::{
+    env,
+    ffi::OsStr,
+    io,
+    os::unix::{ffi::OsStrExt, net::UnixDatagram},
+    process,
+    time::Duration,
+};
+
+use tracing::warn;
+
+/// Return whether systemd expects notifications from this process
+pub fn enabled() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Send the given newline separated assignments (e.g. `READY=1`) to systemd
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(err) = send(&path, state) {
+        warn!("failed to notify systemd: {err}");
+    }
+}
+
+/// Send a datagram to the notification socket at the given path. Paths
+/// starting with `@` refer to abstract sockets.
+fn send(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Return the interval in which systemd expects watchdog keep-alive
+/// notifications, i.e. half of the configured watchdog timeout
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // the watchdog might be meant for another process
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec) / 2)
+}
@@ -0,0 +1,182 @@
+//! End-to-end tests against a real docker daemon. They start containers with
+//! healthchecks labels, run docker-healthchecks in-process and check the pings
+//! it sends to a mock healthchecks server, which also covers the event stream.
+//!
+//! The tests are ignored by default, as they need a docker daemon that can
+//! build images from `alpine`. All containers with healthchecks labels on this
+//! daemon are monitored and pinged while the tests run, so use a dedicated
+//! daemon (e.g. docker-in-docker) and pass its address in
+//! `DHC_TEST_DOCKER_HOST`:
+//!
+//! ```sh
+//! DHC_TEST_DOCKER_HOST=tcp://localhost:2375 cargo test --test docker -- --ignored
+//! ```
+
+use std::{env, fs, process, time::Duration};
+
+use docker_api::{
+    models::ImageBuildChunk,
+    opts::{
+        ContainerCreateOpts, ContainerFilter, ContainerListOpts, ContainerRemoveOpts,
+        ExecCreateOpts, ExecStartOpts, ImageBuildOpts,
+    },
+    Container, Docker,
+};
+use docker_healthchecks::Config;
+use futures_util::StreamExt;
+use tokio::{
+    spawn,
+    time::{sleep, Instant},
+};
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+/// Tag of the test image
+const IMAGE: &str = "docker-healthchecks-test:latest";
+
+/// Label of the containers created by the tests
+const TEST_LABEL: &str = "docker-healthchecks.test";
+
+/// Dockerfile of the test image. Containers of this image are healthy as long
+/// as `/tmp/healthy` exists.
+const DOCKERFILE: &str = r#"FROM alpine:3.20
+HEALTHCHECK --interval=1s --timeout=1s --retries=1 CMD test -f /tmp/healthy
+CMD ["sh", "-c", "touch /tmp/healthy && exec sleep 3600"]
+"#;
+
+/// Maximum time to wait for an expected ping
+const PING_WAIT: Duration = Duration::from_secs(60);
+
+/// Connect to the docker daemon used by the tests
+fn docker() -> (Docker, String) {
+    let host = env::var("DHC_TEST_DOCKER_HOST")
+        .expect("DHC_TEST_DOCKER_HOST must point to a dedicated docker daemon");
+    (Docker::new(&host).unwrap(), host)
+}
+
+/// Build the test image
+async fn build_image(docker: &Docker) {
+    let dir = env::temp_dir().join(format!("docker-healthchecks-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("Dockerfile"), DOCKERFILE).unwrap();
+    let opts = ImageBuildOpts::builder(&dir).tag(IMAGE).build();
+    let images = docker.images();
+    let mut build = images.build(&opts);
+    while let Some(chunk) = build.next().await {
+        if let ImageBuildChunk::Error { error, .. } = chunk.unwrap() {
+            panic!("failed to build test image: {error}");
+        }
+    }
+    fs::remove_dir_all(&dir).ok();
+}
+
+/// Remove all containers created by the tests, including those left behind
+/// by failed runs
+async fn remove_test_containers(docker: &Docker) {
+    let opts = ContainerListOpts::builder()
+        .all(true)
+        .filter([ContainerFilter::LabelKey(TEST_LABEL.to_owned())])
+        .build();
+    for summary in docker.containers().list(&opts).await.unwrap() {
+        if let Some(id) = summary.id {
+            docker
+                .containers()
+                .get(id)
+                .remove(&ContainerRemoveOpts::builder().force(true).build())
+                .await
+                .unwrap();
+        }
+    }
+}
+
+/// Create and start a container of the test image with the given ping url
+async fn start_container(docker: &Docker, name: &str, url: &str) -> Container {
+    let opts = ContainerCreateOpts::builder()
+        .image(IMAGE)
+        .name(name)
+        .labels([("healthchecks.url", url), (TEST_LABEL, "1")])
+        .build();
+    let container = docker.containers().create(&opts).await.unwrap();
+    container.start().await.unwrap();
+    container
+}
+
+/// Make the healthcheck of a container pass or fail
+async fn set_healthy(container: &Container, healthy: bool) {
+    let command: &[&str] = if healthy {
+        &["touch", "/tmp/healthy"]
+    } else {
+        &["rm", "-f", "/tmp/healthy"]
+    };
+    let opts = ExecCreateOpts::builder()
+        .command(command)
+        .attach_stdout(true)
+        .attach_stderr(true)
+        .build();
+    let mut output = container
+        .exec(&opts, &ExecStartOpts::default())
+        .await
+        .unwrap();
+    while output.next().await.is_some() {}
+}
+
+/// Wait for a ping to the given path among the requests after the first
+/// `seen` ones. Return the number of requests up to and including this ping.
+async fn wait_for_ping(server: &MockServer, path: &str, seen: usize) -> usize {
+    let deadline = Instant::now() + PING_WAIT;
+    loop {
+        let requests = server.received_requests().await.unwrap_or_default();
+        if let Some(index) = requests
+            .iter()
+            .skip(seen)
+            .position(|request| request.url.path() == path)
+        {
+            return seen + index + 1;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no ping to {path} within {PING_WAIT:?}"
+        );
+        sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs a dedicated docker daemon in DHC_TEST_DOCKER_HOST"]
+async fn containers_are_reported() {
+    let (docker, host) = docker();
+    build_image(&docker).await;
+    remove_test_containers(&docker).await;
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let config = Config {
+        docker_host: Some(host),
+        ping_interval: Duration::from_secs(5),
+        ping_timeout: Duration::from_secs(4),
+        ..Config::default()
+    };
+    let daemon = spawn(docker_healthchecks::run(config, None));
+
+    // a container is reported healthy once its healthcheck passes, and its
+    // health changes are reported
+    let url = format!("{}/flip", server.uri());
+    let container = start_container(&docker, "dhc-test-flip", &url).await;
+    let seen = wait_for_ping(&server, "/flip", 0).await;
+    set_healthy(&container, false).await;
+    let seen = wait_for_ping(&server, "/flip/fail", seen).await;
+    set_healthy(&container, true).await;
+    wait_for_ping(&server, "/flip", seen).await;
+
+    // a killed container is reported unhealthy
+    let url = format!("{}/kill", server.uri());
+    let container = start_container(&docker, "dhc-test-kill", &url).await;
+    let seen = wait_for_ping(&server, "/kill", 0).await;
+    container.kill(None).await.unwrap();
+    wait_for_ping(&server, "/kill/fail", seen).await;
+
+    daemon.abort();
+    remove_test_containers(&docker).await;
+}